@@ -0,0 +1,429 @@
+use crate::config::DivergenceStyle;
+use chrono::{DateTime, TimeZone, Utc};
+use git2::Oid;
+use serde::Deserialize;
+use std::borrow::Cow;
+use std::fmt::Display;
+
+/// A cap for [`recent_branches`] that fits the most recent few on a screen
+pub const RECENT_LIMIT: usize = 10;
+
+/// What kind of ref a list entry is, which decides how it's checked out
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RefKind {
+    Local,
+    Remote,
+    Tag,
+    Stash,
+    /// The commit a detached HEAD is on, which no branch is
+    Head,
+}
+
+impl RefKind {
+    pub fn badge(self) -> &'static str {
+        match self {
+            RefKind::Local => "local",
+            RefKind::Remote => "remote",
+            RefKind::Tag => "tag",
+            RefKind::Stash => "stash",
+            RefKind::Head => "head",
+        }
+    }
+}
+
+/// A branch, or with `--all-refs` any other ref, as listed in the picker
+#[derive(Clone, Debug)]
+pub struct GitBranch {
+    pub name: String,
+    pub ref_kind: RefKind,
+    pub is_current: bool,
+    pub tip: Option<Oid>,
+    pub last_commit_time: DateTime<Utc>,
+    pub description: Option<String>,
+    /// Whether a stash was made while on this branch
+    pub has_stash: bool,
+    /// Matches one of the `protected_branches` patterns, so it can't be deleted
+    pub is_protected: bool,
+    /// The upstream's short name, like `origin/main`, if the branch tracks one
+    pub upstream: Option<String>,
+    /// Commits ahead of and behind the upstream, if the branch tracks one
+    pub ahead_behind: Option<(usize, usize)>,
+    /// Tracks an upstream that no longer exists, usually deleted on the
+    /// remote after the branch was merged
+    pub upstream_gone: bool,
+    /// When the branch last moved or was checked out, according to the reflogs
+    pub last_reflog_time: Option<DateTime<Utc>>,
+    /// The tip commit carries a GPG or SSH signature (not checked for validity)
+    pub tip_signed: bool,
+    /// Who wrote the tip commit
+    pub author: Option<String>,
+    /// The first line of the tip commit's message
+    pub subject: Option<String>,
+    /// The base branch (main or master) contains the tip, so deleting the
+    /// branch loses nothing. Only known for local branches.
+    pub is_merged: bool,
+    /// Checked out but without a commit yet, as right after `git init`. Its
+    /// `last_commit_time` is only there to sort it as the newest branch.
+    pub is_unborn: bool,
+    /// The ref points at something that doesn't peel to a commit, like a
+    /// tree or a blob. It has no time of its own, so it sorts as the oldest.
+    pub not_a_commit: bool,
+}
+
+impl GitBranch {
+    /// When the branch was last worked in: committed to, reset, or just
+    /// checked out, whichever was latest
+    pub fn last_activity(&self) -> DateTime<Utc> {
+        self.last_reflog_time.map_or(self.last_commit_time, |time| {
+            time.max(self.last_commit_time)
+        })
+    }
+
+    /// The name as shown in the list: short, or the full ref path like
+    /// `refs/remotes/origin/main`. Stashes and a detached HEAD have no ref of
+    /// their own to show.
+    pub fn display_name(&self, full: bool) -> Cow<'_, str> {
+        let prefix = match self.ref_kind {
+            _ if !full => return Cow::Borrowed(&self.name),
+            RefKind::Local => "refs/heads/",
+            RefKind::Remote => "refs/remotes/",
+            RefKind::Tag => "refs/tags/",
+            RefKind::Stash | RefKind::Head => return Cow::Borrowed(&self.name),
+        };
+        Cow::Owned(format!("{}{}", prefix, self.name))
+    }
+
+    /// How the branch compares to its upstream, e.g. `↑2` or `[gone]`
+    pub fn upstream_label(&self, style: DivergenceStyle) -> Option<String> {
+        if self.upstream_gone {
+            return Some("[gone]".to_string());
+        }
+        self.ahead_behind
+            .map(|counts| divergence_label(counts, style))
+    }
+
+    /// Whether the branch has commits its upstream doesn't, i.e. ones to push
+    pub fn is_unpushed(&self) -> bool {
+        self.ahead_behind.is_some_and(|(ahead, _)| ahead > 0)
+    }
+
+    /// The age shown in the list, e.g. `(3d)`
+    pub fn age_label(&self, now: DateTime<Utc>, sort_mode: SortMode) -> String {
+        if self.is_unborn {
+            "(no commits)".to_string()
+        } else if self.not_a_commit {
+            "(not a commit)".to_string()
+        } else {
+            time_ago(now, self.display_time(sort_mode))
+        }
+    }
+
+    /// The exact time behind [`GitBranch::age_label`] in ISO 8601, e.g.
+    /// `2024-01-31T13:00:00+01:00`, or nothing for a branch without commits
+    pub fn timestamp_label<Tz: TimeZone>(&self, sort_mode: SortMode, tz: &Tz) -> Option<String>
+    where
+        Tz::Offset: Display,
+    {
+        (!self.is_unborn && !self.not_a_commit).then(|| {
+            self.display_time(sort_mode)
+                .with_timezone(tz)
+                .format("%Y-%m-%dT%H:%M:%S%:z")
+                .to_string()
+        })
+    }
+
+    /// The time the list is sorted by and shows for this branch
+    pub fn display_time(&self, sort_mode: SortMode) -> DateTime<Utc> {
+        match sort_mode {
+            SortMode::Activity | SortMode::Frecency => self.last_activity(),
+            SortMode::Recent | SortMode::Name => self.last_commit_time,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum SortMode {
+    /// Newest last commit first
+    #[default]
+    Recent,
+    /// By last activity, which also counts checkouts without new commits
+    Activity,
+    /// By how often and how recently gb checked the branch out, with its
+    /// last commit counting as one more visit; see [`frecency`]
+    Frecency,
+    /// Alphabetically
+    Name,
+}
+
+impl SortMode {
+    pub fn next(self) -> SortMode {
+        match self {
+            SortMode::Recent => SortMode::Activity,
+            SortMode::Activity => SortMode::Frecency,
+            SortMode::Frecency => SortMode::Name,
+            SortMode::Name => SortMode::Recent,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            SortMode::Recent => "recent",
+            SortMode::Activity => "activity",
+            SortMode::Frecency => "frecency",
+            SortMode::Name => "name",
+        }
+    }
+
+    /// The sort mode called `label`, as written in the config
+    pub fn from_label(label: &str) -> Option<SortMode> {
+        [
+            SortMode::Recent,
+            SortMode::Activity,
+            SortMode::Frecency,
+            SortMode::Name,
+        ]
+        .into_iter()
+        .find(|mode| mode.label() == label)
+    }
+}
+
+/// How much a branch is in use, for sorting by [`SortMode::Frecency`]: every
+/// time gb checked it out scores by how long ago that was, from 100 points
+/// within four days down to 10 after three months, and its last commit
+/// scores the same way once more. Regular use beats a single recent visit,
+/// and old habits fade.
+pub fn frecency(
+    checkouts: &[DateTime<Utc>],
+    last_commit: DateTime<Utc>,
+    now: DateTime<Utc>,
+) -> u32 {
+    let points = |time: DateTime<Utc>| match (now - time).num_days() {
+        ..4 => 100,
+        4..14 => 70,
+        14..31 => 50,
+        31..90 => 30,
+        _ => 10,
+    };
+    checkouts
+        .iter()
+        .copied()
+        .chain([last_commit])
+        .map(points)
+        .sum()
+}
+
+/// Sorts newest first by last commit. The sort is stable, so branches
+/// committed to at the same time keep their order.
+pub fn sort_by_recency(branches: &mut [GitBranch]) {
+    branches.sort_by_key(|branch| std::cmp::Reverse(branch.last_commit_time));
+}
+
+/// The first `limit` of `items`, in their order, or all of them without a
+/// limit. The picker caps its list with this, whatever it's sorted by.
+pub fn capped<I: IntoIterator>(items: I, limit: Option<usize>) -> std::iter::Take<I::IntoIter> {
+    items.into_iter().take(limit.unwrap_or(usize::MAX))
+}
+
+/// The `limit` most recently committed-to branches of `all`, newest first,
+/// or all of them without a limit: what the picker lists when sorting by
+/// recency, before favorites and the previous branch are moved up.
+pub fn recent_branches(mut all: Vec<GitBranch>, limit: Option<usize>) -> Vec<GitBranch> {
+    sort_by_recency(&mut all);
+    capped(all, limit).collect()
+}
+
+/// Formats how a branch compares to its upstream, e.g. `↑2 ↓3` or `⇅`. Counts
+/// leave in-sync branches blank, where a single glyph shows `=`.
+pub fn divergence_label((ahead, behind): (usize, usize), style: DivergenceStyle) -> String {
+    match style {
+        DivergenceStyle::Counts => {
+            let mut parts = Vec::new();
+            if ahead > 0 {
+                parts.push(format!("↑{}", ahead));
+            }
+            if behind > 0 {
+                parts.push(format!("↓{}", behind));
+            }
+            parts.join(" ")
+        }
+        DivergenceStyle::Glyph => match (ahead > 0, behind > 0) {
+            (true, true) => "⇅",
+            (true, false) => "↑",
+            (false, true) => "↓",
+            (false, false) => "=",
+        }
+        .to_string(),
+    }
+}
+
+pub fn time_ago(now: DateTime<Utc>, time: DateTime<Utc>) -> String {
+    let duration = now.signed_duration_since(time);
+
+    if duration.num_days() > 0 {
+        format!("({}d)", duration.num_days())
+    } else if duration.num_hours() > 0 {
+        format!("({}h)", duration.num_hours())
+    } else {
+        format!("({}m)", duration.num_minutes().max(1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn branch(name: &str, days_ago: i64) -> GitBranch {
+        GitBranch {
+            name: name.to_string(),
+            ref_kind: RefKind::Local,
+            is_current: false,
+            tip: None,
+            last_commit_time: Utc.with_ymd_and_hms(2024, 1, 31, 12, 0, 0).unwrap()
+                - chrono::Duration::days(days_ago),
+            description: None,
+            has_stash: false,
+            is_protected: false,
+            ahead_behind: None,
+            upstream: None,
+            upstream_gone: false,
+            last_reflog_time: None,
+            tip_signed: false,
+            author: None,
+            subject: None,
+            is_merged: false,
+            is_unborn: false,
+            not_a_commit: false,
+        }
+    }
+
+    fn names(branches: &[GitBranch]) -> Vec<&str> {
+        branches.iter().map(|branch| branch.name.as_str()).collect()
+    }
+
+    #[test]
+    fn recent_branches_are_newest_first_up_to_the_limit() {
+        let all = || {
+            vec![
+                branch("old", 30),
+                branch("new", 0),
+                branch("middle", 7),
+                branch("also-middle", 7),
+            ]
+        };
+
+        let cases: &[(Option<usize>, &[&str])] = &[
+            (None, &["new", "middle", "also-middle", "old"]),
+            (Some(0), &[]),
+            (Some(1), &["new"]),
+            // Ties keep their order
+            (Some(3), &["new", "middle", "also-middle"]),
+            (Some(4), &["new", "middle", "also-middle", "old"]),
+            (Some(100), &["new", "middle", "also-middle", "old"]),
+        ];
+        for &(limit, expected) in cases {
+            assert_eq!(
+                names(&recent_branches(all(), limit)),
+                expected,
+                "limit {limit:?}"
+            );
+        }
+
+        assert!(recent_branches(Vec::new(), Some(RECENT_LIMIT)).is_empty());
+
+        // The picker's cap keeps whatever order it's given
+        let capped: Vec<_> = capped(["b", "a", "c"], Some(2)).collect();
+        assert_eq!(capped, ["b", "a"]);
+    }
+
+    #[test]
+    fn time_ago_rolls_over_at_unit_boundaries() {
+        let now = Utc.with_ymd_and_hms(2024, 1, 31, 12, 0, 0).unwrap();
+        let cases = [
+            (chrono::Duration::seconds(0), "(1m)"),
+            (chrono::Duration::seconds(59), "(1m)"),
+            (chrono::Duration::minutes(1), "(1m)"),
+            (chrono::Duration::minutes(59), "(59m)"),
+            (chrono::Duration::seconds(59 * 60 + 59), "(59m)"),
+            (chrono::Duration::minutes(60), "(1h)"),
+            (chrono::Duration::minutes(119), "(1h)"),
+            (chrono::Duration::hours(2), "(2h)"),
+            (chrono::Duration::hours(23), "(23h)"),
+            (
+                chrono::Duration::hours(24) - chrono::Duration::seconds(1),
+                "(23h)",
+            ),
+            (chrono::Duration::hours(24), "(1d)"),
+            (chrono::Duration::days(400), "(400d)"),
+            // Clock skew puts commits in the future
+            (chrono::Duration::minutes(-5), "(1m)"),
+        ];
+
+        for (age, expected) in cases {
+            assert_eq!(time_ago(now, now - age), expected, "{age:?} ago");
+        }
+    }
+
+    #[test]
+    fn timestamp_label_is_iso_8601_in_the_given_zone() {
+        let mut branch = branch("feat", 0);
+        let cet = chrono::FixedOffset::east_opt(3600).unwrap();
+        assert_eq!(
+            branch.timestamp_label(SortMode::Recent, &cet).as_deref(),
+            Some("2024-01-31T13:00:00+01:00")
+        );
+        assert_eq!(
+            branch.timestamp_label(SortMode::Recent, &Utc).as_deref(),
+            Some("2024-01-31T12:00:00+00:00")
+        );
+
+        branch.is_unborn = true;
+        assert_eq!(branch.timestamp_label(SortMode::Recent, &Utc), None);
+    }
+
+    #[test]
+    fn frecency_weighs_visits_by_how_long_ago_they_were() {
+        let now = Utc.with_ymd_and_hms(2024, 6, 1, 12, 0, 0).unwrap();
+        let days_ago = |days| now - chrono::Duration::days(days);
+
+        assert_eq!(frecency(&[], days_ago(1), now), 100);
+        assert_eq!(
+            frecency(&[days_ago(2), days_ago(10)], days_ago(200), now),
+            180
+        );
+        // Checked out again and again last month beats committed to today
+        let habit = [days_ago(20), days_ago(21), days_ago(22)];
+        assert!(frecency(&habit, days_ago(40), now) > frecency(&[], days_ago(0), now));
+    }
+
+    #[test]
+    fn upstream_labels_say_how_far_apart_the_branches_are() {
+        let cases = [
+            ((2, 0), "↑2", "↑"),
+            ((0, 3), "↓3", "↓"),
+            ((2, 3), "↑2 ↓3", "⇅"),
+            ((0, 0), "", "="),
+        ];
+        for (counts, expected_counts, expected_glyph) in cases {
+            assert_eq!(
+                divergence_label(counts, DivergenceStyle::Counts),
+                expected_counts
+            );
+            assert_eq!(
+                divergence_label(counts, DivergenceStyle::Glyph),
+                expected_glyph
+            );
+        }
+
+        // Nothing to compare against, or nothing left to compare against
+        let mut feat = branch("feat", 0);
+        assert_eq!(feat.upstream_label(DivergenceStyle::Counts), None);
+        feat.ahead_behind = Some((1, 0));
+        feat.upstream_gone = true;
+        assert_eq!(
+            feat.upstream_label(DivergenceStyle::Counts).as_deref(),
+            Some("[gone]")
+        );
+    }
+}
@@ -0,0 +1,137 @@
+use crate::palette::ColorMode;
+use clap::{Parser, Subcommand, ValueEnum};
+use gb::{SortMode, config::Column};
+use std::path::PathBuf;
+
+/// A terminal user interface (TUI) for managing Git branches
+#[derive(Debug, Default, Parser)]
+#[command(version, about)]
+pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Check out the one branch matching this filter straight away, or open the picker filtered by it
+    #[arg(value_name = "PATTERN")]
+    pub pattern: Option<String>,
+
+    /// Check out the branch gb last switched away from, without opening the picker
+    #[arg(long)]
+    pub checkout_last: bool,
+
+    /// Print the current branch (or the commit, on a detached HEAD) without opening the picker
+    #[arg(long)]
+    pub current: bool,
+
+    /// Only list branches matching this glob, e.g. 'feat/*'
+    #[arg(long, value_name = "GLOB")]
+    pub only: Option<String>,
+
+    /// How to sort the list at startup; `Ctrl-s` cycles through the orders [default: recent]
+    #[arg(long, value_enum, value_name = "ORDER")]
+    pub sort: Option<SortMode>,
+
+    /// How many branches to list when sorting by recency or activity, 0 for all of them [default: 0]
+    #[arg(long, value_name = "N")]
+    pub limit: Option<usize>,
+
+    /// List every branch, however old, over a `limit` in the config; the same as `--limit 0`
+    #[arg(long, conflicts_with = "limit")]
+    pub all: bool,
+
+    /// Review and bulk-delete the branches already merged into main/master
+    #[arg(long)]
+    pub cleanup: bool,
+
+    /// Only offer the current and the previously checked-out branch, to toggle between them
+    #[arg(long)]
+    pub quick: bool,
+
+    /// Only list branches with commits their upstream doesn't have yet, i.e. the ones to push
+    #[arg(long)]
+    pub unpushed: bool,
+
+    /// Browse without changing anything: checking out, creating, deleting, fetching and worktrees are refused
+    #[arg(long)]
+    pub read_only: bool,
+
+    /// Let protected branches be deleted and renamed, for the rare time one has to go
+    #[arg(long)]
+    pub allow_protected: bool,
+
+    /// Also list remote branches, tags and stashes, each checked out the way its kind needs
+    #[arg(long)]
+    pub all_refs: bool,
+
+    /// List the remote branches instead, checking one out as a local branch tracking it
+    #[arg(long, conflicts_with = "all_refs")]
+    pub remotes: bool,
+
+    /// Draw the picker below the prompt instead of on the alternate screen, leaving it in the scrollback
+    #[arg(long)]
+    pub inline: bool,
+
+    /// Which columns to show after the branch name, like `time,subject` [default: time,author,upstream,subject]
+    #[arg(long, value_enum, value_delimiter = ',', value_name = "COLUMNS")]
+    pub columns: Option<Vec<Column>>,
+
+    /// How many colors to draw with; `auto` goes by NO_COLOR, COLORTERM and TERM
+    #[arg(long, value_enum, value_name = "WHEN", default_value_t = ColorMode::Auto)]
+    pub colors: ColorMode,
+
+    /// Shell command to run after checking out a branch in the picker, with the branch in $GB_BRANCH
+    #[arg(long, value_name = "COMMAND")]
+    pub then: Option<String>,
+
+    /// Print each branch as a line of JSON as soon as it's read, unsorted, instead of opening the picker
+    #[arg(long)]
+    pub jsonl: bool,
+
+    /// Print the names the picker would list, in its order, instead of opening it
+    #[arg(long, conflicts_with_all = ["jsonl", "print"])]
+    pub list: bool,
+
+    /// With `--list`, print the branches as a JSON array with their commit time and upstream
+    #[arg(long, requires = "list")]
+    pub json: bool,
+
+    /// Print errors in full, with their backtrace if RUST_BACKTRACE is set, for bug reports
+    #[arg(long)]
+    pub verbose: bool,
+
+    /// Print the branch picked with Enter instead of checking it out, for shell functions; exits non-zero when nothing was picked
+    #[arg(long, conflicts_with = "cleanup")]
+    pub print: bool,
+
+    /// After adding (or finding) a worktree, print only its path, for wrappers to `cd` into
+    #[arg(long)]
+    pub print_path: bool,
+
+    /// After adding (or finding) a worktree, also write its path to FILE, for wrappers that leave stdout on the terminal
+    #[arg(long, value_name = "FILE")]
+    pub print_path_to: Option<PathBuf>,
+
+    /// List the branches of every git checkout in DIR together, or of the ones the `repos` setting names, to check them out in any of them
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["cleanup", "quick", "jsonl"])]
+    pub repos: Option<Option<PathBuf>>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Check out a branch by its exact name, without opening the picker
+    Switch {
+        /// The branch to check out
+        name: String,
+    },
+    /// Print a `gb` shell function that changes into the worktree it picks, and completions, to eval in your shell's startup file
+    Init {
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
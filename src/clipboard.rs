@@ -0,0 +1,109 @@
+use anyhow::{Result, bail};
+use std::{
+    env,
+    io::{self, Write},
+    process::{Command, Stdio},
+};
+
+/// Programs that take the clipboard contents on stdin, tried in order
+const CLIPBOARD_COMMANDS: [(&str, &[&str]); 4] = [
+    ("pbcopy", &[]),
+    ("wl-copy", &[]),
+    ("xclip", &["-selection", "clipboard"]),
+    ("xsel", &["--clipboard", "--input"]),
+];
+
+/// Puts `text` on the system clipboard.
+///
+/// Uses the first clipboard program that's installed. Without one it falls
+/// back to the OSC 52 escape sequence, which asks the terminal itself to set
+/// the clipboard; not every terminal allows that, so it can silently do
+/// nothing.
+///
+/// Over SSH the clipboard programs would set the remote machine's clipboard,
+/// so OSC 52 goes first there, to reach the terminal you're sitting at.
+pub fn copy(text: &str) -> Result<()> {
+    let terminal = env::var_os("TERM").is_some_and(|term| term != "dumb");
+    if terminal && over_ssh() {
+        return osc52(text);
+    }
+
+    for (program, args) in CLIPBOARD_COMMANDS {
+        let child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+        let mut child = match child {
+            Ok(child) => child,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err.into()),
+        };
+
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(text.as_bytes())?;
+        }
+        if child.wait()?.success() {
+            return Ok(());
+        }
+        // Installed but unusable, e.g. xclip without an X display
+    }
+
+    if !terminal {
+        bail!("no clipboard program found (pbcopy, wl-copy, xclip or xsel)");
+    }
+    osc52(text)
+}
+
+fn over_ssh() -> bool {
+    env::var_os("SSH_TTY").is_some() || env::var_os("SSH_CONNECTION").is_some()
+}
+
+/// Asks the terminal to set its clipboard
+fn osc52(text: &str) -> Result<()> {
+    // The TUI draws on stderr, so that's where the terminal is
+    let mut stderr = io::stderr();
+    write!(stderr, "\x1b]52;c;{}\x07", base64(text.as_bytes()))?;
+    stderr.flush()?;
+    Ok(())
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_base64() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(
+            base64(b"git checkout main --"),
+            "Z2l0IGNoZWNrb3V0IG1haW4gLS0="
+        );
+    }
+}
@@ -0,0 +1,378 @@
+use crate::{branch::SortMode, glob::glob_match};
+use anyhow::{Context, Result, anyhow};
+use git2::ErrorCode;
+use serde::Deserialize;
+use std::{collections::BTreeMap, env, fs, io, path::PathBuf};
+
+/// User settings, read from `$XDG_CONFIG_HOME/gb/config.toml` (or
+/// `~/.config/gb/config.toml`), or from the file `GB_CONFIG` names. Every
+/// setting is optional. A few can also be
+/// set in git config, see [`Config::apply_git_config`].
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Longest branch name shown before it gets truncated
+    pub max_name_width: usize,
+    /// Which branch the cursor starts on
+    pub start_selection: StartSelection,
+    /// How the list is sorted at startup
+    pub sort: SortMode,
+    /// How many branches are listed when sorting by recency or activity,
+    /// with 0, the default, for all of them
+    pub limit: usize,
+    /// Start on the list of every ref, remote branches included, like
+    /// `--all-refs`
+    pub show_remote: bool,
+    /// Show the repository name above the list
+    pub show_repo_name: bool,
+    /// The color of the selection marker and the fetch spinner, like
+    /// `"magenta"`, `"141"` or `"#af87ff"`
+    pub accent_color: Option<String>,
+    /// The color of the selected branch name
+    pub highlight_color: Option<String>,
+    /// Which timestamp of the tip commit to sort and display by
+    pub time_source: TimeSource,
+    /// Leave the checked out branch out of the list
+    pub hide_current_branch: bool,
+    /// List the checked out branch first, whatever the sort order
+    pub current_first: bool,
+    /// Rows kept visible above and below the selection when scrolling
+    pub scroll_margin: usize,
+    /// Glob patterns for branches gb refuses to delete or rename
+    pub protected_branches: Vec<String>,
+    /// Refresh the list when refs change on disk, e.g. from another terminal
+    pub watch_refs: bool,
+    /// How far ahead of or behind its upstream each branch is shown
+    pub divergence_style: DivergenceStyle,
+    /// Ask before deleting branches already merged into the base branch
+    pub confirm_merged_deletes: bool,
+    /// Ask before checking out with uncommitted changes or untracked files
+    pub confirm_dirty_checkout: bool,
+    /// Group the list under "Today", "Yesterday", ... headers in recency order
+    pub date_headers: bool,
+    /// Start with the branches grouped by prefix, like `feature/`
+    pub tree_view: bool,
+    /// Start with the selected branch's recent commits shown beside the list
+    pub preview: bool,
+    /// Show the keys that go with the selection below the list, when there's
+    /// nothing else to say there
+    pub key_hints: bool,
+    /// A shell command `Enter` runs instead of checking out, with `{branch}`
+    /// replaced by the selected name
+    pub enter_command: Option<String>,
+    /// A shell command to run after checking out in the picker, with the
+    /// branch in `$GB_BRANCH`
+    pub post_checkout_command: Option<String>,
+    /// The directory `w` suggests adding worktrees in, instead of next to
+    /// the repository; a leading `~/` is the home directory
+    pub worktree_dir: Option<String>,
+    /// Where `--repos` looks without a directory: checkouts, or directories
+    /// of them
+    pub repos: Vec<String>,
+    /// Show the exact time of the selected branch's tip in the status line
+    pub show_timestamp: bool,
+    /// Show the subject and author of each branch's tip at the end of its row
+    pub show_subject: bool,
+    /// Which of the time, author, upstream and subject columns follow the
+    /// branch name
+    pub columns: Vec<Column>,
+    /// Set the terminal title to the repository and current branch while
+    /// the picker is open
+    pub terminal_title: bool,
+    /// Where the repository's pull requests are, to show the open one next
+    /// to each branch; off unless set
+    pub forge: Option<Forge>,
+    /// A token for the forge, instead of the one its command line tool is
+    /// signed in with or finds in `GH_TOKEN` or `GITLAB_TOKEN`
+    pub forge_token: Option<String>,
+    /// Keys that act like other keys in the list, like `x = "d"`, both named
+    /// the way `j`, `G`, `ctrl-r` or `pagedown` are
+    pub keys: BTreeMap<String, String>,
+    /// The colors to draw with, for a dark or a light terminal background
+    pub theme: Theme,
+    /// Styles replacing the theme's, by what they're for, like
+    /// `selected = "bold blue"`
+    pub styles: BTreeMap<String, String>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StartSelection {
+    /// The most recent branch at the top of the list
+    #[default]
+    Recent,
+    /// The branch that is currently checked out
+    Current,
+    /// Where the list was left last time, scrolled the same way, as long as
+    /// that branch is still there
+    Last,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TimeSource {
+    /// When the commit was last rewritten, e.g. by a rebase
+    #[default]
+    Committer,
+    /// When the change was originally authored
+    Author,
+}
+
+/// A column of the list besides the branch name, which is always there
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Column {
+    /// How long ago the tip was committed, like `(3d)`
+    Time,
+    /// Who made the tip commit
+    Author,
+    /// The upstream a local branch tracks, like `origin/main`, so the ones
+    /// tracking nothing stand out
+    Upstream,
+    /// The tip commit's subject, cut to fit the row
+    Subject,
+}
+
+/// A code host whose pull requests the list can show, asked through its
+/// command line tool
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Forge {
+    /// Pull requests, through `gh`
+    GitHub,
+    /// Merge requests, through `glab`
+    GitLab,
+}
+
+/// The built-in sets of colors
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Theme {
+    /// Light or dark by the background `COLORFGBG` names, which some
+    /// terminals set, and dark without it
+    #[default]
+    Auto,
+    /// Light text on a dark background
+    Dark,
+    /// Dark text on a light background
+    Light,
+    /// No colors, only bold, italics and reverse video, whatever the terminal
+    Monochrome,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DivergenceStyle {
+    /// Commit counts, like `↑2 ↓3`
+    #[default]
+    Counts,
+    /// One symbol: `↑` ahead, `↓` behind, `⇅` both, `=` in sync
+    Glyph,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            max_name_width: 40,
+            start_selection: StartSelection::default(),
+            sort: SortMode::default(),
+            limit: 0,
+            show_remote: false,
+            show_repo_name: true,
+            accent_color: None,
+            highlight_color: None,
+            time_source: TimeSource::default(),
+            hide_current_branch: false,
+            current_first: false,
+            scroll_margin: 0,
+            protected_branches: Vec::new(),
+            watch_refs: false,
+            divergence_style: DivergenceStyle::default(),
+            confirm_merged_deletes: true,
+            confirm_dirty_checkout: true,
+            date_headers: false,
+            tree_view: false,
+            preview: false,
+            key_hints: true,
+            enter_command: None,
+            post_checkout_command: None,
+            worktree_dir: None,
+            repos: Vec::new(),
+            show_timestamp: false,
+            show_subject: true,
+            columns: vec![
+                Column::Time,
+                Column::Author,
+                Column::Upstream,
+                Column::Subject,
+            ],
+            terminal_title: true,
+            forge: None,
+            forge_token: None,
+            keys: BTreeMap::new(),
+            theme: Theme::default(),
+            styles: BTreeMap::new(),
+        }
+    }
+}
+
+impl Config {
+    pub fn load() -> Result<Config> {
+        // A file asked for by name has to be there, unlike the usual one
+        let (path, required) = match env::var_os("GB_CONFIG").filter(|path| !path.is_empty()) {
+            Some(path) => (PathBuf::from(path), true),
+            None => match config_path() {
+                Some(path) => (path, false),
+                None => return Ok(Config::default()),
+            },
+        };
+
+        match fs::read_to_string(&path) {
+            Ok(contents) => toml::from_str(&contents)
+                .with_context(|| format!("Invalid config file {}", path.display())),
+            Err(err) if err.kind() == io::ErrorKind::NotFound && !required => Ok(Config::default()),
+            Err(err) => {
+                Err(err).with_context(|| format!("Failed to read config file {}", path.display()))
+            }
+        }
+    }
+}
+
+impl Config {
+    /// Overrides settings with the `gb.sort`, `gb.limit` and `gb.showRemote`
+    /// keys from git config, so a repository can carry its own defaults in
+    /// `.git/config` (or in an included file the team shares). Git config
+    /// wins over the TOML file, being the more specific of the two; command
+    /// line flags still win over both.
+    pub fn apply_git_config(&mut self, git_config: &git2::Config) -> Result<()> {
+        if let Some(sort) = git_value(git_config.get_string("gb.sort"), "gb.sort")? {
+            self.sort = SortMode::from_label(&sort).ok_or_else(|| {
+                anyhow!(
+                    "Invalid gb.sort '{}' in git config, expected recent, activity, frecency or name",
+                    sort
+                )
+            })?;
+        }
+        if let Some(limit) = git_value(git_config.get_i64("gb.limit"), "gb.limit")? {
+            self.limit = usize::try_from(limit).map_err(|_| {
+                anyhow!(
+                    "Invalid gb.limit '{}' in git config, expected 0 or more",
+                    limit
+                )
+            })?;
+        }
+        if let Some(show_remote) = git_value(git_config.get_bool("gb.showRemote"), "gb.showRemote")?
+        {
+            self.show_remote = show_remote;
+        }
+        Ok(())
+    }
+
+    /// Whether rows show `column`. Turning `show_subject` off, as older
+    /// configs do, still leaves out the author and subject.
+    pub fn shows_column(&self, column: Column) -> bool {
+        self.columns.contains(&column) && (column == Column::Time || self.show_subject)
+    }
+
+    pub fn is_protected(&self, branch: &str) -> bool {
+        self.protected_branches
+            .iter()
+            .any(|pattern| glob_match(pattern, branch))
+    }
+}
+
+fn config_path() -> Option<PathBuf> {
+    let config_dir = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+
+    Some(config_dir.join("gb").join("config.toml"))
+}
+
+/// A git config value, or `None` when the key isn't set
+fn git_value<T>(value: Result<T, git2::Error>, key: &str) -> Result<Option<T>> {
+    match value {
+        Ok(value) => Ok(Some(value)),
+        Err(err) if err.code() == ErrorCode::NotFound => Ok(None),
+        Err(err) => Err(anyhow!("Invalid {} in git config: {}", key, err.message())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_repo::TempRepo;
+
+    /// Applies a git config file holding `contents` to the default config
+    fn with_git_config(name: &str, contents: &str) -> Result<Config> {
+        let dir = TempRepo::new(&format!("git-config-{}", name));
+        let path = dir.join("config");
+        fs::write(&path, contents).unwrap();
+        let mut config = Config::default();
+        config.apply_git_config(&git2::Config::open(&path).unwrap())?;
+        Ok(config)
+    }
+
+    #[test]
+    fn reads_gb_keys_from_git_config() {
+        let config = with_git_config(
+            "set",
+            "[gb]\n\tsort = name\n\tlimit = 25\n\tshowRemote = yes\n",
+        )
+        .unwrap();
+        assert_eq!(config.sort, SortMode::Name);
+        assert_eq!(config.limit, 25);
+        assert!(config.show_remote);
+
+        // Keys are case-insensitive, and git's size suffixes work
+        let config = with_git_config("case", "[GB]\n\tLimit = 1k\n\tshowremote\n").unwrap();
+        assert_eq!(config.limit, 1024);
+        assert!(config.show_remote);
+    }
+
+    #[test]
+    fn unset_gb_keys_keep_the_toml_settings() {
+        let config = with_git_config("unset", "[user]\n\tname = gb\n").unwrap();
+        assert_eq!(config.sort, SortMode::Recent);
+        assert_eq!(config.limit, 0);
+        assert!(!config.show_remote);
+    }
+
+    #[test]
+    fn columns_can_be_picked_and_show_subject_still_hides_two() {
+        let config: Config = toml::from_str("columns = [\"subject\", \"time\"]").unwrap();
+        assert!(config.shows_column(Column::Time));
+        assert!(config.shows_column(Column::Subject));
+        assert!(!config.shows_column(Column::Author));
+
+        let config: Config = toml::from_str("show_subject = false").unwrap();
+        assert!(config.shows_column(Column::Time));
+        assert!(!config.shows_column(Column::Author));
+        assert!(!config.shows_column(Column::Subject));
+
+        assert!(toml::from_str::<Config>("columns = [\"name\"]").is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_gb_keys() {
+        let cases = [
+            ("sort", "[gb]\n\tsort = size\n", "Invalid gb.sort 'size'"),
+            ("limit", "[gb]\n\tlimit = -1\n", "Invalid gb.limit '-1'"),
+            (
+                "nan",
+                "[gb]\n\tlimit = lots\n",
+                "Invalid gb.limit in git config",
+            ),
+            (
+                "bool",
+                "[gb]\n\tshowRemote = maybe\n",
+                "Invalid gb.showRemote in git config",
+            ),
+        ];
+        for (name, contents, expected) in cases {
+            let err = with_git_config(name, contents).unwrap_err().to_string();
+            assert!(err.starts_with(expected), "{contents:?} gave {err:?}");
+        }
+    }
+}
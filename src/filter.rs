@@ -0,0 +1,280 @@
+use chrono::Duration;
+
+/// The filter text, split into the part matched against branch names, the
+/// age bounds written like `>7d` (older than a week) or `<1h` (newer than an
+/// hour), and authors written like `@alice`. These can go anywhere and
+/// combine with each other and with the name text: `feat >30d` lists feat
+/// branches older than thirty days, `@alice @bob` the ones either of them
+/// committed to last.
+///
+/// The same can be spelled out as `author:alice` and `age:>30d`, along with
+/// `has-upstream:` and `merged:` followed by `true` or `false` (or `yes` or
+/// `no`). Names can't have a `:` in them, so any word with one is taken for
+/// one of these, and left out while it isn't finished.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Filter {
+    /// Leading `!`: list the branches that don't match the name text
+    pub negate: bool,
+    /// What's matched against branch names, without the age bounds
+    pub text: String,
+    pub age_bounds: Vec<AgeBound>,
+    /// Lowercased parts of author names, any of which the tip's author has
+    /// to contain
+    pub authors: Vec<String>,
+    /// Whether the branch has to track an upstream, or not track one
+    pub has_upstream: Option<bool>,
+    /// Whether the branch has to be merged into the base branch, or not be
+    pub merged: Option<bool>,
+}
+
+/// Shown while a `key:value` term is being typed
+pub const TERMS_HINT: &str =
+    "author:NAME · age:<7d or age:>30d · has-upstream:true/false · merged:true/false";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AgeBound {
+    OlderThan(Duration),
+    NewerThan(Duration),
+}
+
+impl AgeBound {
+    pub fn allows(self, age: Duration) -> bool {
+        match self {
+            AgeBound::OlderThan(bound) => age > bound,
+            AgeBound::NewerThan(bound) => age < bound,
+        }
+    }
+}
+
+impl Filter {
+    pub fn parse(input: &str) -> Filter {
+        let (negate, input) = match input.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, input),
+        };
+
+        let mut filter = Filter {
+            negate,
+            ..Filter::default()
+        };
+        let mut words = Vec::new();
+        for word in input.split(' ') {
+            if let Some((key, value)) = word.split_once(':') {
+                filter.add_term(key, value);
+            } else if let Some(author) = word.strip_prefix('@') {
+                // A lone `@` is still being typed
+                if !author.is_empty() {
+                    filter.authors.push(author.to_lowercase());
+                }
+            } else if word.starts_with(['<', '>']) {
+                // Half-typed or malformed bounds like `>7` or `>7x` are left
+                // out rather than matched against names, which they'd never match
+                filter.age_bounds.extend(parse_age_bound(word));
+            } else {
+                words.push(word);
+            }
+        }
+
+        filter.text = words.join(" ").trim().to_string();
+        filter
+    }
+
+    /// Adds a `key:value` term, returning whether it was one
+    fn add_term(&mut self, key: &str, value: &str) -> bool {
+        let flag = || match value {
+            "true" | "yes" => Some(true),
+            "false" | "no" => Some(false),
+            _ => None,
+        };
+        match key {
+            "author" if !value.is_empty() => self.authors.push(value.to_lowercase()),
+            "age" => match parse_age_bound(value) {
+                Some(bound) => self.age_bounds.push(bound),
+                None => return false,
+            },
+            "has-upstream" if flag().is_some() => self.has_upstream = flag(),
+            "merged" if flag().is_some() => self.merged = flag(),
+            _ => return false,
+        }
+        true
+    }
+
+    /// Whether the last word of `input` is a `key:value` term that isn't
+    /// finished, or isn't one at all, to show what they can be
+    pub fn wants_hint(input: &str) -> bool {
+        input
+            .rsplit(' ')
+            .next()
+            .and_then(|word| word.split_once(':'))
+            .is_some_and(|(key, value)| !Filter::default().add_term(key, value))
+    }
+
+    pub fn allows_age(&self, age: Duration) -> bool {
+        self.age_bounds.iter().all(|bound| bound.allows(age))
+    }
+
+    pub fn allows_author(&self, author: Option<&str>) -> bool {
+        if self.authors.is_empty() {
+            return true;
+        }
+        let Some(author) = author else {
+            return false;
+        };
+        let author = author.to_lowercase();
+        self.authors
+            .iter()
+            .any(|wanted| author.contains(wanted.as_str()))
+    }
+
+    pub fn allows_upstream(&self, has_upstream: bool) -> bool {
+        self.has_upstream
+            .is_none_or(|wanted| wanted == has_upstream)
+    }
+
+    pub fn allows_merged(&self, merged: bool) -> bool {
+        self.merged.is_none_or(|wanted| wanted == merged)
+    }
+}
+
+/// Parses `>`/`<`, a number and a unit: `m`inutes, `h`ours, `d`ays or `w`eeks
+fn parse_age_bound(word: &str) -> Option<AgeBound> {
+    let (bound, rest): (fn(Duration) -> AgeBound, _) = if let Some(rest) = word.strip_prefix('>') {
+        (AgeBound::OlderThan, rest)
+    } else if let Some(rest) = word.strip_prefix('<') {
+        (AgeBound::NewerThan, rest)
+    } else {
+        return None;
+    };
+    let unit = rest.chars().last()?;
+    let count: u32 = rest[..rest.len() - unit.len_utf8()].parse().ok()?;
+    let duration = match unit {
+        'm' => Duration::try_minutes(count.into())?,
+        'h' => Duration::try_hours(count.into())?,
+        'd' => Duration::try_days(count.into())?,
+        'w' => Duration::try_weeks(count.into())?,
+        _ => return None,
+    };
+    Some(bound(duration))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_age_bounds_out_of_the_text() {
+        let cases: &[(&str, bool, &str, &[AgeBound])] = &[
+            ("", false, "", &[]),
+            ("feat", false, "feat", &[]),
+            (
+                "feat >30d",
+                false,
+                "feat",
+                &[AgeBound::OlderThan(Duration::days(30))],
+            ),
+            (
+                "<1h fix",
+                false,
+                "fix",
+                &[AgeBound::NewerThan(Duration::hours(1))],
+            ),
+            (
+                ">1w <90m",
+                false,
+                "",
+                &[
+                    AgeBound::OlderThan(Duration::weeks(1)),
+                    AgeBound::NewerThan(Duration::minutes(90)),
+                ],
+            ),
+            (
+                "!release >7d",
+                true,
+                "release",
+                &[AgeBound::OlderThan(Duration::days(7))],
+            ),
+            // Malformed or half-typed bounds are ignored
+            ("feat >", false, "feat", &[]),
+            ("feat >7", false, "feat", &[]),
+            ("feat >7x", false, "feat", &[]),
+            ("feat >d", false, "feat", &[]),
+            ("feat <-1d", false, "feat", &[]),
+            ("feat >99999999999999999999d", false, "feat", &[]),
+        ];
+
+        for &(input, negate, text, age_bounds) in cases {
+            assert_eq!(
+                Filter::parse(input),
+                Filter {
+                    negate,
+                    text: text.to_string(),
+                    age_bounds: age_bounds.to_vec(),
+                    ..Filter::default()
+                },
+                "filter {input:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn matches_any_of_the_authors() {
+        let filter = Filter::parse("feat @alice @BOB");
+        assert_eq!(filter.text, "feat");
+        assert_eq!(filter.authors, ["alice", "bob"]);
+
+        assert!(filter.allows_author(Some("Alice Smith")));
+        assert!(filter.allows_author(Some("Bobby Tables")));
+        assert!(!filter.allows_author(Some("Carol")));
+        assert!(!filter.allows_author(None));
+
+        // Nothing to match yet
+        let typing = Filter::parse("@");
+        assert!(typing.authors.is_empty());
+        assert!(typing.allows_author(None));
+    }
+
+    #[test]
+    fn parses_key_value_terms() {
+        let filter = Filter::parse("fix author:Alice age:<7d has-upstream:false merged:yes");
+        assert_eq!(
+            filter,
+            Filter {
+                text: "fix".to_string(),
+                age_bounds: vec![AgeBound::NewerThan(Duration::days(7))],
+                authors: vec!["alice".to_string()],
+                has_upstream: Some(false),
+                merged: Some(true),
+                ..Filter::default()
+            }
+        );
+        assert!(filter.allows_upstream(false));
+        assert!(!filter.allows_upstream(true));
+        assert!(filter.allows_merged(true));
+        assert!(!filter.allows_merged(false));
+        assert!(Filter::parse("fix").allows_merged(false));
+
+        // Unfinished or unknown terms are left out, and hinted at
+        for input in [
+            "fix merged:",
+            "fix merged:tr",
+            "fix age:<7",
+            "fix size:big",
+            "fix :",
+        ] {
+            assert_eq!(Filter::parse(input), Filter::parse("fix"), "{input:?}");
+            assert!(Filter::wants_hint(input), "{input:?}");
+        }
+        assert!(!Filter::wants_hint("fix merged:no"));
+        assert!(!Filter::wants_hint("merged: fix"));
+    }
+
+    #[test]
+    fn age_bounds_are_exclusive() {
+        let filter = Filter::parse(">1d <1w");
+        assert!(!filter.allows_age(Duration::hours(23)));
+        assert!(!filter.allows_age(Duration::days(1)));
+        assert!(filter.allows_age(Duration::days(2)));
+        assert!(!filter.allows_age(Duration::weeks(1)));
+        assert!(Filter::parse("").allows_age(Duration::zero()));
+    }
+}
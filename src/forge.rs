@@ -0,0 +1,341 @@
+use crate::palette::Palette;
+use anyhow::{Result, anyhow, bail};
+use gb::config::Forge;
+use ratatui::text::Span;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    io,
+    path::Path,
+    process::{Command, Stdio},
+};
+
+/// How many open pull requests are asked for, newest first
+const PULL_REQUEST_LIMIT: &str = "200";
+
+/// The open pull (or merge) request for a branch, as shown beside it
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PullRequest {
+    pub number: u64,
+    pub draft: bool,
+    pub review: Review,
+    pub checks: Checks,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Review {
+    Approved,
+    ChangesRequested,
+    /// Waiting on a review the repository requires
+    Required,
+    /// Nobody has to review it, or the forge doesn't say
+    None,
+}
+
+/// How the CI checks on the pull request's latest commit are doing, the
+/// worst of them
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Checks {
+    Passing,
+    Failing,
+    Pending,
+    None,
+}
+
+impl PullRequest {
+    /// Like `#12 ✓ ●`: the number, then a review and a checks glyph when
+    /// there's something to say
+    pub fn spans(&self, palette: &Palette) -> Vec<Span<'static>> {
+        let number_style = if self.draft {
+            palette.dim
+        } else {
+            palette.notice
+        };
+        let mut spans = vec![Span::styled(format!("#{}", self.number), number_style)];
+        match self.review {
+            Review::Approved => spans.push(Span::styled(" ✓", palette.current)),
+            Review::ChangesRequested => spans.push(Span::styled(" ✗", palette.danger)),
+            Review::Required => spans.push(Span::styled(" …", palette.dim)),
+            Review::None => {}
+        }
+        match self.checks {
+            Checks::Passing => spans.push(Span::styled(" ●", palette.current)),
+            Checks::Failing => spans.push(Span::styled(" ●", palette.danger)),
+            Checks::Pending => spans.push(Span::styled(" ●", palette.notice)),
+            Checks::None => {}
+        }
+        spans
+    }
+}
+
+/// The open pull requests by the branch they're from, asked of the forge's
+/// command line tool (`gh` or `glab`) in `dir`, which knows the remote and
+/// does the signing in. A `token` is handed to it instead of its own login.
+pub fn pull_requests(
+    forge: Forge,
+    dir: &Path,
+    token: Option<&str>,
+) -> Result<BTreeMap<String, PullRequest>> {
+    let (program, args, token_var): (_, &[&str], _) = match forge {
+        Forge::GitHub => (
+            "gh",
+            &[
+                "pr",
+                "list",
+                "--state",
+                "open",
+                "--limit",
+                PULL_REQUEST_LIMIT,
+                "--json",
+                "number,headRefName,isDraft,reviewDecision,statusCheckRollup",
+            ],
+            "GH_TOKEN",
+        ),
+        Forge::GitLab => (
+            "glab",
+            &[
+                "mr",
+                "list",
+                "--output",
+                "json",
+                "--per-page",
+                PULL_REQUEST_LIMIT,
+            ],
+            "GITLAB_TOKEN",
+        ),
+    };
+
+    let mut command = Command::new(program);
+    command
+        .args(args)
+        .current_dir(dir)
+        // Nothing may prompt, the terminal is in raw mode
+        .env("GH_PROMPT_DISABLED", "1")
+        .env("NO_PROMPT", "1")
+        .stdin(Stdio::null());
+    if let Some(token) = token {
+        command.env(token_var, token);
+    }
+    let output = match command.output() {
+        Ok(output) => output,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            bail!(
+                "{} isn't installed, it's what gb asks for pull requests",
+                program
+            )
+        }
+        Err(err) => return Err(err.into()),
+    };
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let message = stderr.lines().find(|line| !line.trim().is_empty());
+        bail!(
+            "{} failed: {}",
+            program,
+            message.unwrap_or("no output").trim()
+        );
+    }
+
+    let json = String::from_utf8_lossy(&output.stdout);
+    match forge {
+        Forge::GitHub => parse_github(&json),
+        Forge::GitLab => parse_gitlab(&json),
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GitHubPullRequest {
+    number: u64,
+    head_ref_name: String,
+    #[serde(default)]
+    is_draft: bool,
+    /// `APPROVED`, `CHANGES_REQUESTED`, `REVIEW_REQUIRED` or empty
+    #[serde(default)]
+    review_decision: Option<String>,
+    #[serde(default)]
+    status_check_rollup: Vec<GitHubCheck>,
+}
+
+/// A check run (with a status and, once completed, a conclusion) or a
+/// commit status (with a state)
+#[derive(Deserialize)]
+struct GitHubCheck {
+    status: Option<String>,
+    conclusion: Option<String>,
+    state: Option<String>,
+}
+
+/// `gh pr list --json` output
+fn parse_github(json: &str) -> Result<BTreeMap<String, PullRequest>> {
+    let pulls: Vec<GitHubPullRequest> =
+        serde_json::from_str(json).map_err(|err| anyhow!("Unexpected output from gh: {}", err))?;
+    Ok(pulls
+        .into_iter()
+        .map(|pull| {
+            let review = match pull.review_decision.as_deref() {
+                Some("APPROVED") => Review::Approved,
+                Some("CHANGES_REQUESTED") => Review::ChangesRequested,
+                Some("REVIEW_REQUIRED") => Review::Required,
+                _ => Review::None,
+            };
+            let states = pull.status_check_rollup.iter().map(|check| {
+                match (
+                    check.status.as_deref(),
+                    check.conclusion.as_deref(),
+                    check.state.as_deref(),
+                ) {
+                    (_, _, Some("SUCCESS")) => Checks::Passing,
+                    (_, _, Some("PENDING" | "EXPECTED")) => Checks::Pending,
+                    (_, _, Some(_)) => Checks::Failing,
+                    (Some(status), _, _) if status != "COMPLETED" => Checks::Pending,
+                    (_, Some("SUCCESS" | "NEUTRAL" | "SKIPPED"), _) => Checks::Passing,
+                    _ => Checks::Failing,
+                }
+            });
+            let pull_request = PullRequest {
+                number: pull.number,
+                draft: pull.is_draft,
+                review,
+                checks: worst(states),
+            };
+            (pull.head_ref_name, pull_request)
+        })
+        .collect())
+}
+
+#[derive(Deserialize)]
+struct GitLabMergeRequest {
+    iid: u64,
+    source_branch: String,
+    #[serde(default)]
+    draft: bool,
+    /// What stands between it and merging, like `not_approved` or
+    /// `ci_still_running`, which is all the list says about reviews and CI
+    #[serde(default)]
+    detailed_merge_status: Option<String>,
+    /// Who approved it, where the output says; the list usually doesn't
+    #[serde(default)]
+    approved_by: Vec<serde::de::IgnoredAny>,
+}
+
+/// `glab mr list --output json` output
+fn parse_gitlab(json: &str) -> Result<BTreeMap<String, PullRequest>> {
+    let merge_requests: Vec<GitLabMergeRequest> = serde_json::from_str(json)
+        .map_err(|err| anyhow!("Unexpected output from glab: {}", err))?;
+    Ok(merge_requests
+        .into_iter()
+        .map(|merge_request| {
+            let status = merge_request.detailed_merge_status.as_deref();
+            let pull_request = PullRequest {
+                number: merge_request.iid,
+                draft: merge_request.draft,
+                review: match status {
+                    Some("not_approved") => Review::Required,
+                    // `mergeable` only says nothing blocks it, which an
+                    // unreviewed merge request can be too
+                    _ if !merge_request.approved_by.is_empty() => Review::Approved,
+                    _ => Review::None,
+                },
+                checks: match status {
+                    Some("ci_still_running") => Checks::Pending,
+                    Some("ci_must_pass") => Checks::Failing,
+                    _ => Checks::None,
+                },
+            };
+            (merge_request.source_branch, pull_request)
+        })
+        .collect())
+}
+
+/// Failing over pending over passing, or none without any checks
+fn worst(checks: impl Iterator<Item = Checks>) -> Checks {
+    let rank = |checks: Checks| match checks {
+        Checks::None => 0,
+        Checks::Passing => 1,
+        Checks::Pending => 2,
+        Checks::Failing => 3,
+    };
+    checks
+        .max_by_key(|&checks| rank(checks))
+        .unwrap_or(Checks::None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_github_pull_requests() {
+        let json = r#"[
+            {"number": 12, "headRefName": "feat", "isDraft": false,
+             "reviewDecision": "APPROVED",
+             "statusCheckRollup": [
+                {"__typename": "CheckRun", "status": "COMPLETED", "conclusion": "SUCCESS"},
+                {"__typename": "StatusContext", "state": "SUCCESS"}
+             ]},
+            {"number": 13, "headRefName": "fix", "isDraft": true,
+             "reviewDecision": "CHANGES_REQUESTED",
+             "statusCheckRollup": [
+                {"__typename": "CheckRun", "status": "IN_PROGRESS", "conclusion": ""},
+                {"__typename": "CheckRun", "status": "COMPLETED", "conclusion": "FAILURE"}
+             ]},
+            {"number": 14, "headRefName": "wip", "isDraft": false,
+             "reviewDecision": "", "statusCheckRollup": []}
+        ]"#;
+        let pulls = parse_github(json).unwrap();
+
+        assert_eq!(
+            pulls["feat"],
+            PullRequest {
+                number: 12,
+                draft: false,
+                review: Review::Approved,
+                checks: Checks::Passing,
+            }
+        );
+        assert_eq!(
+            pulls["fix"],
+            PullRequest {
+                number: 13,
+                draft: true,
+                review: Review::ChangesRequested,
+                checks: Checks::Failing,
+            }
+        );
+        assert_eq!(
+            (pulls["wip"].review, pulls["wip"].checks),
+            (Review::None, Checks::None)
+        );
+        assert!(parse_github("not json").is_err());
+    }
+
+    #[test]
+    fn reads_gitlab_merge_requests() {
+        let json = r#"[
+            {"iid": 3, "source_branch": "feat", "draft": false,
+             "detailed_merge_status": "ci_still_running"},
+            {"iid": 4, "source_branch": "fix", "draft": true,
+             "detailed_merge_status": "not_approved"},
+            {"iid": 5, "source_branch": "unreviewed", "draft": false,
+             "detailed_merge_status": "mergeable"},
+            {"iid": 6, "source_branch": "approved", "draft": false,
+             "detailed_merge_status": "mergeable",
+             "approved_by": [{"user": {"username": "alice"}}]}
+        ]"#;
+        let merge_requests = parse_gitlab(json).unwrap();
+
+        assert_eq!(
+            merge_requests["feat"],
+            PullRequest {
+                number: 3,
+                draft: false,
+                review: Review::None,
+                checks: Checks::Pending,
+            }
+        );
+        assert_eq!(merge_requests["fix"].review, Review::Required);
+        assert_eq!(merge_requests["unreviewed"].review, Review::None);
+        assert_eq!(merge_requests["approved"].review, Review::Approved);
+    }
+}
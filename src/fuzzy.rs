@@ -0,0 +1,254 @@
+// Points for each matched character, and bonuses for where it matched
+const MATCH_SCORE: i64 = 1;
+const CONSECUTIVE_BONUS: i64 = 8;
+const BOUNDARY_BONUS: i64 = 4;
+// Taken off for every character skipped between two matched ones
+const GAP_PENALTY: i64 = 1;
+
+/// Ranks `names` against `query`, returning the index and score of every name
+/// that matches, best first.
+///
+/// A name matches when it contains the query's characters in order, though not
+/// necessarily next to each other, ignoring case: `ftx` matches
+/// `feature/thing-x`. Runs of consecutive characters and matches at the start
+/// of a word (after `/`, `-`, `_` or `.`) score higher, and gaps cost a little,
+/// so `log` ranks `login` above `l-o-g`. Names with equal scores keep their
+/// order in `names`, as does everything when the query is empty.
+pub fn rank_matches(names: &[&str], query: &str) -> Vec<(usize, i64)> {
+    let query: Vec<char> = query.chars().flat_map(char::to_lowercase).collect();
+
+    let mut ranked: Vec<(usize, i64)> = names
+        .iter()
+        .enumerate()
+        .filter_map(|(i, name)| {
+            let name: Vec<char> = name.chars().flat_map(char::to_lowercase).collect();
+            score(&name, &query).map(|score| (i, score))
+        })
+        .collect();
+    // Stable, so ties stay in their original order
+    ranked.sort_by_key(|&(_, score)| std::cmp::Reverse(score));
+    ranked
+}
+
+/// The positions of the characters in `name` that `query` matched, as
+/// counted by `chars()`, for highlighting them. Empty when it doesn't match,
+/// or when the query is.
+pub fn match_positions(name: &str, query: &str) -> Vec<usize> {
+    let query: Vec<char> = query.chars().flat_map(char::to_lowercase).collect();
+    let name: Vec<char> = name.chars().flat_map(char::to_lowercase).collect();
+    best_positions(&name, &query).unwrap_or_default()
+}
+
+fn score(name: &[char], query: &[char]) -> Option<i64> {
+    best_positions(name, query).map(|positions| score_positions(name, &positions))
+}
+
+/// Where the query matches best, if it matches at all
+fn best_positions(name: &[char], query: &[char]) -> Option<Vec<usize>> {
+    if query.is_empty() {
+        return Some(Vec::new());
+    }
+
+    // A contiguous occurrence beats any scattered one, so take the best of those
+    let substring = (0..=name.len().saturating_sub(query.len()))
+        .filter(|&start| name[start..].starts_with(query))
+        .map(|start| (start..start + query.len()).collect::<Vec<_>>())
+        // The first of the best, when several score the same
+        .min_by_key(|positions| std::cmp::Reverse(score_positions(name, positions)));
+    if substring.is_some() {
+        return substring;
+    }
+
+    // Otherwise line the query up where it scores best, rather than taking
+    // each character at its earliest position: `fxl` in `fixture/fix-login`
+    // goes with `fix-l`, not with the `f` and `x` of `fixture`
+    best_alignment(name, query)
+}
+
+/// The positions `score_positions` scores highest for a scattered match, the
+/// first of them on a tie. For each query character and each place it could
+/// go, this keeps the best score of the query so far ending there, and where
+/// the character before it went for that.
+fn best_alignment(name: &[char], query: &[char]) -> Option<Vec<usize>> {
+    let char_score = |at: usize| {
+        let boundary = at == 0 || matches!(name[at - 1], '/' | '-' | '_' | '.');
+        MATCH_SCORE + if boundary { BOUNDARY_BONUS } else { 0 }
+    };
+
+    let mut best: Vec<Vec<Option<i64>>> = vec![vec![None; name.len()]; query.len()];
+    let mut from = vec![vec![0; name.len()]; query.len()];
+    for (at, &c) in name.iter().enumerate() {
+        if c == query[0] {
+            best[0][at] = Some(char_score(at));
+        }
+    }
+    for i in 1..query.len() {
+        // The best previous position more than one back, by its score plus
+        // its own gap allowance, so each gap is priced without rescanning
+        let mut gapped: Option<(i64, usize)> = None;
+        for at in 1..name.len() {
+            if at >= 2
+                && let Some(score) = best[i - 1][at - 2]
+            {
+                let allowance = score + (at - 2) as i64 * GAP_PENALTY;
+                if gapped.is_none_or(|(kept, _)| allowance > kept) {
+                    gapped = Some((allowance, at - 2));
+                }
+            }
+            if name[at] != query[i] {
+                continue;
+            }
+
+            let next_to = best[i - 1][at - 1].map(|score| (score + CONSECUTIVE_BONUS, at - 1));
+            let after_gap = gapped
+                .map(|(allowance, previous)| (allowance - (at - 1) as i64 * GAP_PENALTY, previous));
+            let chosen = match (next_to, after_gap) {
+                (Some(next_to), Some(after_gap)) if after_gap.0 > next_to.0 => Some(after_gap),
+                (Some(next_to), _) => Some(next_to),
+                (None, after_gap) => after_gap,
+            };
+            if let Some((score, previous)) = chosen {
+                best[i][at] = Some(score + char_score(at));
+                from[i][at] = previous;
+            }
+        }
+    }
+
+    let last = query.len() - 1;
+    let (mut at, _) = best[last]
+        .iter()
+        .enumerate()
+        .filter_map(|(at, score)| score.map(|score| (at, score)))
+        .min_by_key(|&(_, score)| std::cmp::Reverse(score))?;
+    let mut positions = vec![at; query.len()];
+    for i in (1..query.len()).rev() {
+        at = from[i][at];
+        positions[i - 1] = at;
+    }
+    Some(positions)
+}
+
+fn score_positions(name: &[char], positions: &[usize]) -> i64 {
+    let mut score = 0;
+    let mut previous: Option<usize> = None;
+
+    for &position in positions {
+        score += MATCH_SCORE;
+        if position == 0 || matches!(name[position - 1], '/' | '-' | '_' | '.') {
+            score += BOUNDARY_BONUS;
+        }
+        match previous {
+            Some(previous) if position == previous + 1 => score += CONSECUTIVE_BONUS,
+            Some(previous) => score -= (position - previous - 1) as i64 * GAP_PENALTY,
+            None => {}
+        }
+        previous = Some(position);
+    }
+    score
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranked_indices(names: &[&str], query: &str) -> Vec<usize> {
+        rank_matches(names, query)
+            .into_iter()
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    #[test]
+    fn empty_query_returns_everything_in_order() {
+        let names = ["zebra", "apple", "mango"];
+        assert_eq!(rank_matches(&names, ""), vec![(0, 0), (1, 0), (2, 0)]);
+        assert_eq!(rank_matches(&[], ""), vec![]);
+    }
+
+    #[test]
+    fn matches_table() {
+        let names = [
+            "main",
+            "feature/login",
+            "feature/thing-x",
+            "fix/thing",
+            "Release/2.0",
+        ];
+        let cases: &[(&str, &[usize])] = &[
+            // Substrings
+            ("main", &[0]),
+            ("login", &[1]),
+            ("thing", &[2, 3]),
+            // Fuzzy subsequences
+            ("ftx", &[2]),
+            ("flogin", &[1]),
+            ("fxthg", &[3]),
+            // Case is ignored on both sides
+            ("LOGIN", &[1]),
+            ("release", &[4]),
+            ("rel/2", &[4]),
+            // Order matters and every character has to be there
+            ("nigol", &[]),
+            ("mainx", &[]),
+            ("zzz", &[]),
+        ];
+
+        for (query, expected) in cases {
+            assert_eq!(
+                ranked_indices(&names, query),
+                expected.to_vec(),
+                "query {query:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn better_matches_rank_first() {
+        let cases: &[(&[&str], &str, &[usize])] = &[
+            // Contiguous beats scattered
+            (&["l-o-g", "login"], "log", &[1, 0]),
+            // Start of a word beats the middle of one
+            (&["blogpost", "feature/login"], "log", &[1, 0]),
+            // Tighter beats looser
+            (&["f-----x", "f-x"], "fx", &[1, 0]),
+            // However the characters are best lined up, not where they first appear
+            (&["f-x-------l", "fixture/fix-login"], "fxl", &[1, 0]),
+        ];
+
+        for (names, query, expected) in cases {
+            assert_eq!(
+                ranked_indices(names, query),
+                expected.to_vec(),
+                "query {query:?} in {names:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn ties_keep_their_original_order() {
+        let names = ["feature/login", "bugfix/login", "login"];
+        let ranked = rank_matches(&names, "login");
+        assert!(ranked.iter().all(|&(_, score)| score == ranked[0].1));
+        assert_eq!(ranked_indices(&names, "login"), vec![0, 1, 2]);
+
+        let reversed = ["login", "bugfix/login", "feature/login"];
+        assert_eq!(ranked_indices(&reversed, "login"), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn match_positions_point_at_the_matched_characters() {
+        // The `t` that starts a word rather than the first one
+        assert_eq!(match_positions("feature/thing-x", "ftx"), vec![0, 8, 14]);
+        assert_eq!(match_positions("fixture/fix-login", "fxl"), vec![8, 10, 12]);
+        assert_eq!(
+            match_positions("fix/logging-errors", "fixlog"),
+            vec![0, 1, 2, 4, 5, 6]
+        );
+        // The contiguous occurrence, and of those the one at a word start
+        assert_eq!(match_positions("l-o-g/login", "log"), vec![6, 7, 8]);
+        assert_eq!(match_positions("blog/log", "log"), vec![5, 6, 7]);
+        assert_eq!(match_positions("Release", "REL"), vec![0, 1, 2]);
+        assert_eq!(match_positions("main", "x"), Vec::<usize>::new());
+        assert_eq!(match_positions("main", ""), Vec::<usize>::new());
+    }
+}
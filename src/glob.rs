@@ -0,0 +1,58 @@
+/// Matches `text` against a shell-style pattern where `*` matches any run of
+/// characters (including `/`) and `?` matches exactly one character.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    // Where the last `*` was seen, and how much of the text it has swallowed
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star_p, star_t)) => {
+                    p = star_p + 1;
+                    t = star_t + 1;
+                    backtrack = Some((star_p, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_globs() {
+        assert!(glob_match("main", "main"));
+        assert!(glob_match("feat/*", "feat/login"));
+        assert!(glob_match("feat/*", "feat/login/oauth"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("*/fix-*", "user/fix-123"));
+        assert!(glob_match("v?.?", "v1.2"));
+        assert!(glob_match("**a", "bba"));
+    }
+
+    #[test]
+    fn rejects_non_matches() {
+        assert!(!glob_match("main", "mainline"));
+        assert!(!glob_match("feat/*", "feature/login"));
+        assert!(!glob_match("feat/*", "fix/feat/login"));
+        assert!(!glob_match("v?.?", "v1.23"));
+        assert!(!glob_match("?", ""));
+    }
+}
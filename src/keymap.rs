@@ -0,0 +1,140 @@
+use anyhow::{Result, anyhow};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::collections::{BTreeMap, HashMap};
+
+/// A key as the list tells keys apart: by code, and by Ctrl and Alt. Shift
+/// is already in the character, `G` rather than shift-`g`.
+type Key = (KeyCode, KeyModifiers);
+
+/// The `keys` remappings from the configuration, making one key act like
+/// another
+#[derive(Debug, Default)]
+pub struct Keymap {
+    keys: HashMap<Key, Key>,
+}
+
+impl Keymap {
+    pub fn new(keys: &BTreeMap<String, String>) -> Result<Keymap> {
+        let parse = |name: &str| {
+            parse_key(name).ok_or_else(|| anyhow!("Invalid key '{}' in the keys config", name))
+        };
+        let keys = keys
+            .iter()
+            .map(|(from, to)| Ok((parse(from)?, parse(to)?)))
+            .collect::<Result<_>>()?;
+        Ok(Keymap { keys })
+    }
+
+    /// The key `key` stands for, or `key` itself when it isn't remapped
+    pub fn apply(&self, key: KeyEvent) -> KeyEvent {
+        let modifiers = key.modifiers & (KeyModifiers::CONTROL | KeyModifiers::ALT);
+        match self.keys.get(&(key.code, modifiers)) {
+            Some(&(code, modifiers)) => KeyEvent::new(code, modifiers),
+            None => key,
+        }
+    }
+}
+
+/// Parses a key like `j`, `G`, `ctrl-r`, `alt-enter`, `space` or `f5`
+fn parse_key(name: &str) -> Option<Key> {
+    let mut modifiers = KeyModifiers::NONE;
+    let mut rest = name;
+    // Only while something's left after the dash, so `-` is a key of its own
+    loop {
+        if let Some(key) = rest.strip_prefix("ctrl-").filter(|key| !key.is_empty()) {
+            modifiers |= KeyModifiers::CONTROL;
+            rest = key;
+        } else if let Some(key) = rest.strip_prefix("alt-").filter(|key| !key.is_empty()) {
+            modifiers |= KeyModifiers::ALT;
+            rest = key;
+        } else {
+            break;
+        }
+    }
+
+    let mut chars = rest.chars();
+    let code = match (chars.next()?, chars.next()) {
+        (c, None) => KeyCode::Char(c),
+        _ => match rest {
+            "space" => KeyCode::Char(' '),
+            "enter" => KeyCode::Enter,
+            "esc" => KeyCode::Esc,
+            "tab" => KeyCode::Tab,
+            "backspace" => KeyCode::Backspace,
+            "delete" => KeyCode::Delete,
+            "up" => KeyCode::Up,
+            "down" => KeyCode::Down,
+            "left" => KeyCode::Left,
+            "right" => KeyCode::Right,
+            "home" => KeyCode::Home,
+            "end" => KeyCode::End,
+            "pageup" => KeyCode::PageUp,
+            "pagedown" => KeyCode::PageDown,
+            _ => KeyCode::F(
+                rest.strip_prefix('f')?
+                    .parse()
+                    .ok()
+                    .filter(|n| (1..=12).contains(n))?,
+            ),
+        },
+    };
+    Some((code, modifiers))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_key_names() {
+        let cases = [
+            ("j", Some((KeyCode::Char('j'), KeyModifiers::NONE))),
+            ("G", Some((KeyCode::Char('G'), KeyModifiers::NONE))),
+            ("-", Some((KeyCode::Char('-'), KeyModifiers::NONE))),
+            ("ctrl-r", Some((KeyCode::Char('r'), KeyModifiers::CONTROL))),
+            ("ctrl--", Some((KeyCode::Char('-'), KeyModifiers::CONTROL))),
+            (
+                "ctrl-alt-enter",
+                Some((KeyCode::Enter, KeyModifiers::CONTROL | KeyModifiers::ALT)),
+            ),
+            ("space", Some((KeyCode::Char(' '), KeyModifiers::NONE))),
+            ("pagedown", Some((KeyCode::PageDown, KeyModifiers::NONE))),
+            ("f5", Some((KeyCode::F(5), KeyModifiers::NONE))),
+            ("f13", None),
+            ("ctrl-", None),
+            ("", None),
+            ("jump", None),
+        ];
+        for (name, expected) in cases {
+            assert_eq!(parse_key(name), expected, "key {name:?}");
+        }
+    }
+
+    #[test]
+    fn remapped_keys_stand_for_others() {
+        let keys = [("x", "d"), ("ctrl-j", "down")]
+            .map(|(from, to)| (from.to_string(), to.to_string()))
+            .into();
+        let keymap = Keymap::new(&keys).unwrap();
+
+        let x = keymap.apply(KeyEvent::new(KeyCode::Char('x'), KeyModifiers::NONE));
+        assert_eq!(
+            (x.code, x.modifiers),
+            (KeyCode::Char('d'), KeyModifiers::NONE)
+        );
+        let ctrl_j = keymap.apply(KeyEvent::new(KeyCode::Char('j'), KeyModifiers::CONTROL));
+        assert_eq!(ctrl_j.code, KeyCode::Down);
+        // Others are left alone, Shift included
+        let g = keymap.apply(KeyEvent::new(KeyCode::Char('G'), KeyModifiers::SHIFT));
+        assert_eq!(
+            (g.code, g.modifiers),
+            (KeyCode::Char('G'), KeyModifiers::SHIFT)
+        );
+
+        let bad = [("x".to_string(), "jump".to_string())].into();
+        assert_eq!(
+            Keymap::new(&bad).unwrap_err().to_string(),
+            "Invalid key 'jump' in the keys config"
+        );
+    }
+}
@@ -0,0 +1,11 @@
+//! The branch list behind the `gb` picker: what a branch is, how the list is
+//! sorted and capped, and the user's configuration.
+
+pub mod branch;
+pub mod config;
+pub mod glob;
+
+#[cfg(test)]
+mod test_repo;
+
+pub use branch::{GitBranch, RECENT_LIMIT, RefKind, SortMode, capped, recent_branches};
@@ -1,306 +1,8308 @@
-use anyhow::Result;
-use chrono::{DateTime, Utc};
+mod cli;
+mod clipboard;
+mod filter;
+mod forge;
+mod fuzzy;
+mod keymap;
+mod palette;
+mod repos;
+mod shell;
+mod state;
+mod tree;
+
+#[cfg(test)]
+mod test_repo;
+
+use anyhow::{Result, anyhow, bail};
+use chrono::{DateTime, Local, Utc};
+use clap::Parser;
+use cli::{Args, Command as CliCommand};
 use crossterm::{
-    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEventKind},
+    event::{
+        self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEvent, KeyEventKind,
+        KeyModifiers, MouseButton, MouseEvent, MouseEventKind,
+    },
     execute,
-    terminal::{EnterAlternateScreen, LeaveAlternateScreen, disable_raw_mode, enable_raw_mode},
+    terminal::{
+        EnterAlternateScreen, LeaveAlternateScreen, SetTitle, disable_raw_mode, enable_raw_mode,
+    },
 };
-use git2::{BranchType, Repository};
+use filter::Filter;
+use forge::PullRequest;
+use fuzzy::{match_positions, rank_matches};
+use gb::{
+    GitBranch, RefKind, SortMode,
+    branch::{capped, frecency, sort_by_recency, time_ago},
+    config::{Column, Config, StartSelection, Theme, TimeSource},
+    glob::glob_match,
+};
+use git2::{BranchType, ErrorCode, Oid, Repository, RepositoryState};
+use keymap::Keymap;
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use palette::{ColorMode, Palette};
 use ratatui::{
-    Frame, Terminal,
+    Frame, Terminal, TerminalOptions, Viewport,
     backend::{Backend, CrosstermBackend},
-    layout::{Constraint, Direction, Layout},
-    style::{Color, Modifier, Style},
+    layout::{Constraint, Direction, Layout, Position, Rect},
+    style::Style,
     text::{Line, Span},
-    widgets::{List, ListItem, ListState, Paragraph},
+    widgets::{Block, Borders, Clear, List, ListItem, ListState, Paragraph, Wrap},
+};
+use repos::Checkout;
+use serde::Serialize;
+use state::{ListView, RepoState, State};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process::{Command, ExitCode, ExitStatus, Output, Stdio},
+    sync::mpsc::{self, Receiver, TryRecvError},
+    thread,
+    time::{Duration, Instant},
 };
-use std::{io, process::Command};
+use tree::{Group, TreeRow, tree_rows};
+
+// How many rows the mouse wheel scrolls at a time
+const MOUSE_SCROLL_LINES: isize = 3;
+// How soon a second click on the same row makes it a double click
+const DOUBLE_CLICK: Duration = Duration::from_millis(400);
+// How long a confirmation, like for copying, stays in the status line
+const FLASH_DURATION: Duration = Duration::from_secs(3);
+
+// How long to wait for input before redrawing, so the spinner keeps moving
+// and the ages roll over (59m to 1h) without a keypress
+const TICK_RATE: Duration = Duration::from_millis(100);
+const SPINNER_FRAMES: [&str; 10] = ["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
+const MAX_DESCRIPTION_WIDTH: usize = 50;
+const MAX_AUTHOR_WIDTH: usize = 20;
+const MAX_UPSTREAM_WIDTH: usize = 30;
+/// How many remote branches `u`'s menu shows at once
+const UPSTREAM_MENU_ROWS: usize = 10;
+/// How many of the selected branch's commits the preview lists
+const PREVIEW_COMMITS: usize = 50;
+/// Narrower than this, the list keeps the whole width to itself
+const MIN_PREVIEW_WIDTH: u16 = 80;
+// Rows the picker takes up with `--inline`
+const INLINE_HEIGHT: u16 = 12;
+
+/// Which refs the list shows, picked from the `Tab` menu
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum ListSource {
+    Local,
+    Remote,
+    Tags,
+    /// Local and remote branches, tags and stashes
+    All,
+    /// Only the stashes, to apply, pop or drop
+    Stashes,
+}
+
+impl ListSource {
+    const ALL: [ListSource; 5] = [
+        ListSource::Local,
+        ListSource::Remote,
+        ListSource::Tags,
+        ListSource::All,
+        ListSource::Stashes,
+    ];
+
+    /// The key that picks it in the menu
+    fn key(self) -> char {
+        match self {
+            ListSource::Local => 'l',
+            ListSource::Remote => 'r',
+            ListSource::Tags => 't',
+            ListSource::All => 'a',
+            ListSource::Stashes => 's',
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            ListSource::Local => "local branches",
+            ListSource::Remote => "remote branches",
+            ListSource::Tags => "tags",
+            ListSource::All => "all refs",
+            ListSource::Stashes => "stashes",
+        }
+    }
+
+    /// The list to start on, from `--remotes`, `--all-refs` or `show_remote`
+    fn starting(args: &Args, config: &Config) -> ListSource {
+        if args.remotes {
+            ListSource::Remote
+        } else if args.all_refs || config.show_remote {
+            ListSource::All
+        } else {
+            ListSource::Local
+        }
+    }
+
+    /// Whether it lists refs of `kind`. A detached HEAD is in all of them
+    /// but the stashes, being where the working tree is
+    fn includes(self, kind: RefKind) -> bool {
+        match self {
+            ListSource::Stashes => kind == RefKind::Stash,
+            _ if kind == RefKind::Head => true,
+            ListSource::Local => kind == RefKind::Local,
+            ListSource::Remote => kind == RefKind::Remote,
+            ListSource::Tags => kind == RefKind::Tag,
+            ListSource::All => true,
+        }
+    }
+}
+
+/// The headers the recency-sorted list is grouped under
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum DateGroup {
+    Today,
+    Yesterday,
+    ThisWeek,
+    Older,
+}
+
+impl DateGroup {
+    /// Which group `time` falls in, by calendar day in the local time zone
+    fn of(now: DateTime<Local>, time: DateTime<Utc>) -> DateGroup {
+        let days = (now.date_naive() - time.with_timezone(&Local).date_naive()).num_days();
+        match days {
+            ..=0 => DateGroup::Today,
+            1 => DateGroup::Yesterday,
+            2..=6 => DateGroup::ThisWeek,
+            _ => DateGroup::Older,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            DateGroup::Today => "Today",
+            DateGroup::Yesterday => "Yesterday",
+            DateGroup::ThisWeek => "This week",
+            DateGroup::Older => "Older",
+        }
+    }
+}
+
+/// Counts for the `G` overview, see `App::branch_stats`
+#[derive(Debug, Default, PartialEq, Eq)]
+struct BranchStats {
+    /// Today, this week, this month, older
+    ages: [usize; 4],
+    merged: usize,
+    unmerged: usize,
+}
+
+impl BranchStats {
+    const AGE_LABELS: [&str; 4] = ["Today", "This week", "This month", "Older"];
+}
+
+/// How the picker was left, so `main` can decide what to print and how to exit
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Outcome {
+    /// Closed with `q`/`Esc` without doing anything
+    Quit,
+    /// A branch was checked out
+    CheckedOut(String),
+    /// Interrupted with `Ctrl-c`
+    Cancelled,
+    /// Branches were deleted in cleanup mode
+    Deleted(Vec<String>),
+    /// A stash was applied to the working tree
+    StashApplied(String),
+    /// A stash was applied to the working tree and dropped
+    StashPopped(String),
+    /// The configured `enter_command`, to run once the terminal is restored
+    RunCommand(String),
+    /// Picked with `--print`, to print once the terminal is restored
+    Picked(String),
+    /// A worktree for the branch was added, or one already existed
+    Worktree {
+        branch: String,
+        path: PathBuf,
+        created: bool,
+    },
+}
+
+/// What the pane beside the list shows about the selected branch
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum PreviewKind {
+    /// Its latest commits
+    Commits,
+    /// The files it changed since it forked from HEAD
+    DiffStat,
+}
+
+/// The files a branch changed, and how many lines it added and removed
+#[derive(Debug)]
+struct DiffStat {
+    files: Vec<FileStat>,
+    insertions: usize,
+    deletions: usize,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct FileStat {
+    /// Like `old → new` when it was renamed
+    path: String,
+    /// Lines added and removed, or nothing for a binary file
+    lines: Option<(usize, usize)>,
+}
+
+/// A commit in the preview beside the list
+struct PreviewCommit {
+    short_id: String,
+    author: String,
+    time: DateTime<Utc>,
+    subject: String,
+}
+
+/// `u`'s menu of remote branches for the selected branch to track, narrowed
+/// down by typing
+struct UpstreamMenu {
+    branch: String,
+    /// The upstream it tracks now, if any
+    current: Option<String>,
+    /// Every remote branch, like `origin/main`
+    remotes: Vec<String>,
+    query: String,
+    /// Which of the matches is highlighted
+    highlighted: usize,
+}
+
+impl UpstreamMenu {
+    /// The remote branches the query matches, best first
+    fn matches(&self) -> Vec<&str> {
+        let names: Vec<&str> = self.remotes.iter().map(String::as_str).collect();
+        rank_matches(&names, &self.query)
+            .into_iter()
+            .map(|(i, _)| names[i])
+            .collect()
+    }
+}
+
+/// Free text typed into the status line, submitted with `Enter`
+struct Input {
+    kind: InputKind,
+    value: String,
+}
+
+enum InputKind {
+    /// Where to add a worktree for the given branch
+    WorktreePath { branch: String },
+    /// The new name for the given branch
+    RenameBranch { branch: String },
+}
+
+impl Input {
+    fn label(&self) -> String {
+        match &self.kind {
+            InputKind::WorktreePath { branch } => format!("Worktree path for {}: ", branch),
+            InputKind::RenameBranch { branch } => format!("Rename {} to: ", branch),
+        }
+    }
+}
+
+/// A yes/no question shown in the status line, answered with `y`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Prompt {
+    DeleteMarked,
+    /// Check out again once the git process holding the lock is done
+    RetryLocked,
+    /// Check out even though there are uncommitted changes
+    DirtyCheckout,
+    /// Check out a tag, which leaves HEAD detached
+    DetachedCheckout,
+    /// Delete the selected branch
+    DeleteSelected,
+    /// Delete the selected branch, which isn't merged: the first of two asks
+    DeleteUnmerged,
+    /// Delete the selected branch and the commits only it has
+    ForceDeleteSelected,
+    /// Create a branch named like the filter, which matches none
+    CreateBranch,
+    /// Rebase onto or merge the selected branch
+    Integrate(Integration),
+    /// Drop the selected stash
+    DropStash,
+}
+
+/// Bringing the selected branch into the current one, which git does with
+/// the terminal handed over so the editor and conflicts can be dealt with
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Integration {
+    Rebase,
+    Merge,
+}
+
+impl Integration {
+    fn verb(self) -> &'static str {
+        match self {
+            Integration::Rebase => "rebase",
+            Integration::Merge => "merge",
+        }
+    }
 
-#[derive(Clone, Debug)]
-struct GitBranch {
-    name: String,
-    is_current: bool,
-    last_commit_time: DateTime<Utc>,
+    /// The integration a queued git command is, if any
+    fn of(args: &[String]) -> Option<Integration> {
+        match args.first().map(String::as_str) {
+            Some("rebase") => Some(Integration::Rebase),
+            Some("merge") => Some(Integration::Merge),
+            _ => None,
+        }
+    }
 }
 
 struct App {
+    config: Config,
+    only: Option<String>,
+    /// Only list branches merged into the base branch, for bulk deletion
+    cleanup: bool,
+    /// The list `M` switched to cleanup mode from, to go back to
+    pruning: Option<ListSource>,
+    /// The list `s` switched to the stashes from, to go back to
+    stashes_from: Option<ListSource>,
+    /// Only list the current branch and the one checked out before it
+    quick: bool,
+    /// Protected branches can be deleted and renamed after all
+    allow_protected: bool,
+    /// Browse only: anything that would change the repository is refused
+    read_only: bool,
+    /// `Enter` picks the branch to print on exit instead of checking it out
+    print: bool,
+    /// The branch HEAD last moved away from, according to the reflog
+    previous_branch: Option<String>,
+    /// Which refs are listed
+    source: ListSource,
+    /// The highlighted entry while the `Tab` menu for picking the list
+    /// source is open
+    source_menu: Option<usize>,
+    upstream_menu: Option<UpstreamMenu>,
+    /// Where each source's list was left, to return there when switching back
+    source_views: HashMap<ListSource, ListView>,
+    /// Drawn below the prompt rather than on the alternate screen
+    inline: bool,
+    palette: Palette,
+    /// Keys remapped in the configuration
+    keymap: Keymap,
+    repo_name: Option<String>,
     branches: Vec<GitBranch>,
+    current_branch: Option<String>,
+    base_branch: Option<String>,
+    marked: HashSet<String>,
+    /// Local branches cleanup mode left out of the list, with why
+    cleanup_skipped: Vec<(String, &'static str)>,
+    /// How far the deletion summary is scrolled, in lines
+    summary_scroll: u16,
+    /// Showing branch counts by age and merged status instead of the list
+    show_stats: bool,
+    /// The selected branch's recent commits or diffstat, shown beside the list
+    show_preview: bool,
+    preview_kind: PreviewKind,
+    /// The preview's commits and the tip they were read from, read again
+    /// once the selection moves to another tip
+    preview: Option<(Oid, Result<Vec<PreviewCommit>, String>)>,
+    /// The diffstat preview and the tip and HEAD it was worked out between
+    diff_stat: Option<((Oid, Oid), Result<DiffStat, String>)>,
+    /// How far the preview is scrolled, in lines
+    preview_scroll: u16,
+    /// The commit checked out when the branches were read
+    head: Option<Oid>,
+    prompt: Option<Prompt>,
+    /// The lock file another git process held when checking out failed
+    lock_file: Option<PathBuf>,
+    /// Said once the terminal is restored, after leaving with a checkout
+    exit_note: Option<String>,
+    /// The working tree has uncommitted changes or untracked files
+    dirty: bool,
+    input: Option<Input>,
+    /// Typing goes to the filter, even keys that are otherwise actions
+    filter_focused: bool,
+    /// A git command to run with the terminal handed over, e.g. to page output
+    pending_git: Option<Vec<String>>,
+    sort_mode: SortMode,
+    /// List the sort mode's order back to front, e.g. oldest first
+    reversed: bool,
+    /// Show full ref paths like `refs/heads/main` instead of short names
+    full_names: bool,
+    /// Local branches checked out in another worktree, and where
+    worktrees: HashMap<String, PathBuf>,
+    /// With `--repos`, the checkouts listed together. Their branches are
+    /// named `<checkout>/<branch>` and only ever checked out.
+    checkouts_listed: Vec<Checkout>,
+    /// Local branches pinned to the top of the list, saved per repository
+    favorites: BTreeSet<String>,
+    /// When gb checked out each local branch, for sorting by frecency
+    checkouts: HashMap<String, Vec<DateTime<Utc>>>,
+    /// Show date headers between the rows, in recency order
+    date_headers: bool,
+    /// Only list branches with commits their upstream doesn't have yet
+    unpushed_only: bool,
+    /// Group the branches by their slash-delimited prefixes, like `feature/`
+    tree_view: bool,
+    /// The groups opened in the tree view, by prefix; the rest are collapsed
+    expanded: BTreeSet<String>,
     filtered_branches: Vec<usize>,
+    /// In the tree view, how each of the filtered branches is laid out
+    tree_rows: Vec<TreeRow>,
     list_state: ListState,
+    /// Rows the list had on the last draw, for scrolling by the page
+    list_height: usize,
+    /// The row each filtered branch was drawn on, which date headers push down
+    list_rows: Vec<usize>,
+    /// Where the list and the preview were drawn, to tell what a click is on
+    list_area: Rect,
+    preview_area: Option<Rect>,
+    /// The branch last clicked and when, to tell a double click
+    last_click: Option<(usize, Instant)>,
     filter: String,
+    status_message: Option<String>,
+    /// Why the last action failed, shown until the next key
+    error_message: Option<String>,
+    /// A status message that goes away by itself, and since when it's shown
+    flashed: Option<(String, Instant)>,
+    fetch_task: Option<Receiver<io::Result<Output>>>,
+    /// Count how far branches are from their upstreams on another thread,
+    /// filling them in as the counts arrive, rather than while loading
+    background_divergence: bool,
+    /// The counts still coming in
+    divergence_task: Option<Receiver<DivergenceCount>>,
+    /// List the local branches by name and tip straight away, reading their
+    /// commits, reflogs and whether they're merged on another thread
+    background_details: bool,
+    /// The details still coming in
+    details_task: Option<Receiver<BranchDetail>>,
+    /// The local branches listed before their details came in, which show
+    /// no age until they do
+    pending_details: HashSet<String>,
+    /// The open pull requests by the branch they're from, as last asked of
+    /// the forge, which is kept between runs so they show straight away
+    pull_requests: BTreeMap<String, PullRequest>,
+    /// The pull requests being asked for again
+    pull_request_task: Option<Receiver<Result<BTreeMap<String, PullRequest>>>>,
+    spinner_frame: usize,
+    /// Watches the refs on disk when `watch_refs` is on, signalling changes
+    ref_watcher: Option<(RecommendedWatcher, Receiver<()>)>,
 }
 
 impl App {
-    fn new() -> Result<App> {
+    fn new(mut config: Config, args: &Args) -> Result<App> {
+        // Across repositories there's none to start in
+        let checkouts = match &args.repos {
+            Some(Some(dir)) => repos::find_checkouts(std::slice::from_ref(dir))?,
+            Some(None) if config.repos.is_empty() => {
+                bail!("--repos needs a directory, or the `repos` setting to say where to look")
+            }
+            Some(None) => {
+                let paths: Vec<_> = config.repos.iter().map(|path| expand_home(path)).collect();
+                repos::find_checkouts(&paths)?
+            }
+            None => Vec::new(),
+        };
+        let repo = if checkouts.is_empty() {
+            Some(open_repo()?)
+        } else {
+            None
+        };
+        if let Some(repo) = &repo {
+            config.apply_git_config(&repo.config()?)?;
+        }
+        let mut app = App::with_branches(Vec::new(), config);
+        app.date_headers = app.config.date_headers;
+        app.tree_view = app.config.tree_view;
+        app.show_preview = app.config.preview;
+        app.preview_kind = PreviewKind::Commits;
+        app.sort_mode = args.sort.unwrap_or(app.config.sort);
+        if let Some(limit) = args.limit {
+            app.config.limit = limit;
+        }
+        if args.all {
+            app.config.limit = 0;
+        }
+        if let Some(columns) = &args.columns {
+            app.config.columns = columns.clone();
+        }
+        app.only = args.only.clone();
+        app.cleanup = args.cleanup;
+        app.quick = args.quick;
+        app.read_only = args.read_only;
+        app.allow_protected = args.allow_protected;
+        app.print = args.print;
+        // Listing only the unpushed branches takes the counts up front, as
+        // does printing them
+        app.background_divergence = !args.unpushed && !args.list;
+        // Cleanup needs to know what's merged before it can offer anything
+        app.background_details = !args.list && !args.cleanup;
+        app.unpushed_only = args.unpushed;
+        app.source = ListSource::starting(args, &app.config);
+        app.inline = args.inline;
+        app.palette = Palette::new(args.colors, app.config.theme)
+            .with_colors(
+                args.colors,
+                app.config.accent_color.as_deref(),
+                app.config.highlight_color.as_deref(),
+            )?
+            .with_styles(args.colors, &app.config.styles)?;
+        app.keymap = Keymap::new(&app.config.keys)?;
+        if !checkouts.is_empty() {
+            // Grouped by checkout, all of them open, with nothing capped
+            app.tree_view = true;
+            app.expanded = checkouts
+                .iter()
+                .map(|checkout| format!("{}/", checkout.name))
+                .collect();
+            app.background_divergence = false;
+            app.background_details = false;
+            app.checkouts_listed = checkouts;
+        }
+        let repo_state = match &repo {
+            Some(repo) => State::load()?.repo(repo),
+            None => RepoState::default(),
+        };
+        app.favorites = repo_state.favorites;
+        if app.config.forge.is_some() {
+            app.pull_requests = repo_state.pull_requests;
+        }
+        app.checkouts = repo_state
+            .checkouts
+            .iter()
+            .map(|(name, times)| {
+                let times = times
+                    .iter()
+                    .filter_map(|&time| DateTime::from_timestamp(time, 0))
+                    .collect();
+                (name.clone(), times)
+            })
+            .collect();
+        if let Some(pattern) = &args.pattern {
+            app.filter = pattern.clone();
+        }
+        app.refresh()?;
+
+        if app.config.watch_refs && !args.list && repo.is_some() {
+            match watch_refs() {
+                Ok(watcher) => app.ref_watcher = Some(watcher),
+                Err(err) => {
+                    app.status_message = Some(format!("Not watching for ref changes: {}", err))
+                }
+            }
+        }
+
+        if !args.list
+            && let Some(repo) = &repo
+        {
+            app.start_pull_requests(repo);
+        }
+
+        if repo.as_ref().is_some_and(Repository::is_bare) {
+            app.status_message = Some(
+                "Bare repository: branches can be looked through, renamed and deleted, not checked out"
+                    .to_string(),
+            );
+        }
+
+        if app.quick && app.previous_branch.is_none() {
+            app.status_message = Some("No previous branch in the reflog".to_string());
+        }
+
+        if app.cleanup {
+            app.mark_for_cleanup();
+        }
+
+        match app.config.start_selection {
+            StartSelection::Recent => {}
+            StartSelection::Current => {
+                if let Some(current) = app.current_branch.clone() {
+                    app.select_branch(&current);
+                }
+            }
+            StartSelection::Last => {
+                if app.remembers_view()
+                    && let Some(view) = repo_state.last_view
+                    && app.select_branch(&view.selected)
+                {
+                    *app.list_state.offset_mut() = view.offset;
+                }
+            }
+        }
+        Ok(app)
+    }
+
+    fn terminal_title(&self) -> String {
+        let repo = self.repo_name.as_deref().unwrap_or("gb");
+        match &self.current_branch {
+            Some(branch) => format!("gb — {}:{}", repo, branch),
+            None => format!("gb — {}", repo),
+        }
+    }
+
+    /// Whether this list is the regular one, whose place is kept between runs
+    /// with `start_selection = "last"`
+    fn remembers_view(&self) -> bool {
+        self.config.start_selection == StartSelection::Last
+            && !self.cleanup
+            && !self.quick
+            && self.checkouts_listed.is_empty()
+    }
+
+    /// Saves the selection and scroll position for the next run
+    fn save_view(&self) -> Result<()> {
+        let Some(branch) = self.selected_branch() else {
+            return Ok(());
+        };
+        let repo = open_repo()?;
+        let mut state = State::load()?;
+        state.repo_mut(&repo).last_view = Some(ListView {
+            selected: branch.name.clone(),
+            offset: self.list_state.offset(),
+        });
+        state.save()
+    }
+
+    fn with_branches(branches: Vec<GitBranch>, config: Config) -> App {
         let mut app = App {
-            branches: Vec::new(),
+            config,
+            only: None,
+            cleanup: false,
+            pruning: None,
+            stashes_from: None,
+            quick: false,
+            print: false,
+            read_only: false,
+            allow_protected: false,
+            previous_branch: None,
+            source: ListSource::Local,
+            source_menu: None,
+            upstream_menu: None,
+            source_views: HashMap::new(),
+            inline: false,
+            palette: Palette::new(ColorMode::Ansi16, Theme::Dark),
+            keymap: Keymap::default(),
+            repo_name: None,
+            branches,
+            current_branch: None,
+            base_branch: None,
+            marked: HashSet::new(),
+            cleanup_skipped: Vec::new(),
+            summary_scroll: 0,
+            show_stats: false,
+            show_preview: false,
+            preview_kind: PreviewKind::Commits,
+            preview: None,
+            diff_stat: None,
+            preview_scroll: 0,
+            head: None,
+            prompt: None,
+            lock_file: None,
+            exit_note: None,
+            dirty: false,
+            input: None,
+            filter_focused: false,
+            pending_git: None,
+            sort_mode: SortMode::Recent,
+            reversed: false,
+            full_names: false,
+            worktrees: HashMap::new(),
+            checkouts_listed: Vec::new(),
+            favorites: BTreeSet::new(),
+            checkouts: HashMap::new(),
+            date_headers: false,
+            unpushed_only: false,
+            tree_view: false,
+            expanded: BTreeSet::new(),
             filtered_branches: Vec::new(),
+            tree_rows: Vec::new(),
             list_state: ListState::default(),
+            list_height: 0,
+            list_rows: Vec::new(),
+            list_area: Rect::default(),
+            preview_area: None,
+            last_click: None,
+            error_message: None,
+            flashed: None,
             filter: String::new(),
+            status_message: None,
+            fetch_task: None,
+            background_divergence: false,
+            divergence_task: None,
+            background_details: false,
+            details_task: None,
+            pending_details: HashSet::new(),
+            pull_requests: BTreeMap::new(),
+            pull_request_task: None,
+            spinner_frame: 0,
+            ref_watcher: None,
         };
-        app.fetch_branches()?;
+        app.sort_branches();
         app.update_filter();
-        Ok(app)
+        app
     }
 
     fn fetch_branches(&mut self) -> Result<()> {
-        let repo = Repository::open(".")?;
+        if !self.checkouts_listed.is_empty() {
+            return self.load_checkouts();
+        }
+        let mut repo = open_repo()?;
+        self.dirty = is_dirty(&repo);
+        self.load_branches(&mut repo)
+    }
+
+    fn load_branches(&mut self, repo: &mut Repository) -> Result<()> {
+        let mut branches = Vec::new();
+        self.read_branches(repo, &mut |branch| {
+            branches.push(branch);
+            Ok(())
+        })?;
+
+        // Every branch is kept; the recency cap is applied in `update_filter`
+        self.branches = branches;
+        self.sort_branches();
+        Ok(())
+    }
+
+    /// Reads the branches of every checkout listed with `--repos`, each named
+    /// after its checkout. What's worked out about the current repository
+    /// along the way means nothing across them, so it's left out.
+    fn load_checkouts(&mut self) -> Result<()> {
         let mut branches = Vec::new();
+        for checkout in self.checkouts_listed.clone() {
+            let mut repo = Repository::open(&checkout.path).map_err(|err| {
+                anyhow!(
+                    "Failed to open {}: {}",
+                    checkout.path.display(),
+                    err.message()
+                )
+            })?;
+            self.read_branches(&mut repo, &mut |mut branch| {
+                branch.name = format!("{}/{}", checkout.name, branch.name);
+                branches.push(branch);
+                Ok(())
+            })?;
+        }
+        self.repo_name = None;
+        self.current_branch = None;
+        self.previous_branch = None;
+        self.base_branch = None;
+        self.head = None;
+        self.worktrees.clear();
+
+        self.branches = branches;
+        self.sort_branches();
+        Ok(())
+    }
+
+    /// The checkout a branch listed with `--repos` is in, and its name there
+    fn checkout_of<'a>(&self, branch: &'a GitBranch) -> Option<(&Checkout, &'a str)> {
+        let (name, rest) = branch.name.split_once('/')?;
+        let checkout = self
+            .checkouts_listed
+            .iter()
+            .find(|checkout| checkout.name == name)?;
+        Some((checkout, rest))
+    }
+
+    /// The repository `branch` is in: the current one, or with `--repos` its
+    /// checkout
+    fn repo_of(&self, branch: &GitBranch) -> Result<Repository> {
+        match self.checkout_of(branch) {
+            Some((checkout, _)) => Ok(Repository::open(&checkout.path)?),
+            None => open_repo(),
+        }
+    }
+
+    /// Checks out the selected branch in its checkout, staying open to
+    /// switch others
+    fn checkout_across_repos(&mut self) -> Result<Option<Outcome>> {
+        let Some(branch) = self.selected_branch() else {
+            return Ok(None);
+        };
+        let Some((checkout, name)) = self.checkout_of(branch) else {
+            return Ok(None);
+        };
+        if branch.is_current {
+            self.status_message = Some(format!("{} is already on {}", checkout.name, name));
+            return Ok(None);
+        }
+        let repo = Repository::open(&checkout.path)?;
+        if self.config.confirm_dirty_checkout && is_dirty(&repo) {
+            self.status_message = Some(format!(
+                "{} has uncommitted changes, commit or stash them there first",
+                checkout.name
+            ));
+            return Ok(None);
+        }
+        let checkout_name = checkout.name.clone();
+        let checked_out = checkout_ref(&repo, branch.ref_kind, name)?;
+        self.refresh()?;
+        self.status_message = Some(format!("Switched {} to {}", checkout_name, checked_out));
+        Ok(None)
+    }
+
+    /// Reads the branches, and the other refs the source includes, handing
+    /// each to `on_branch` as soon as it's read, unsorted. Along the way it
+    /// works out the current, previous and base branches. With
+    /// `background_details`, local branches come without what their commits
+    /// and reflogs say, which `read_details` sends on.
+    fn read_branches(
+        &mut self,
+        repo: &mut Repository,
+        on_branch: &mut dyn FnMut(GitBranch) -> Result<()>,
+    ) -> Result<()> {
+        let stashed = stashed_branches(repo)?;
+        let checkouts = checkout_times(repo);
+        self.repo_name = repo
+            .workdir()
+            .unwrap_or_else(|| repo.path())
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned());
+
+        let git_config = repo.config()?;
+        let mut cleanup_skipped = Vec::new();
+        let mut current_branch = None;
+        let mut uncounted = Vec::new();
+
+        let base_branch = default_branch(repo);
+        let base_oid = base_branch
+            .as_deref()
+            .and_then(|name| repo.find_branch(name, BranchType::Local).ok())
+            .and_then(|branch| peeled_tip(branch.get()));
+        // The picker leaves this to `read_details`, but cleanup, `M`
+        // included, and the rest need it now
+        let merged_now = !self.background_details || self.cleanup;
+        let merged = match base_oid {
+            Some(base) if merged_now => {
+                let tips = repo
+                    .branches(Some(BranchType::Local))?
+                    .flatten()
+                    .filter_map(|(branch, _)| peeled_tip(branch.get()))
+                    .collect();
+                merged_tips(repo, base, &tips)
+            }
+            _ => HashSet::new(),
+        };
+
+        // While they're read again in the background, a branch still at the
+        // same tip shows what it did in the list being replaced
+        let known: HashMap<&str, &GitBranch> = self
+            .branches
+            .iter()
+            .filter(|branch| self.background_details && branch.ref_kind == RefKind::Local)
+            .map(|branch| (branch.name.as_str(), branch))
+            .collect();
+        let mut undetailed = Vec::new();
+        let mut pending_details = HashSet::new();
 
         let branch_iter = repo.branches(Some(BranchType::Local))?;
         for branch_result in branch_iter {
             let (branch, _) = branch_result?;
             if let Some(name) = branch.name()? {
                 let is_current = branch.is_head();
+                if is_current {
+                    current_branch = Some(name.to_string());
+                }
 
-                let last_commit_time = {
-                    let reference = branch.get();
-                    if let Some(target) = reference.target() {
-                        if let Ok(commit) = repo.find_commit(target) {
-                            let timestamp = commit.time();
-                            DateTime::from_timestamp(timestamp.seconds(), 0)
-                                .unwrap_or_else(|| Utc::now())
-                        } else {
-                            Utc::now()
-                        }
+                if let Some(pattern) = &self.only
+                    && !glob_match(pattern, name)
+                {
+                    continue;
+                }
+
+                let tip = peeled_tip(branch.get());
+                let is_merged = tip.is_some_and(|tip| merged.contains(&tip));
+
+                // The current and base branches are never offered for deletion
+                if self.cleanup {
+                    let skipped = if is_current {
+                        Some("checked out")
+                    } else if base_branch.as_deref() == Some(name) {
+                        Some("the base branch")
+                    } else if !is_merged {
+                        Some("not merged")
                     } else {
-                        Utc::now()
+                        None
+                    };
+                    if let Some(reason) = skipped {
+                        cleanup_skipped.push((name.to_string(), reason));
+                        continue;
                     }
-                };
+                }
+
+                // Set with `git branch --edit-description`
+                let description = git_config
+                    .get_string(&format!("branch.{}.description", name))
+                    .ok()
+                    .and_then(|description| {
+                        description
+                            .lines()
+                            .next()
+                            .map(|line| line.trim().to_string())
+                    })
+                    .filter(|description| !description.is_empty());
+
+                // Still looked at for the current branch, even when not listed
+                if !self.source.includes(RefKind::Local) {
+                    continue;
+                }
 
-                branches.push(GitBranch {
+                let mut listed = GitBranch {
                     name: name.to_string(),
+                    ref_kind: RefKind::Local,
                     is_current,
-                    last_commit_time,
-                });
+                    tip,
+                    last_commit_time: DateTime::UNIX_EPOCH,
+                    description,
+                    has_stash: stashed.contains(name),
+                    is_protected: self.config.is_protected(name),
+                    ahead_behind: if self.background_divergence {
+                        uncounted
+                            .extend(upstream_tips(&branch).map(|tips| (name.to_string(), tips)));
+                        None
+                    } else {
+                        ahead_behind(repo, &branch)
+                    },
+                    upstream: branch
+                        .upstream()
+                        .ok()
+                        .and_then(|upstream| upstream.name().ok().flatten().map(str::to_string)),
+                    upstream_gone: upstream_gone(&git_config, &branch, name),
+                    last_reflog_time: None,
+                    tip_signed: false,
+                    author: None,
+                    subject: None,
+                    is_merged,
+                    is_unborn: false,
+                    not_a_commit: false,
+                };
+                match tip {
+                    Some(tip) if self.background_details => {
+                        match known.get(name).filter(|known| known.tip == Some(tip)) {
+                            Some(known) => {
+                                CommitDetails::of(known).apply(&mut listed);
+                                if !merged_now {
+                                    listed.is_merged = known.is_merged;
+                                }
+                            }
+                            None => {
+                                pending_details.insert(name.to_string());
+                            }
+                        }
+                        undetailed.push((name.to_string(), tip));
+                    }
+                    _ => CommitDetails::read(
+                        repo,
+                        name,
+                        tip,
+                        self.config.time_source,
+                        checkouts.get(name).copied(),
+                    )
+                    .apply(&mut listed),
+                }
+                on_branch(listed)?;
             }
         }
 
-        branches.sort_by(|a, b| b.last_commit_time.cmp(&a.last_commit_time));
-        self.branches = branches.into_iter().take(10).collect();
-        Ok(())
-    }
+        // An unborn branch has no ref yet, so it isn't among the branches
+        if let Some(name) = unborn_branch(repo)
+            && !self.cleanup
+            && self.source.includes(RefKind::Local)
+            && self
+                .only
+                .as_deref()
+                .is_none_or(|pattern| glob_match(pattern, &name))
+        {
+            current_branch = Some(name.clone());
+            on_branch(GitBranch {
+                is_protected: self.config.is_protected(&name),
+                description: None,
+                name,
+                ref_kind: RefKind::Local,
+                is_current: true,
+                tip: None,
+                last_commit_time: Utc::now(),
+                has_stash: false,
+                ahead_behind: None,
+                upstream: None,
+                upstream_gone: false,
+                last_reflog_time: None,
+                tip_signed: false,
+                author: None,
+                subject: None,
+                is_merged: false,
+                is_unborn: true,
+                not_a_commit: false,
+            })?;
+        }
 
-    fn update_filter(&mut self) {
-        if self.filter.is_empty() {
-            self.filtered_branches = (0..self.branches.len()).collect();
-        } else {
-            self.filtered_branches = self
-                .branches
-                .iter()
-                .enumerate()
-                .filter(|(_, branch)| {
-                    branch
-                        .name
-                        .to_lowercase()
-                        .contains(&self.filter.to_lowercase())
-                })
-                .map(|(i, _)| i)
-                .collect();
+        // A detached HEAD is on no branch, so the commit gets an entry of its
+        // own, named by its hash like `git checkout` would take it
+        if !self.cleanup
+            && self.source.includes(RefKind::Head)
+            && repo.head_detached().unwrap_or(false)
+            && let Ok(head) = repo.head()
+            && let Ok(commit) = head.peel_to_commit()
+            && let Some(name) = commit.as_object().short_id()?.as_str()
+            && self
+                .only
+                .as_deref()
+                .is_none_or(|pattern| glob_match(pattern, name))
+        {
+            let tip = Some(commit.id());
+            let last_commit_time = commit_time(repo, tip, self.config.time_source);
+            let (author, subject) = commit_summary(repo, tip);
+            on_branch(GitBranch {
+                name: name.to_string(),
+                ref_kind: RefKind::Head,
+                is_current: true,
+                tip,
+                last_commit_time: last_commit_time.unwrap_or(DateTime::UNIX_EPOCH),
+                description: Some("(detached)".to_string()),
+                has_stash: false,
+                is_protected: false,
+                ahead_behind: None,
+                upstream: None,
+                upstream_gone: false,
+                last_reflog_time: None,
+                tip_signed: is_signed(repo, tip),
+                author,
+                subject,
+                is_merged: false,
+                is_unborn: false,
+                not_a_commit: false,
+            })?;
         }
 
-        if !self.filtered_branches.is_empty() {
-            self.list_state.select(Some(0));
-        } else {
-            self.list_state.select(None);
+        // Cleanup only ever deletes local branches, so it never lists the rest
+        if !self.cleanup {
+            self.other_refs(repo, on_branch)?;
         }
+
+        // Replacing the receiver stops counts for a list that's gone
+        self.divergence_task =
+            (!uncounted.is_empty()).then(|| count_divergence(repo.path(), uncounted));
+        self.details_task = (!undetailed.is_empty()).then(|| {
+            read_details(
+                repo.path(),
+                undetailed,
+                base_oid,
+                self.config.time_source,
+                checkouts,
+            )
+        });
+        self.pending_details = pending_details;
+        self.worktrees = other_worktrees(repo);
+        self.previous_branch = previous_checkout(repo, current_branch.as_deref());
+        self.head = repo.head().ok().and_then(|head| head.target());
+        self.current_branch = current_branch;
+        self.base_branch = base_branch;
+        self.cleanup_skipped = cleanup_skipped;
+        Ok(())
     }
 
-    fn next(&mut self) {
-        if self.filtered_branches.is_empty() {
-            return;
+    /// Remote branches, tags and stashes, for all-refs mode
+    fn other_refs(
+        &self,
+        repo: &mut Repository,
+        on_branch: &mut dyn FnMut(GitBranch) -> Result<()>,
+    ) -> Result<()> {
+        if self.source == ListSource::Local {
+            return Ok(());
         }
-        let i = match self.list_state.selected() {
-            Some(i) => {
-                if i >= self.filtered_branches.len() - 1 {
-                    0
-                } else {
-                    i + 1
-                }
+        let wanted = |kind: RefKind, name: &str| {
+            self.source.includes(kind)
+                && self
+                    .only
+                    .as_deref()
+                    .is_none_or(|pattern| glob_match(pattern, name))
+        };
+        let time_source = self.config.time_source;
+        let entry = |repo: &Repository,
+                     name: String,
+                     ref_kind: RefKind,
+                     tip: Option<Oid>,
+                     description: Option<String>| {
+            let last_commit_time = commit_time(repo, tip, time_source);
+            let (author, subject) = commit_summary(repo, tip);
+            GitBranch {
+                last_commit_time: last_commit_time.unwrap_or(DateTime::UNIX_EPOCH),
+                name,
+                ref_kind,
+                is_current: false,
+                tip,
+                description,
+                has_stash: false,
+                is_protected: false,
+                ahead_behind: None,
+                upstream: None,
+                upstream_gone: false,
+                last_reflog_time: None,
+                tip_signed: is_signed(repo, tip),
+                author,
+                subject,
+                is_merged: false,
+                is_unborn: false,
+                not_a_commit: last_commit_time.is_none(),
             }
-            None => 0,
         };
-        self.list_state.select(Some(i));
-    }
 
-    fn previous(&mut self) {
-        if self.filtered_branches.is_empty() {
-            return;
+        for branch_result in repo.branches(Some(BranchType::Remote))? {
+            let (branch, _) = branch_result?;
+            // `origin/HEAD` only points at another remote branch
+            if branch.get().symbolic_target().is_some() {
+                continue;
+            }
+            if let Some(name) = branch.name()?
+                && wanted(RefKind::Remote, name)
+            {
+                let tip = peeled_tip(branch.get());
+                on_branch(entry(repo, name.to_string(), RefKind::Remote, tip, None))?;
+            }
         }
-        let i = match self.list_state.selected() {
-            Some(i) => {
-                if i == 0 {
-                    self.filtered_branches.len() - 1
-                } else {
-                    i - 1
-                }
+
+        for name in repo.tag_names(None)?.iter().flatten() {
+            if !wanted(RefKind::Tag, name) {
+                continue;
             }
-            None => 0,
-        };
-        self.list_state.select(Some(i));
-    }
+            // Annotated tags point at a tag object, so peel to the commit
+            let object = repo.revparse_single(&format!("refs/tags/{}", name)).ok();
+            let tip = object
+                .as_ref()
+                .and_then(|object| object.peel_to_commit().ok())
+                .map(|commit| commit.id());
+            let mut tag = entry(repo, name.to_string(), RefKind::Tag, tip, None);
+            // They're dated by when they were tagged, which is the release
+            // date, rather than by their commit
+            if let Some(tagged) = object
+                .as_ref()
+                .and_then(|object| object.as_tag())
+                .and_then(|tag| tag.tagger())
+                .and_then(|tagger| DateTime::from_timestamp(tagger.when().seconds(), 0))
+            {
+                tag.last_commit_time = tagged;
+            }
+            on_branch(tag)?;
+        }
 
-    fn checkout_selected(&self) -> Result<()> {
-        if let Some(selected) = self.list_state.selected() {
-            if let Some(&branch_idx) = self.filtered_branches.get(selected) {
-                if let Some(branch) = self.branches.get(branch_idx) {
-                    if !branch.is_current {
-                        let output = Command::new("git")
-                            .args(["checkout", &branch.name])
-                            .output()?;
-
-                        if !output.status.success() {
-                            return Err(anyhow::anyhow!(
-                                "Failed to checkout branch: {}",
-                                String::from_utf8_lossy(&output.stderr)
-                            ));
-                        }
-                    }
-                }
+        // Walking the stashes borrows the repository mutably, so they're
+        // gathered before being looked at
+        let mut stashes = Vec::new();
+        repo.stash_foreach(|index, message, oid| {
+            let name = format!("stash@{{{}}}", index);
+            if wanted(RefKind::Stash, &name) {
+                stashes.push((name, *oid, message.to_string()));
             }
+            true
+        })?;
+        for (name, oid, message) in stashes {
+            let mut stash = entry(repo, name, RefKind::Stash, Some(oid), Some(message));
+            // The stash commit's subject is its message, already shown
+            if stash.subject == stash.description {
+                stash.subject = None;
+            }
+            on_branch(stash)?;
         }
         Ok(())
     }
 
-    fn add_char(&mut self, c: char) {
-        self.filter.push(c);
-        self.update_filter();
+    /// How many branches, from the top of the sorted list, can be shown.
+    ///
+    /// A `limit` only applies in recency and activity order, where "the 25
+    /// most recent branches" is a meaningful set. In name order the first 25
+    /// would be an arbitrary slice, so everything is listed. Cleanup mode also lists
+    /// every merged branch, however old.
+    fn branch_limit(&self) -> Option<usize> {
+        match self.sort_mode {
+            // Cleanup and the unpushed list are only useful complete, as
+            // are the stashes, however old, and the tree view, where the
+            // groups take the room instead
+            SortMode::Recent | SortMode::Activity | SortMode::Frecency
+                if !self.cleanup
+                    && !self.unpushed_only
+                    && !self.tree_view
+                    && self.source != ListSource::Stashes
+                    && self.config.limit > 0 =>
+            {
+                Some(self.config.limit)
+            }
+            _ => None,
+        }
     }
 
-    fn remove_char(&mut self) {
-        self.filter.pop();
-        self.update_filter();
+    /// How many branches make it past the cap, out of how many there are
+    fn listed_count(&self) -> (usize, usize) {
+        let total = self
+            .branches
+            .iter()
+            .filter(|branch| self.is_listable(branch))
+            .count();
+        (
+            self.branch_limit().map_or(total, |limit| total.min(limit)),
+            total,
+        )
     }
-}
 
-fn ui(f: &mut Frame, app: &mut App) {
-    let area = f.area();
+    /// Whether `branch` is left in the list before the cap and the filter
+    fn is_listable(&self, branch: &GitBranch) -> bool {
+        !(self.config.hide_current_branch && branch.is_current)
+            && (!self.unpushed_only || branch.is_unpushed())
+    }
 
-    // Only show filter if there's text
-    let list_area = if app.filter.is_empty() {
-        area
-    } else {
-        let chunks = Layout::default()
-            .direction(Direction::Vertical)
-            .constraints([Constraint::Length(1), Constraint::Min(0)].as_ref())
-            .split(area);
+    fn sort_branches(&mut self) {
+        match self.sort_mode {
+            SortMode::Recent => sort_by_recency(&mut self.branches),
+            SortMode::Activity => self
+                .branches
+                .sort_by_key(|b| std::cmp::Reverse(b.last_activity())),
+            SortMode::Frecency => {
+                // Stable, so the newest go first among the equally used
+                sort_by_recency(&mut self.branches);
+                let now = Utc::now();
+                let checkouts = &self.checkouts;
+                self.branches.sort_by_cached_key(|branch| {
+                    let times = match branch.ref_kind {
+                        RefKind::Local => checkouts.get(&branch.name).map(Vec::as_slice),
+                        _ => None,
+                    };
+                    std::cmp::Reverse(frecency(
+                        times.unwrap_or_default(),
+                        branch.last_commit_time,
+                        now,
+                    ))
+                });
+            }
+            SortMode::Name => self.branches.sort_by(|a, b| a.name.cmp(&b.name)),
+        }
+        if self.reversed {
+            self.branches.reverse();
+        }
+        // Stable, so favorites keep the sort order among themselves. The
+        // previous branch goes above them, being what `-` switches back to
+        let favorites = &self.favorites;
+        let previous = self.previous_branch.as_deref();
+        self.branches.sort_by_key(|branch| {
+            let is_previous = branch.ref_kind == RefKind::Local && Some(&*branch.name) == previous;
+            (!is_previous, !is_favorite(favorites, branch))
+        });
+        if self.config.current_first {
+            self.branches.sort_by_key(|branch| !branch.is_current);
+        }
+        // Where the working tree is, when that's on no branch
+        self.branches
+            .sort_by_key(|branch| branch.ref_kind != RefKind::Head);
+    }
 
-        let filter_text = format!("Filter: {}", app.filter);
-        let filter_paragraph = Paragraph::new(filter_text).style(Style::default().fg(Color::Cyan));
-        f.render_widget(filter_paragraph, chunks[0]);
+    fn is_favorite(&self, branch: &GitBranch) -> bool {
+        is_favorite(&self.favorites, branch)
+    }
 
-        chunks[1]
-    };
+    /// Whether the branch is protected from being deleted or renamed, which
+    /// `--allow-protected` lifts. It's still marked as protected then.
+    fn guards(&self, branch: &GitBranch) -> bool {
+        branch.is_protected && !self.allow_protected
+    }
 
-    let items: Vec<ListItem> = app
-        .filtered_branches
-        .iter()
-        .enumerate()
-        .map(|(idx, &i)| {
-            let branch = &app.branches[i];
-            let is_selected = app.list_state.selected() == Some(idx);
+    /// Whether it's the branch checked out before the current one
+    fn is_previous(&self, branch: &GitBranch) -> bool {
+        branch.ref_kind == RefKind::Local
+            && self.previous_branch.as_deref() == Some(branch.name.as_str())
+    }
 
-            let mut spans = vec![];
+    /// Goes back to the branch checked out before this one, like
+    /// `git checkout -`
+    fn switch_back(&mut self) -> Result<Option<Outcome>> {
+        let Some(previous) = self.previous_branch.clone() else {
+            self.status_message = Some("No previous branch in the reflog".to_string());
+            return Ok(None);
+        };
+        if !self.select_branch(&previous) {
+            self.status_message = Some(format!("{} isn't in the list", previous));
+            return Ok(None);
+        }
+        activate(self)
+    }
 
-            // Selection indicator (like gum)
-            if is_selected {
-                spans.push(Span::styled("❯ ", Style::default().fg(Color::Magenta)));
-            } else {
-                spans.push(Span::raw("  "));
-            }
+    /// Whether to badge the branch as merged: a local one the base branch
+    /// has all of, other than the base branch itself. Cleanup mode only
+    /// lists those, so there it goes without saying.
+    fn shows_merged(&self, branch: &GitBranch) -> bool {
+        !self.cleanup
+            && branch.ref_kind == RefKind::Local
+            && branch.is_merged
+            && self.base_branch.as_deref() != Some(branch.name.as_str())
+    }
 
-            // Current branch indicator
-            if branch.is_current {
-                spans.push(Span::styled("● ", Style::default().fg(Color::Green)));
+    /// Where the branch is checked out, if that's another worktree
+    fn in_other_worktree(&self, branch: &GitBranch) -> Option<&PathBuf> {
+        match branch.ref_kind {
+            RefKind::Local => self.worktrees.get(&branch.name),
+            _ => None,
+        }
+    }
+
+    /// Pins the selected branch to the top of the list, or unpins it, and
+    /// saves that for the next run
+    fn toggle_favorite(&mut self) -> Result<()> {
+        if self.refuses_across_repos() {
+            return Ok(());
+        }
+        let Some(branch) = self.selected_branch() else {
+            return Ok(());
+        };
+        if branch.ref_kind != RefKind::Local {
+            self.status_message = Some(format!(
+                "Only local branches can be favorites, {} is a {}",
+                branch.name,
+                branch.ref_kind.badge()
+            ));
+            return Ok(());
+        }
+        let name = branch.name.clone();
+
+        let added = self.favorites.insert(name.clone());
+        if !added {
+            self.favorites.remove(&name);
+        }
+        let repo = open_repo()?;
+        let mut state = State::load()?;
+        state.repo_mut(&repo).favorites = self.favorites.clone();
+        state.save()?;
+
+        self.resort();
+        self.status_message = Some(if added {
+            format!("Pinned {} to the top", name)
+        } else {
+            format!("Unpinned {}", name)
+        });
+        Ok(())
+    }
+
+    /// Sorts again after the order changed, keeping the highlighted branch
+    /// highlighted, and says how the list is sorted now
+    fn resort(&mut self) {
+        let selected_name = self.selected_branch().map(|branch| branch.name.clone());
+
+        self.sort_branches();
+        // Sorting moved the branches under the selection, so look it up by name
+        self.list_state.select(None);
+        self.update_filter();
+
+        if let Some(name) = selected_name {
+            self.select_branch(&name);
+        }
+
+        let direction = match (self.sort_mode, self.reversed) {
+            (SortMode::Name, false) => "A to Z",
+            (SortMode::Name, true) => "Z to A",
+            (SortMode::Frecency, false) => "most used first",
+            (SortMode::Frecency, true) => "least used first",
+            (_, false) => "newest first",
+            (_, true) => "oldest first",
+        };
+        self.status_message = Some(format!(
+            "Sorted by {}, {}",
+            self.sort_mode.label(),
+            direction
+        ));
+    }
+
+    fn cycle_sort_mode(&mut self) {
+        self.sort_mode = self.sort_mode.next();
+        self.resort();
+    }
+
+    /// Flips the list end to end, keeping the sort mode
+    fn reverse_order(&mut self) {
+        self.reversed = !self.reversed;
+        self.resort();
+    }
+
+    fn toggle_full_names(&mut self) {
+        self.full_names = !self.full_names;
+        self.status_message = Some(if self.full_names {
+            "Showing full ref names".to_string()
+        } else {
+            "Showing short names".to_string()
+        });
+    }
+
+    /// Shows the pane beside the list with `kind`, or closes it when it
+    /// already shows that
+    fn toggle_preview(&mut self, kind: PreviewKind) {
+        // The diff is against HEAD, of which there's one per checkout
+        if kind == PreviewKind::DiffStat && self.refuses_across_repos() {
+            return;
+        }
+        self.show_preview = !(self.show_preview && self.preview_kind == kind);
+        if self.preview_kind != kind {
+            self.preview_kind = kind;
+            self.preview_scroll = 0;
+        }
+    }
+
+    /// Scrolls the preview by `lines`, no further than its last line
+    fn scroll_preview(&mut self, lines: i16) {
+        let count = match self.preview_kind {
+            PreviewKind::Commits => match &self.preview {
+                Some((_, Ok(commits))) => commits.len(),
+                _ => 0,
+            },
+            // The summary comes first
+            PreviewKind::DiffStat => match &self.diff_stat {
+                Some((_, Ok(stat))) => stat.files.len() + 1,
+                _ => 0,
+            },
+        };
+        self.preview_scroll = self
+            .preview_scroll
+            .saturating_add_signed(lines)
+            .min(count.saturating_sub(1) as u16);
+    }
+
+    fn toggle_unpushed_only(&mut self) {
+        self.unpushed_only = !self.unpushed_only;
+        self.update_filter();
+        self.status_message = Some(if self.unpushed_only {
+            "Showing only branches ahead of their upstream".to_string()
+        } else {
+            "Showing all branches".to_string()
+        });
+    }
+
+    fn toggle_date_headers(&mut self) {
+        self.date_headers = !self.date_headers;
+        self.status_message = Some(match (self.date_headers, self.sort_mode) {
+            (false, _) => "Date headers off".to_string(),
+            (true, SortMode::Recent) => "Date headers on".to_string(),
+            (true, mode) => format!(
+                "Date headers on, shown when sorting by recent (now by {})",
+                mode.label()
+            ),
+        });
+    }
+
+    /// Whether the list is ordered by match quality rather than the sort mode
+    fn ranked_by_filter(&self) -> bool {
+        let filter = Filter::parse(&self.filter);
+        !filter.text.is_empty() && !filter.negate
+    }
+
+    fn toggle_tree_view(&mut self) {
+        self.tree_view = !self.tree_view;
+        self.update_filter();
+        self.status_message = Some(
+            if self.tree_view {
+                "Grouped by prefix, ← and → collapse and expand the groups"
             } else {
-                spans.push(Span::raw("  "));
+                "Tree view off"
             }
+            .to_string(),
+        );
+    }
 
-            // Branch name
-            let name_style = if is_selected {
-                Style::default()
-                    .fg(Color::White)
-                    .add_modifier(Modifier::BOLD)
-            } else if branch.is_current {
-                Style::default().fg(Color::Green)
-            } else {
-                Style::default().fg(Color::Gray)
-            };
+    /// Whether the list is laid out as a tree, which matches don't use, as
+    /// they're searched for in every group and ranked
+    fn shows_tree(&self) -> bool {
+        self.tree_view && !self.quick && !self.ranked_by_filter()
+    }
 
-            spans.push(Span::styled(branch.name.clone(), name_style));
+    /// The collapsed group that's selected in the tree view, which isn't a
+    /// branch to act on
+    fn selected_group(&self) -> Option<&Group> {
+        let selected = self.list_state.selected()?;
+        self.tree_rows.get(selected)?.collapsed.as_ref()
+    }
 
-            // Time ago (more subtle)
-            let time_ago = {
-                let now = Utc::now();
-                let duration = now.signed_duration_since(branch.last_commit_time);
+    /// `←`: collapses the group the selected row is in
+    fn collapse_group(&mut self) {
+        let Some(selected) = self.list_state.selected() else {
+            return;
+        };
+        let (Some(row), Some(&i)) = (
+            self.tree_rows.get(selected),
+            self.filtered_branches.get(selected),
+        ) else {
+            return;
+        };
+        if row.depth == 0 {
+            return;
+        }
+        let prefix = self.branches[i].name[..row.label_start].to_string();
+        self.expanded.remove(&prefix);
+        self.update_filter();
+    }
+
+    /// `→` or `Enter` on a collapsed group: opens it
+    fn expand_group(&mut self) {
+        if let Some(group) = self.selected_group() {
+            self.expanded.insert(group.prefix.clone());
+            self.update_filter();
+        }
+    }
+
+    fn selected_branch(&self) -> Option<&GitBranch> {
+        if self.selected_group().is_some() {
+            return None;
+        }
+        let selected = self.list_state.selected()?;
+        let &branch_idx = self.filtered_branches.get(selected)?;
+        self.branches.get(branch_idx)
+    }
+
+    /// Selects the branch called `name`, returning whether it's listed. In
+    /// the tree view, the groups it's in are opened for it.
+    fn select_branch(&mut self, name: &str) -> bool {
+        if self.shows_tree() && tree::prefixes(name).any(|prefix| !self.expanded.contains(prefix)) {
+            self.expanded
+                .extend(tree::prefixes(name).map(str::to_string));
+            self.update_filter();
+        }
+        let idx = self
+            .filtered_branches
+            .iter()
+            .position(|&i| self.branches[i].name == name);
+        if idx.is_some() {
+            self.list_state.select(idx);
+        }
+        idx.is_some()
+    }
+
+    /// Matches branch names against the filter text with `rank_matches`, so the
+    /// typed characters have to appear in order but not necessarily together.
+    /// The best matches come first, and the most recent commit first among
+    /// equally good ones. A leading `!` inverts the match, so `!release` shows
+    /// every branch that doesn't match "release", in the usual sort order. A
+    /// lone `!` is the same as no filter. Age bounds like `>7d` anywhere in the
+    /// filter narrow the list down by last commit, and `@name` by who wrote
+    /// the tip commit, see `Filter`.
+    ///
+    /// The highlighted branch stays highlighted if it still matches, so editing
+    /// the query doesn't lose your place; otherwise the first match is.
+    fn update_filter(&mut self) {
+        // A collapsed group's row goes by its first branch
+        let selected_name = self
+            .list_state
+            .selected()
+            .and_then(|selected| self.filtered_branches.get(selected))
+            .map(|&i| self.branches[i].name.clone());
+        let filter = Filter::parse(&self.filter);
+        let query = filter.text.as_str();
+        let now = Utc::now();
+
+        let candidates: Vec<usize> = if self.quick {
+            // The branch to toggle to comes first, so Enter switches straight away
+            [
+                self.previous_branch.as_deref(),
+                self.current_branch.as_deref(),
+            ]
+            .into_iter()
+            .flatten()
+            .filter_map(|name| {
+                self.branches
+                    .iter()
+                    .position(|b| b.ref_kind == RefKind::Local && b.name == name)
+            })
+            .collect()
+        } else {
+            // Hidden before the cap, so it doesn't cost one of the slots
+            let listable = self
+                .branches
+                .iter()
+                .enumerate()
+                .filter(|(_, branch)| self.is_listable(branch));
+            capped(listable, self.branch_limit())
+                .map(|(i, _)| i)
+                .collect()
+        };
+        let candidates: Vec<usize> = candidates
+            .into_iter()
+            .filter(|&i| {
+                let branch = &self.branches[i];
+                filter.allows_age(now - branch.last_commit_time)
+                    && filter.allows_author(branch.author.as_deref())
+                    && filter.allows_upstream(branch.upstream.is_some())
+                    && filter.allows_merged(branch.is_merged)
+            })
+            .collect();
+
+        let names: Vec<&str> = candidates
+            .iter()
+            .map(|&i| self.branches[i].name.as_str())
+            .collect();
+        let mut ranked: Vec<(usize, i64)> = rank_matches(&names, query)
+            .into_iter()
+            .map(|(position, score)| (candidates[position], score))
+            .collect();
+
+        self.filtered_branches = if query.is_empty() {
+            candidates
+        } else if filter.negate {
+            let matched: HashSet<usize> = ranked.into_iter().map(|(i, _)| i).collect();
+            candidates
+                .into_iter()
+                .filter(|i| !matched.contains(i))
+                .collect()
+        } else {
+            ranked.sort_by(|(a, a_score), (b, b_score)| {
+                b_score.cmp(a_score).then_with(|| {
+                    self.branches[*b]
+                        .last_commit_time
+                        .cmp(&self.branches[*a].last_commit_time)
+                })
+            });
+            ranked.into_iter().map(|(i, _)| i).collect()
+        };
+
+        self.tree_rows = Vec::new();
+        if self.shows_tree() {
+            let names: Vec<&str> = self
+                .filtered_branches
+                .iter()
+                .map(|&i| self.branches[i].name.as_str())
+                .collect();
+            let rows = tree_rows(&names, &self.expanded);
+            self.filtered_branches = rows
+                .iter()
+                .map(|row| self.filtered_branches[row.index])
+                .collect();
+            self.tree_rows = rows;
+        }
+
+        let selected = selected_name
+            .and_then(|name| {
+                self.filtered_branches
+                    .iter()
+                    .position(|&i| self.branches[i].name == name)
+                    // Or the collapsed group it's now in
+                    .or_else(|| {
+                        self.tree_rows.iter().position(|row| {
+                            row.collapsed
+                                .as_ref()
+                                .is_some_and(|group| name.starts_with(&group.prefix))
+                        })
+                    })
+            })
+            .or_else(|| self.default_selection());
+        self.list_state.select(selected);
+    }
+
+    /// The top of the list, skipping the current branch when it's pinned
+    /// there by `current_first`, since there's no switching to it
+    fn default_selection(&self) -> Option<usize> {
+        if self.filtered_branches.is_empty() {
+            return None;
+        }
+        let current_on_top = self.branches[self.filtered_branches[0]].is_current;
+        Some(
+            if self.config.current_first && current_on_top && self.filtered_branches.len() > 1 {
+                1
+            } else {
+                0
+            },
+        )
+    }
 
-                if duration.num_days() > 0 {
-                    format!(" ({}d)", duration.num_days())
-                } else if duration.num_hours() > 0 {
-                    format!(" ({}h)", duration.num_hours())
+    fn next(&mut self) {
+        if self.filtered_branches.is_empty() {
+            return;
+        }
+        let i = match self.list_state.selected() {
+            Some(i) => {
+                if i >= self.filtered_branches.len() - 1 {
+                    0
                 } else {
-                    format!(" ({}m)", duration.num_minutes().max(1))
+                    i + 1
                 }
-            };
+            }
+            None => 0,
+        };
+        self.list_state.select(Some(i));
+    }
+
+    fn previous(&mut self) {
+        if self.filtered_branches.is_empty() {
+            return;
+        }
+        let i = match self.list_state.selected() {
+            Some(i) => {
+                if i == 0 {
+                    self.filtered_branches.len() - 1
+                } else {
+                    i - 1
+                }
+            }
+            None => 0,
+        };
+        self.list_state.select(Some(i));
+    }
 
-            spans.push(Span::styled(time_ago, Style::default().fg(Color::DarkGray)));
+    /// The filtered branch drawn at `position`, if it's on one rather than on
+    /// a header or past the end of the list
+    fn branch_at(&self, position: Position) -> Option<usize> {
+        if !self.list_area.contains(position) {
+            return None;
+        }
+        let row = self.list_state.offset() + (position.y - self.list_area.y) as usize;
+        (0..self.filtered_branches.len()).find(|&idx| self.row_of(idx) == row)
+    }
 
-            ListItem::new(Line::from(spans))
-        })
-        .collect();
+    fn row_of(&self, idx: usize) -> usize {
+        self.list_rows.get(idx).copied().unwrap_or(idx)
+    }
 
-    // Clean list without borders
-    let list = List::new(items)
-        .highlight_style(Style::default()) // No background highlight
-        .highlight_symbol(""); // No symbol since we handle it manually
+    fn row_count(&self) -> usize {
+        self.filtered_branches
+            .len()
+            .checked_sub(1)
+            .map_or(0, |last| self.row_of(last) + 1)
+    }
 
-    f.render_stateful_widget(list, list_area, &mut app.list_state);
-}
+    /// Whether the list, scrolled to `offset`, shows `row` with the scroll
+    /// margin around it, so drawing won't scroll it back
+    fn row_in_view(&self, row: usize, offset: usize) -> bool {
+        let margin = self
+            .config
+            .scroll_margin
+            .min(self.list_height.saturating_sub(1) / 2);
+        let last = self.row_count().saturating_sub(1);
+        row.saturating_sub(margin) >= offset && (row + margin).min(last) < offset + self.list_height
+    }
 
-fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut app: App) -> Result<()> {
-    loop {
-        terminal.draw(|f| ui(f, &mut app))?;
-
-        if let Event::Key(key) = event::read()? {
-            if key.kind == KeyEventKind::Press {
-                match key.code {
-                    KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
-                    KeyCode::Down | KeyCode::Char('j') => app.next(),
-                    KeyCode::Up | KeyCode::Char('k') => app.previous(),
-                    KeyCode::Enter => {
-                        app.checkout_selected()?;
-                        return Ok(());
-                    }
-                    KeyCode::Backspace => app.remove_char(),
-                    KeyCode::Char(c) => app.add_char(c),
-                    _ => {}
+    /// Where the list starts once scrolled as little as it takes to show
+    /// `selected_row` with the scroll margin around it, the way ratatui's
+    /// `List` would scroll it
+    fn view_offset(&self, selected_row: Option<usize>) -> usize {
+        let last = self.row_count().saturating_sub(1);
+        let mut offset = self.list_state.offset().min(last);
+        if let Some(row) = selected_row {
+            let margin = self
+                .config
+                .scroll_margin
+                .min(self.list_height.saturating_sub(1) / 2);
+            offset = offset.min(row.saturating_sub(margin));
+            let bottom = (row + margin).min(last);
+            if bottom >= offset + self.list_height {
+                offset = bottom + 1 - self.list_height;
+            }
+        }
+        offset
+    }
+
+    /// Scrolls the list by `lines` rows, keeping the selection where it is
+    /// unless that scrolls out of view, like `Ctrl-e`/`Ctrl-y` in vim
+    fn scroll_lines(&mut self, lines: isize) {
+        let max_offset = self.row_count().saturating_sub(self.list_height);
+        let offset = self
+            .list_state
+            .offset()
+            .saturating_add_signed(lines)
+            .min(max_offset);
+        *self.list_state.offset_mut() = offset;
+
+        let Some(selected) = self.list_state.selected() else {
+            return;
+        };
+        if self.row_in_view(self.row_of(selected), offset) {
+            return;
+        }
+        let mut in_view = (0..self.filtered_branches.len())
+            .filter(|&idx| self.row_in_view(self.row_of(idx), offset));
+        let dragged = if lines > 0 {
+            in_view.next()
+        } else {
+            in_view.next_back()
+        };
+        if let Some(idx) = dragged {
+            self.list_state.select(Some(idx));
+        }
+    }
+
+    /// Moves the selection half a page and scrolls the list along with it, so
+    /// it stays at the same spot on screen, like `Ctrl-d`/`Ctrl-u` in vim
+    fn scroll_half_page(&mut self, down: bool) {
+        self.scroll_selection((self.list_height / 2).max(1), down);
+    }
+
+    /// Moves the selection a whole screenful, for `PageDown`/`PageUp`
+    fn scroll_page(&mut self, down: bool) {
+        self.scroll_selection(self.list_height.max(1), down);
+    }
+
+    /// Moves the selection `count` branches down or up, stopping at the ends,
+    /// and scrolls the list by as much
+    fn scroll_selection(&mut self, count: usize, down: bool) {
+        let Some(selected) = self.list_state.selected() else {
+            return;
+        };
+        let target = if down {
+            (selected + count).min(self.filtered_branches.len() - 1)
+        } else {
+            selected.saturating_sub(count)
+        };
+        let moved = self.row_of(target) as isize - self.row_of(selected) as isize;
+
+        let max_offset = self.row_count().saturating_sub(self.list_height);
+        *self.list_state.offset_mut() = self
+            .list_state
+            .offset()
+            .saturating_add_signed(moved)
+            .min(max_offset);
+        self.list_state.select(Some(target));
+    }
+
+    /// Checks out the selected entry the way its kind needs: remote branches
+    /// get a local tracking branch, tags are checked out detached and stashes
+    /// are applied to the working tree.
+    fn checkout_selected(&self) -> Result<Option<Outcome>> {
+        let Some(branch) = self.selected_branch().filter(|branch| !branch.is_current) else {
+            return Ok(None);
+        };
+
+        if branch.ref_kind == RefKind::Stash {
+            apply_stash(&mut open_repo()?, &branch.name)?;
+            return Ok(Some(Outcome::StashApplied(branch.name.clone())));
+        }
+        let name = checkout_ref(&open_repo()?, branch.ref_kind, &branch.name)?;
+        Ok(Some(Outcome::CheckedOut(name)))
+    }
+
+    /// With a pattern given on the command line, does what `Enter` would with
+    /// the branch it names: the only one it matches, or the one called
+    /// exactly that among several. Nothing when it names none.
+    fn pick_sole_match(&mut self) -> Result<Option<Outcome>> {
+        // `Enter` deletes the marked branches there, not the selected one
+        if self.cleanup {
+            return Ok(None);
+        }
+        let exact = self
+            .filtered_branches
+            .iter()
+            .position(|&i| self.branches[i].name == self.filter);
+        let Some(sole) = exact.or((self.filtered_branches.len() == 1).then_some(0)) else {
+            return Ok(None);
+        };
+        self.list_state.select(Some(sole));
+        activate(self)
+    }
+
+    /// In `--read-only` mode, says so instead of going ahead with a change.
+    /// Across repositories only checking out goes ahead.
+    fn refuses_changes(&mut self) -> bool {
+        if self.read_only {
+            self.status_message = Some("Read-only mode, nothing was changed".to_string());
+        } else if self.refuses_across_repos() {
+            return true;
+        }
+        self.read_only
+    }
+
+    /// With `--repos`, says that only checking out works, for the actions
+    /// that want the current repository
+    fn refuses_across_repos(&mut self) -> bool {
+        let refused = !self.checkouts_listed.is_empty();
+        if refused {
+            self.status_message = Some("Across repositories, gb only checks out".to_string());
+        }
+        refused
+    }
+
+    /// The keys that go with the selection, for the status line when there's
+    /// nothing else to say, as many as fit in `width`
+    fn key_hints(&self, width: usize) -> String {
+        let mut hints: Vec<String> = Vec::new();
+        if let Some(current) = &self.current_branch {
+            hints.push(format!("on {}", current));
+        } else if self
+            .branches
+            .iter()
+            .any(|branch| branch.ref_kind == RefKind::Head)
+        {
+            hints.push("on a detached HEAD".to_string());
+        }
+        let branch = self.selected_branch();
+        if self.filter_focused {
+            hints.extend(["enter pick", "esc stop filtering"].map(String::from));
+        } else if self.selected_group().is_some() {
+            hints.extend(["→ expand", "← collapse", "/ filter"].map(String::from));
+        } else if self.print {
+            hints.extend(["enter pick", "/ filter", "q give up"].map(String::from));
+        } else if self.read_only {
+            hints.extend(["S show", "L log", "/ filter", "q quit"].map(String::from));
+        } else if !self.checkouts_listed.is_empty() {
+            hints
+                .extend(["enter check out there", "L log", "/ filter", "q quit"].map(String::from));
+        } else if let Some(branch) = branch {
+            let enter = match branch.ref_kind {
+                _ if branch.is_current => None,
+                RefKind::Local => Some("enter check out"),
+                RefKind::Remote => Some("enter check out tracking it"),
+                RefKind::Tag => Some("enter check out detached"),
+                RefKind::Stash => Some("enter apply"),
+                RefKind::Head => None,
+            };
+            hints.extend(enter.map(String::from));
+            if branch.ref_kind == RefKind::Stash {
+                hints.extend(["p pop", "d drop", "D changes"].map(String::from));
+            }
+            if branch.ref_kind == RefKind::Local {
+                if !branch.is_current {
+                    hints.push("d delete".to_string());
                 }
+                hints.extend(["r rename", "u upstream"].map(String::from));
+            }
+            if !branch.is_current && branch.ref_kind != RefKind::Stash {
+                hints.push("Alt-r/Alt-m rebase/merge".to_string());
+            }
+            hints.extend(["/ filter", "q quit"].map(String::from));
+        } else {
+            hints.extend(["/ filter", "q quit"].map(String::from));
+        }
+
+        let mut line = String::new();
+        for hint in hints {
+            let separator = if line.is_empty() { "" } else { " · " };
+            if line.chars().count() + separator.chars().count() + hint.chars().count() > width {
+                break;
             }
+            line.push_str(separator);
+            line.push_str(&hint);
         }
+        line
     }
-}
 
-fn main() -> Result<()> {
-    enable_raw_mode()?;
-    let mut stdout = io::stdout();
-    execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
-    let backend = CrosstermBackend::new(stdout);
-    let mut terminal = Terminal::new(backend)?;
+    /// What to say when the filter matches no branch
+    fn no_match_hint(&self) -> String {
+        if self.offers_create() {
+            format!("No branch matches · Enter creates {}", self.filter)
+        } else {
+            "No branch matches".to_string()
+        }
+    }
 
-    let app = App::new()?;
-    let res = run_app(&mut terminal, app);
+    /// Whether a filter that matches nothing can become a new branch
+    fn offers_create(&self) -> bool {
+        !(self.read_only
+            || self.cleanup
+            || self.quick
+            || self.print
+            || !self.checkouts_listed.is_empty())
+    }
 
-    disable_raw_mode()?;
-    execute!(
-        terminal.backend_mut(),
-        LeaveAlternateScreen,
-        DisableMouseCapture
-    )?;
-    terminal.show_cursor()?;
+    /// Creates a branch off HEAD named exactly what's in the filter, and
+    /// switches to it
+    fn create_from_filter(&mut self) -> Result<Option<Outcome>> {
+        if self.refuses_changes() {
+            return Ok(None);
+        }
+        let name = self.filter.clone();
+        if name.is_empty() {
+            self.status_message = Some("Type a name for the new branch first".to_string());
+            return Ok(None);
+        }
+        validate_branch_name(&name)?;
+        if open_repo()?.find_branch(&name, BranchType::Local).is_ok() {
+            self.status_message = Some(format!("{} already exists", name));
+            return Ok(None);
+        }
 
-    if let Err(err) = res {
-        println!("{:?}", err);
+        create_branch(&open_repo()?, &name)?;
+        Ok(Some(Outcome::CheckedOut(name)))
     }
 
-    Ok(())
+    /// Copies the selected name, to paste into a pull request or a chat
+    fn copy_name(&mut self) {
+        let Some(branch) = self.selected_branch() else {
+            return;
+        };
+        match clipboard::copy(&branch.name) {
+            Ok(()) => self.flash(format!("Copied {}", branch.name)),
+            Err(err) => self.status_message = Some(format!("Failed to copy: {:#}", err)),
+        }
+    }
+
+    /// Copies the command that checks out the selection, ready to paste
+    fn copy_checkout_command(&mut self) {
+        if self.refuses_across_repos() {
+            return;
+        }
+        let Some(branch) = self.selected_branch() else {
+            return;
+        };
+
+        let command = checkout_args(branch.ref_kind, &branch.name).map(|args| {
+            std::iter::once("git")
+                .chain(args.iter().map(String::as_str))
+                .map(shell_quote)
+                .collect::<Vec<_>>()
+                .join(" ")
+        });
+        match command.and_then(|command| clipboard::copy(&command).map(|()| command)) {
+            Ok(command) => self.flash(format!("Copied {}", command)),
+            Err(err) => self.status_message = Some(format!("Failed to copy: {:#}", err)),
+        }
+    }
+
+    /// Copies the hash of the selected branch's tip commit, abbreviated the
+    /// way git abbreviates it unless `full`
+    fn copy_tip_hash(&mut self, full: bool) -> Result<()> {
+        let Some(branch) = self.selected_branch() else {
+            return Ok(());
+        };
+        let Some(tip) = branch.tip else {
+            self.status_message = Some(format!("{} has no commits", branch.name));
+            return Ok(());
+        };
+
+        let hash = if full {
+            tip.to_string()
+        } else {
+            let repo = self.repo_of(branch)?;
+            let short_id = repo.find_object(tip, None)?.short_id()?;
+            short_id.as_str().unwrap_or_default().to_string()
+        };
+        match clipboard::copy(&hash) {
+            Ok(()) => self.flash(format!("Copied {} ({})", hash, branch.name)),
+            Err(err) => self.status_message = Some(format!("Failed to copy: {:#}", err)),
+        }
+        Ok(())
+    }
+
+    /// Shows `message` in the status line for [`FLASH_DURATION`]
+    fn flash(&mut self, message: String) {
+        self.status_message = Some(message.clone());
+        self.flashed = Some((message, Instant::now()));
+    }
+
+    /// Clears a flashed message once its time is up, unless something else
+    /// has been said since
+    fn expire_flash(&mut self, now: Instant) {
+        let Some((message, since)) = &self.flashed else {
+            return;
+        };
+        if now.duration_since(*since) < FLASH_DURATION {
+            return;
+        }
+        if self.status_message.as_ref() == Some(message) {
+            self.status_message = None;
+        }
+        self.flashed = None;
+    }
+
+    /// Asks before deleting the selected branch, unless it's one that can't be
+    fn start_delete(&mut self) -> Result<()> {
+        if self.refuses_changes() {
+            return Ok(());
+        }
+        let Some(branch) = self.selected_branch() else {
+            return Ok(());
+        };
+        if branch.ref_kind == RefKind::Stash {
+            self.prompt = Some(Prompt::DropStash);
+            return Ok(());
+        }
+        let refusal = if branch.ref_kind != RefKind::Local {
+            Some(format!(
+                "Only local branches can be deleted, {} is a {}",
+                branch.name,
+                branch.ref_kind.badge()
+            ))
+        } else if branch.is_current {
+            Some(format!("Can't delete {}, it's checked out", branch.name))
+        } else if self.guards(branch) {
+            Some(format!("{} is protected", branch.name))
+        } else {
+            None
+        };
+        if let Some(message) = refusal {
+            self.status_message = Some(message);
+            return Ok(());
+        }
+
+        // Unmerged commits would be lost, so that takes asking twice
+        let repo = open_repo()?;
+        let merged = branch
+            .tip
+            .is_some_and(|tip| merged_into_head_or(&repo, tip, self.base_branch.as_deref()));
+        if merged && !self.config.confirm_merged_deletes {
+            return self.delete_selected(false);
+        }
+        self.prompt = Some(if merged {
+            Prompt::DeleteSelected
+        } else {
+            Prompt::DeleteUnmerged
+        });
+        Ok(())
+    }
+
+    /// Deletes the selected branch, if it's merged or `force`d, and lists the
+    /// rest in its place
+    fn delete_selected(&mut self, force: bool) -> Result<()> {
+        let (Some(selected), Some(branch)) = (self.list_state.selected(), self.selected_branch())
+        else {
+            return Ok(());
+        };
+        let name = branch.name.clone();
+        let tip = delete_branch(&open_repo()?, &name, self.base_branch.as_deref(), force)?;
+
+        self.refresh()?;
+        self.marked.remove(&name);
+        if !self.filtered_branches.is_empty() {
+            self.list_state
+                .select(Some(selected.min(self.filtered_branches.len() - 1)));
+        }
+        // The hash is what it takes to bring the branch back
+        self.status_message = Some(format!("Deleted {} (was {})", name, tip));
+        Ok(())
+    }
+
+    /// Drops the selected stash, like `git stash drop`, and lists the rest
+    /// in its place
+    fn drop_selected_stash(&mut self) -> Result<()> {
+        let (Some(selected), Some(branch)) = (self.list_state.selected(), self.selected_branch())
+        else {
+            return Ok(());
+        };
+        let name = branch.name.clone();
+        let Some(tip) = branch.tip else {
+            return Ok(());
+        };
+        let mut repo = open_repo()?;
+        let short_id = repo.find_object(tip, None)?.short_id()?;
+        let tip = short_id.as_str().unwrap_or_default().to_string();
+        repo.stash_drop(stash_index(&name)?)
+            .map_err(|err| anyhow!("Failed to drop {}: {}", name, err.message()))?;
+
+        self.refresh()?;
+        if !self.filtered_branches.is_empty() {
+            self.list_state
+                .select(Some(selected.min(self.filtered_branches.len() - 1)));
+        }
+        // The hash is what it takes to bring the stash back
+        self.status_message = Some(format!("Dropped {} (was {})", name, tip));
+        Ok(())
+    }
+
+    /// Applies the selected stash and drops it, like `git stash pop`. One
+    /// that doesn't apply cleanly is kept.
+    fn pop_selected_stash(&mut self) -> Result<Option<Outcome>> {
+        if self.refuses_changes() {
+            return Ok(None);
+        }
+        let Some(branch) = self.selected_branch() else {
+            return Ok(None);
+        };
+        if branch.ref_kind != RefKind::Stash {
+            return Ok(None);
+        }
+        let name = branch.name.clone();
+        let mut repo = open_repo()?;
+        apply_stash(&mut repo, &name)?;
+        repo.stash_drop(stash_index(&name)?)
+            .map_err(|err| anyhow!("Applied {}, but failed to drop it: {}", name, err.message()))?;
+        Ok(Some(Outcome::StashPopped(name)))
+    }
+
+    /// Queues `git show` of the selected branch's tip, to be run in the pager
+    fn show_selected(&mut self) {
+        if self.refuses_across_repos() {
+            return;
+        }
+        let Some(branch) = self.selected_branch() else {
+            return;
+        };
+
+        match branch.tip {
+            Some(tip) => self.pending_git = Some(vec!["show".to_string(), tip.to_string()]),
+            None => self.status_message = Some(format!("{} has no commits", branch.name)),
+        }
+    }
+
+    /// Asks before rebasing the current branch onto the selected one, or
+    /// merging the selected one into it
+    fn start_integration(&mut self, integration: Integration) {
+        if self.refuses_changes() {
+            return;
+        }
+        let Some(branch) = self.selected_branch() else {
+            return;
+        };
+        if branch.ref_kind == RefKind::Stash {
+            self.status_message = Some(format!(
+                "{} is a stash, only branches and tags can be brought in",
+                branch.name
+            ));
+        } else if branch.is_current {
+            self.status_message = Some(format!(
+                "{} is the current branch, pick another to {}",
+                branch.name,
+                integration.verb()
+            ));
+        } else {
+            self.prompt = Some(Prompt::Integrate(integration));
+        }
+    }
+
+    /// Queues the rebase or merge the prompt asked about
+    fn integrate(&mut self, integration: Integration) {
+        if let Some(branch) = self.selected_branch() {
+            self.pending_git = Some(vec![integration.verb().to_string(), branch.name.clone()]);
+        }
+    }
+
+    /// Reports how a git command run with the terminal handed over went. A
+    /// rebase or merge moves the current branch, so the branches are read
+    /// again after one, and one that stopped says how to carry on.
+    fn finish_git(&mut self, args: &[String], result: Result<ExitStatus>) {
+        let Some(integration) = Integration::of(args) else {
+            match result {
+                Ok(status) if status.success() => {}
+                Ok(status) => {
+                    self.status_message = Some(format!("git {} exited with {}", args[0], status))
+                }
+                Err(err) => self.status_message = Some(format!("Failed to run git: {:#}", err)),
+            }
+            return;
+        };
+
+        if let Err(err) = self.refresh() {
+            self.status_message = Some(format!("Failed to refresh branches: {:#}", err));
+            return;
+        }
+        let target = &args[1];
+        let current = self.current_branch.as_deref().unwrap_or("HEAD");
+        let stopped = open_repo().is_ok_and(|repo| repo.state() != RepositoryState::Clean);
+        self.status_message = Some(match (integration, result) {
+            (_, Err(err)) => format!("Failed to run git: {:#}", err),
+            (Integration::Rebase, Ok(status)) if status.success() => {
+                format!("Rebased {} onto {}", current, target)
+            }
+            (Integration::Merge, Ok(status)) if status.success() => {
+                format!("Merged {} into {}", target, current)
+            }
+            (Integration::Rebase, Ok(_)) if stopped => format!(
+                "Rebasing onto {} stopped part way: git rebase --continue once it's sorted, or --abort",
+                target
+            ),
+            (Integration::Merge, Ok(_)) if stopped => format!(
+                "Merging {} stopped at conflicts: commit once they're resolved, or git merge --abort",
+                target
+            ),
+            (_, Ok(status)) => format!("git {} exited with {}", args[0], status),
+        });
+    }
+
+    /// Asks where to add a worktree for the selected branch, suggesting a
+    /// sibling of the current checkout or a spot in `worktree_dir`. If the
+    /// branch already has a worktree, there's nothing to add and we leave
+    /// with its path right away.
+    fn start_worktree(&mut self) -> Result<Option<Outcome>> {
+        if self.refuses_changes() {
+            return Ok(None);
+        }
+        let Some(branch) = self.selected_branch() else {
+            return Ok(None);
+        };
+        if branch.ref_kind != RefKind::Local {
+            self.status_message = Some(format!(
+                "Worktrees can only be added for local branches, {} is a {}",
+                branch.name,
+                branch.ref_kind.badge()
+            ));
+            return Ok(None);
+        }
+        let branch = branch.name.clone();
+
+        let repo = open_repo()?;
+        if let Some(path) = worktree_for_branch(&repo, &branch) {
+            return Ok(Some(Outcome::Worktree {
+                branch,
+                path: path.canonicalize().unwrap_or(path),
+                created: false,
+            }));
+        }
+
+        let workdir = repo.workdir().unwrap_or_else(|| repo.path());
+        let repo_name = self.repo_name.as_deref().unwrap_or("worktree");
+        let dir = match &self.config.worktree_dir {
+            Some(dir) => expand_home(dir),
+            None => workdir.parent().unwrap_or(workdir).to_path_buf(),
+        };
+        let suggestion = dir.join(format!("{}-{}", repo_name, branch.replace('/', "-")));
+
+        self.input = Some(Input {
+            kind: InputKind::WorktreePath { branch },
+            value: suggestion.display().to_string(),
+        });
+        Ok(None)
+    }
+
+    /// `u`: opens the menu of remote branches for the selected one to track,
+    /// starting on the one it tracks, or else the one of the same name
+    fn open_upstream_menu(&mut self) -> Result<()> {
+        if self.refuses_changes() {
+            return Ok(());
+        }
+        let Some(branch) = self.selected_branch() else {
+            return Ok(());
+        };
+        if branch.ref_kind != RefKind::Local {
+            self.status_message = Some(format!(
+                "Only local branches track an upstream, {} is a {}",
+                branch.name,
+                branch.ref_kind.badge()
+            ));
+            return Ok(());
+        }
+
+        let repo = open_repo()?;
+        let mut remotes = Vec::new();
+        for remote in repo.branches(Some(BranchType::Remote))? {
+            let (remote, _) = remote?;
+            // `origin/HEAD` only points at another of them
+            if let Some(name) = remote.name()?
+                && remote.get().symbolic_target().is_none()
+            {
+                remotes.push(name.to_string());
+            }
+        }
+        if remotes.is_empty() {
+            self.status_message = Some("There are no remote branches to track".to_string());
+            return Ok(());
+        }
+        remotes.sort();
+
+        let same_name = |remote: &String| {
+            remote
+                .split_once('/')
+                .is_some_and(|(_, name)| name == branch.name)
+        };
+        let highlighted = remotes
+            .iter()
+            .position(|remote| Some(remote) == branch.upstream.as_ref())
+            .or_else(|| remotes.iter().position(same_name))
+            .unwrap_or(0);
+        self.upstream_menu = Some(UpstreamMenu {
+            branch: branch.name.clone(),
+            current: branch.upstream.clone(),
+            remotes,
+            query: String::new(),
+            highlighted,
+        });
+        Ok(())
+    }
+
+    /// Makes `branch` track `upstream`, or nothing, and keeps it selected
+    fn finish_upstream(&mut self, branch: &str, upstream: Option<&str>) -> Result<()> {
+        self.upstream_menu = None;
+        set_upstream(&open_repo()?, branch, upstream)?;
+        self.refresh()?;
+        self.select_branch(branch);
+        self.status_message = Some(match upstream {
+            Some(upstream) => format!("{} now tracks {}", branch, upstream),
+            None => format!("{} no longer tracks an upstream", branch),
+        });
+        Ok(())
+    }
+
+    /// Asks for a new name for the selected branch, starting from its current one
+    fn start_rename(&mut self) {
+        if self.refuses_changes() {
+            return;
+        }
+        let Some(branch) = self.selected_branch() else {
+            return;
+        };
+        if branch.ref_kind != RefKind::Local {
+            self.status_message = Some(format!(
+                "Only local branches can be renamed, {} is a {}",
+                branch.name,
+                branch.ref_kind.badge()
+            ));
+            return;
+        }
+        if self.guards(branch) {
+            self.status_message =
+                Some(format!("{} is protected and can't be renamed", branch.name));
+            return;
+        }
+        self.input = Some(Input {
+            kind: InputKind::RenameBranch {
+                branch: branch.name.clone(),
+            },
+            value: branch.name.clone(),
+        });
+    }
+
+    /// Renames `branch` to `new`, along with what gb remembers about it, and
+    /// keeps it selected under its new name
+    fn finish_rename(&mut self, branch: &str, new: &str) -> Result<()> {
+        let new = new.trim();
+        if new == branch {
+            return Ok(());
+        }
+        validate_branch_name(new)?;
+        let repo = open_repo()?;
+        if repo.find_branch(new, BranchType::Local).is_ok() {
+            self.status_message = Some(format!("{} already exists", new));
+            return Ok(());
+        }
+        rename_branch(&repo, branch, new)?;
+
+        let mut state = State::load()?;
+        let repo_state = state.repo_mut(&repo);
+        repo_state.rename_branch(branch, new);
+        self.favorites = repo_state.favorites.clone();
+        state.save()?;
+        if self.marked.remove(branch) {
+            self.marked.insert(new.to_string());
+        }
+        if let Some(times) = self.checkouts.remove(branch) {
+            self.checkouts.insert(new.to_string(), times);
+        }
+
+        self.refresh()?;
+        self.select_branch(new);
+        self.status_message = Some(format!("Renamed {} to {}", branch, new));
+        Ok(())
+    }
+
+    fn submit_input(&mut self, input: Input) -> Result<Option<Outcome>> {
+        match input.kind {
+            InputKind::RenameBranch { branch } => {
+                self.finish_rename(&branch, &input.value)?;
+                Ok(None)
+            }
+            InputKind::WorktreePath { branch } => {
+                let path = expand_home(input.value.trim());
+                let output = Command::new("git")
+                    .arg("worktree")
+                    .arg("add")
+                    .arg(&path)
+                    .arg(&branch)
+                    .output()?;
+
+                if !output.status.success() {
+                    self.status_message = Some(format!(
+                        "Failed to add worktree: {}",
+                        String::from_utf8_lossy(&output.stderr).trim()
+                    ));
+                    return Ok(None);
+                }
+
+                Ok(Some(Outcome::Worktree {
+                    branch,
+                    path: path.canonicalize().unwrap_or(path),
+                    created: true,
+                }))
+            }
+        }
+    }
+
+    /// Marks the selected branch for deleting in bulk, or unmarks it. Only
+    /// local branches other than the checked out one can be marked.
+    fn toggle_mark(&mut self) {
+        let Some(branch) = self.selected_branch() else {
+            return;
+        };
+        let name = branch.name.clone();
+
+        if branch.ref_kind != RefKind::Local {
+            self.status_message = Some("Only local branches can be marked".to_string());
+        } else if branch.is_current {
+            self.status_message = Some(format!("{} is checked out and can't be deleted", name));
+        } else if self.guards(branch) {
+            self.status_message = Some(format!("{} is protected and can't be deleted", name));
+        } else if !self.marked.remove(&name) {
+            self.marked.insert(name);
+        }
+    }
+
+    /// Marks every listed branch that isn't protected, the way cleanup mode
+    /// starts out
+    fn mark_for_cleanup(&mut self) {
+        self.marked = self
+            .branches
+            .iter()
+            .filter(|branch| !self.guards(branch))
+            .map(|branch| branch.name.clone())
+            .collect();
+    }
+
+    /// Switches the list to cleanup mode, with every merged branch marked for
+    /// `enter` to delete, or back to the list it came from
+    fn toggle_prune_merged(&mut self) -> Result<()> {
+        if self.refuses_across_repos() {
+            return Ok(());
+        }
+        if let Some(source) = self.pruning.take() {
+            self.cleanup = false;
+            self.marked.clear();
+            self.source = source;
+            self.refresh()?;
+            return Ok(());
+        }
+        // Started with `--cleanup`, there's no other list to go back to
+        if self.cleanup {
+            return Ok(());
+        }
+
+        // Cleanup only ever deletes local branches
+        self.pruning = Some(self.source);
+        self.source = ListSource::Local;
+        self.cleanup = true;
+        self.refresh()?;
+        self.mark_for_cleanup();
+        if !self.marked.is_empty() {
+            self.status_message = Some(format!(
+                "{} merged branches marked · enter deletes them · M goes back",
+                self.marked.len()
+            ));
+        }
+        Ok(())
+    }
+
+    /// `s`: lists only the stashes, to apply, pop or drop them, or goes back
+    /// to the list it came from
+    fn toggle_stashes(&mut self) -> Result<()> {
+        if self.refuses_across_repos() {
+            return Ok(());
+        }
+        if self.source == ListSource::Stashes {
+            let source = self.stashes_from.take().unwrap_or(ListSource::Local);
+            return self.switch_source(source);
+        }
+        if self.cleanup || self.quick {
+            self.status_message = Some(format!(
+                "{} mode only lists local branches",
+                if self.cleanup { "Cleanup" } else { "Quick" }
+            ));
+            return Ok(());
+        }
+        self.stashes_from = Some(self.source);
+        self.switch_source(ListSource::Stashes)?;
+        self.status_message = Some(match self.filtered_branches.len() {
+            0 => "No stashes · s goes back".to_string(),
+            count => format!(
+                "{} stash{} · enter applies · p pops · d drops · D shows the changes · s goes back",
+                count,
+                if count == 1 { "" } else { "es" }
+            ),
+        });
+        Ok(())
+    }
+
+    /// Opens the summary of what deleting the marked branches would do, to
+    /// confirm from
+    fn start_delete_marked(&mut self) {
+        if self.marked.is_empty() {
+            self.status_message = Some(if self.cleanup {
+                "No branches marked for deletion".to_string()
+            } else {
+                "Mark branches to delete with space first".to_string()
+            });
+            return;
+        }
+        self.prompt = Some(Prompt::DeleteMarked);
+        self.summary_scroll = 0;
+    }
+
+    /// How many local branches were last committed to today, this week, this
+    /// month and before that, and how many of them are merged into the base
+    /// branch and how many aren't (the base branch itself left out)
+    fn branch_stats(&self, now: DateTime<Utc>) -> BranchStats {
+        let mut stats = BranchStats::default();
+        for branch in &self.branches {
+            if branch.ref_kind != RefKind::Local
+                || branch.is_unborn
+                || branch.not_a_commit
+                || self.is_pending(branch)
+            {
+                continue;
+            }
+            let age = now - branch.last_commit_time;
+            let bucket = if age < chrono::Duration::days(1) {
+                0
+            } else if age < chrono::Duration::weeks(1) {
+                1
+            } else if age < chrono::Duration::days(30) {
+                2
+            } else {
+                3
+            };
+            stats.ages[bucket] += 1;
+
+            if self.base_branch.as_deref() == Some(branch.name.as_str()) {
+                continue;
+            }
+            if branch.is_merged {
+                stats.merged += 1;
+            } else {
+                stats.unmerged += 1;
+            }
+        }
+        stats
+    }
+
+    /// The branches deleting the marked ones would delete, and the ones it
+    /// wouldn't with why, for a last look before deleting. Outside cleanup
+    /// mode, the unmarked branches aren't worth listing.
+    fn deletion_summary(&self) -> (Vec<&str>, Vec<(&str, &str)>) {
+        let mut deleted = Vec::new();
+        let mut skipped = Vec::new();
+        for branch in &self.branches {
+            if branch.ref_kind != RefKind::Local {
+                continue;
+            }
+            if self.guards(branch) {
+                if self.cleanup || self.marked.contains(&branch.name) {
+                    skipped.push((branch.name.as_str(), "protected"));
+                }
+            } else if self.marked.contains(&branch.name) {
+                deleted.push(branch.name.as_str());
+            } else if self.cleanup {
+                skipped.push((branch.name.as_str(), "not marked"));
+            }
+        }
+        skipped.extend(
+            self.cleanup_skipped
+                .iter()
+                .map(|(name, reason)| (name.as_str(), *reason)),
+        );
+        (deleted, skipped)
+    }
+
+    /// Whether some marked branch has commits the base branch doesn't
+    fn marked_unmerged(&self) -> bool {
+        self.branches.iter().any(|branch| {
+            branch.ref_kind == RefKind::Local
+                && !branch.is_merged
+                && self.marked.contains(&branch.name)
+        })
+    }
+
+    /// Deletes the marked branches, or with `merged_only` just the ones
+    /// merged into the base branch, keeping the rest marked. Cleanup mode
+    /// is done then; the regular list stays open, saying what happened, and
+    /// so does the list `M` came from.
+    fn delete_marked(&mut self, merged_only: bool) -> Result<Option<Outcome>> {
+        if self.refuses_changes() {
+            return Ok(None);
+        }
+        let repo = open_repo()?;
+        let mut deleted = Vec::new();
+        let mut failed = Vec::new();
+
+        for branch in self.branches.iter().filter(|b| {
+            b.ref_kind == RefKind::Local
+                && self.marked.contains(&b.name)
+                && (b.is_merged || !merged_only)
+        }) {
+            if self.guards(branch) {
+                failed.push(format!("{} (protected)", branch.name));
+                continue;
+            }
+            let base = self.base_branch.as_deref();
+            match delete_branch(&repo, &branch.name, base, !merged_only) {
+                Ok(_) => deleted.push(branch.name.clone()),
+                Err(err) => failed.push(format!("{} ({:#})", branch.name, err)),
+            }
+        }
+
+        if failed.is_empty() && self.cleanup && self.pruning.is_none() {
+            return Ok(Some(Outcome::Deleted(deleted)));
+        }
+        if let Some(source) = self.pruning.take() {
+            self.cleanup = false;
+            self.source = source;
+        }
+
+        self.refresh()?;
+        let names: HashSet<&str> = self.branches.iter().map(|b| b.name.as_str()).collect();
+        self.marked.retain(|name| names.contains(name.as_str()));
+        self.status_message = Some(if failed.is_empty() {
+            match self.marked.len() {
+                0 => format!("Deleted {} {}", deleted.len(), branches(deleted.len())),
+                kept => format!(
+                    "Deleted {} {}, kept the {} unmerged {} marked",
+                    deleted.len(),
+                    branches(deleted.len()),
+                    kept,
+                    if kept == 1 { "one" } else { "ones" }
+                ),
+            }
+        } else {
+            format!(
+                "Deleted {} {}, failed to delete {}",
+                deleted.len(),
+                branches(deleted.len()),
+                failed.join(", ")
+            )
+        });
+        Ok(None)
+    }
+
+    fn start_fetch(&mut self) {
+        // Fetching moves the remote-tracking branches
+        if self.fetch_task.is_some() || self.refuses_changes() {
+            return;
+        }
+
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            // Never let git prompt for credentials, the terminal is in raw mode.
+            // Pruning drops the remote branches deleted there, so the local
+            // ones they tracked show up as gone
+            let output = Command::new("git")
+                .args(["fetch", "--all", "--prune"])
+                .env("GIT_TERMINAL_PROMPT", "0")
+                .stdin(Stdio::null())
+                .output();
+            let _ = tx.send(output);
+        });
+
+        self.fetch_task = Some(rx);
+        self.status_message = None;
+    }
+
+    /// Asks the forge for the open pull requests on another thread, as the
+    /// network can take a while, when one is configured
+    fn start_pull_requests(&mut self, repo: &Repository) {
+        let Some(forge) = self.config.forge else {
+            return;
+        };
+        if self.pull_request_task.is_some() {
+            return;
+        }
+
+        let dir = repo.workdir().unwrap_or_else(|| repo.path()).to_path_buf();
+        let token = self.config.forge_token.clone();
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let _ = tx.send(forge::pull_requests(forge, &dir, token.as_deref()));
+        });
+        self.pull_request_task = Some(rx);
+    }
+
+    fn tick(&mut self) {
+        self.check_fetch();
+        self.check_ref_changes();
+        self.check_divergence();
+        self.check_details();
+        self.check_pull_requests();
+        self.expire_flash(Instant::now());
+    }
+
+    /// Shows the pull requests once they arrive, and keeps them for next time
+    fn check_pull_requests(&mut self) {
+        let Some(rx) = &self.pull_request_task else {
+            return;
+        };
+        let result = match rx.try_recv() {
+            Ok(result) => result,
+            Err(TryRecvError::Empty) => return,
+            Err(TryRecvError::Disconnected) => Err(anyhow!("the request was interrupted")),
+        };
+        self.pull_request_task = None;
+
+        let saved = result.and_then(|pull_requests| {
+            self.pull_requests = pull_requests;
+            let repo = open_repo()?;
+            let mut state = State::load()?;
+            state.repo_mut(&repo).pull_requests = self.pull_requests.clone();
+            state.save()
+        });
+        if let Err(err) = saved {
+            self.status_message = Some(format!("Pull requests not updated: {:#}", err));
+        }
+    }
+
+    /// The open pull request from `branch`, or from the remote branch it
+    /// tracks, which is the one the forge knows by name
+    fn pull_request(&self, branch: &GitBranch) -> Option<&PullRequest> {
+        let remote_branch = |name: &str| name.split_once('/').map(|(_, name)| name.to_string());
+        let head = match branch.ref_kind {
+            RefKind::Local => match &branch.upstream {
+                Some(upstream) => remote_branch(upstream)?,
+                None => branch.name.clone(),
+            },
+            RefKind::Remote => remote_branch(&branch.name)?,
+            _ => return None,
+        };
+        self.pull_requests.get(&head)
+    }
+
+    /// Fills in the ahead/behind counts that arrived since the last tick
+    fn check_divergence(&mut self) {
+        let Some(rx) = &self.divergence_task else {
+            return;
+        };
+        let mut arrived = false;
+        loop {
+            match rx.try_recv() {
+                Ok((name, tip, counts)) => {
+                    arrived = true;
+                    // Only while the branch is where it was when counted
+                    if let Some(branch) = self.branches.iter_mut().find(|branch| {
+                        branch.ref_kind == RefKind::Local
+                            && branch.name == name
+                            && branch.tip == Some(tip)
+                    }) {
+                        branch.ahead_behind = Some(counts);
+                    }
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.divergence_task = None;
+                    break;
+                }
+            }
+        }
+
+        // Being ahead is what `U` lists by
+        if arrived && self.unpushed_only {
+            let selected = self.selected_branch().map(|branch| branch.name.clone());
+            self.list_state.select(None);
+            self.update_filter();
+            if let Some(name) = selected {
+                self.select_branch(&name);
+            }
+        }
+    }
+
+    /// Fills in the branch details that arrived since the last tick, and
+    /// sorts the list again by the times among them
+    fn check_details(&mut self) {
+        let Some(rx) = &self.details_task else {
+            return;
+        };
+        let mut arrived = false;
+        loop {
+            match rx.try_recv() {
+                Ok(BranchDetail::Commit(name, tip, details)) => {
+                    arrived = true;
+                    self.pending_details.remove(&name);
+                    // Only while the branch is where it was when read
+                    if let Some(branch) = self.branches.iter_mut().find(|branch| {
+                        branch.ref_kind == RefKind::Local
+                            && branch.name == name
+                            && branch.tip == Some(tip)
+                    }) {
+                        details.apply(branch);
+                    }
+                }
+                Ok(BranchDetail::Merged(merged)) => {
+                    arrived = true;
+                    for branch in &mut self.branches {
+                        if branch.ref_kind == RefKind::Local {
+                            branch.is_merged = branch.tip.is_some_and(|tip| merged.contains(&tip));
+                        }
+                    }
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.details_task = None;
+                    self.pending_details.clear();
+                    break;
+                }
+            }
+        }
+        if !arrived {
+            return;
+        }
+
+        // The selection stays on its branch, except that starting on the
+        // first row means starting on the most recent branch, whichever that
+        // turns out to be
+        let selected = self
+            .list_state
+            .selected()
+            .filter(|&selected| {
+                selected > 0 || self.config.start_selection != StartSelection::Recent
+            })
+            .and_then(|_| self.selected_branch())
+            .map(|branch| branch.name.clone());
+        self.sort_branches();
+        self.list_state.select(None);
+        self.update_filter();
+        if let Some(name) = selected {
+            self.select_branch(&name);
+        }
+    }
+
+    /// Whether the branch is listed without its commit and reflog yet
+    fn is_pending(&self, branch: &GitBranch) -> bool {
+        branch.ref_kind == RefKind::Local && self.pending_details.contains(&branch.name)
+    }
+
+    /// The age shown in the list, none until the branch's commit is read
+    fn age_label(&self, branch: &GitBranch, now: DateTime<Utc>) -> String {
+        if self.is_pending(branch) {
+            String::new()
+        } else {
+            branch.age_label(now, self.sort_mode)
+        }
+    }
+
+    fn check_fetch(&mut self) {
+        let Some(rx) = &self.fetch_task else {
+            return;
+        };
+
+        match rx.try_recv() {
+            Ok(result) => {
+                self.fetch_task = None;
+                self.status_message = Some(match result {
+                    Ok(output) if output.status.success() => match self.refresh() {
+                        Ok(()) => match pruned_count(&String::from_utf8_lossy(&output.stderr)) {
+                            0 => "Fetched from remotes".to_string(),
+                            1 => "Fetched from remotes, 1 remote branch was deleted there"
+                                .to_string(),
+                            pruned => format!(
+                                "Fetched from remotes, {} remote branches were deleted there",
+                                pruned
+                            ),
+                        },
+                        Err(err) => format!("Failed to refresh branches: {:#}", err),
+                    },
+                    Ok(output) => format!(
+                        "Failed to fetch: {}",
+                        one_line(&String::from_utf8_lossy(&output.stderr))
+                    ),
+                    Err(err) => format!("Failed to run git fetch: {}", err),
+                });
+                // Pull requests may have been opened for what was fetched
+                if let Ok(repo) = open_repo() {
+                    self.start_pull_requests(&repo);
+                }
+            }
+            Err(TryRecvError::Empty) => {
+                self.spinner_frame = (self.spinner_frame + 1) % SPINNER_FRAMES.len();
+            }
+            Err(TryRecvError::Disconnected) => {
+                self.fetch_task = None;
+                self.status_message = Some("Fetch was interrupted".to_string());
+            }
+        }
+    }
+
+    /// Reloads the branches once for however many ref changes arrived since
+    /// the last tick, as git touches several files per operation
+    fn check_ref_changes(&mut self) {
+        let Some((_, rx)) = &self.ref_watcher else {
+            return;
+        };
+        if rx.try_iter().count() == 0 {
+            return;
+        }
+
+        if let Err(err) = self.refresh() {
+            self.status_message = Some(format!("Failed to refresh branches: {:#}", err));
+        }
+    }
+
+    fn refresh(&mut self) -> Result<()> {
+        let selected_name = self.selected_branch().map(|branch| branch.name.clone());
+
+        self.fetch_branches()?;
+        // The old indices point into the old list, so the selection is found by name
+        self.list_state.select(None);
+        self.update_filter();
+
+        if let Some(name) = selected_name {
+            self.select_branch(&name);
+        }
+        Ok(())
+    }
+
+    /// Reads the branches again on request, for changes made elsewhere
+    /// while the picker is open
+    fn reload(&mut self) -> Result<()> {
+        self.refresh()?;
+        self.status_message = Some("Branches reloaded".to_string());
+        Ok(())
+    }
+
+    fn open_source_menu(&mut self) {
+        if self.cleanup || self.quick {
+            self.status_message = Some(format!(
+                "{} mode only lists local branches",
+                if self.cleanup { "Cleanup" } else { "Quick" }
+            ));
+            return;
+        }
+        let current = ListSource::ALL
+            .iter()
+            .position(|&source| source == self.source);
+        self.source_menu = current;
+    }
+
+    /// Lists other refs, back where that list was left if it was shown
+    /// before, and otherwise keeping the selection if it's still there
+    fn switch_source(&mut self, source: ListSource) -> Result<()> {
+        self.source_menu = None;
+        if source == self.source {
+            return Ok(());
+        }
+        self.save_source_view();
+        self.source = source;
+        self.refresh()?;
+        self.restore_source_view();
+        self.status_message = Some(format!("Showing {}", source.label()));
+        Ok(())
+    }
+
+    fn save_source_view(&mut self) {
+        if let Some(branch) = self.selected_branch() {
+            let view = ListView {
+                selected: branch.name.clone(),
+                offset: self.list_state.offset(),
+            };
+            self.source_views.insert(self.source, view);
+        }
+    }
+
+    fn restore_source_view(&mut self) {
+        if let Some(view) = self.source_views.get(&self.source).cloned()
+            && self.select_branch(&view.selected)
+        {
+            *self.list_state.offset_mut() = view.offset;
+        }
+    }
+
+    fn add_char(&mut self, c: char) {
+        self.filter.push(c);
+        self.update_filter();
+        // The best match is what's being typed towards, so it's picked over
+        // whatever was selected before
+        if self.ranked_by_filter() && !self.filtered_branches.is_empty() {
+            self.list_state.select(Some(0));
+        }
+    }
+
+    fn remove_char(&mut self) {
+        self.filter.pop();
+        self.update_filter();
+    }
+}
+
+fn checkout_branch(name: &str) -> Result<()> {
+    checkout_ref(&open_repo()?, RefKind::Local, name).map(drop)
+}
+
+/// Creates `name` off HEAD and checks it out, like `git checkout -b`. On a
+/// repository without commits yet, HEAD just moves to the new name.
+fn create_branch(repo: &Repository, name: &str) -> Result<()> {
+    validate_branch_name(name)?;
+    require_work_tree(repo)?;
+    let refname = format!("refs/heads/{}", name);
+    let Some(commit) = repo.head().ok().and_then(|head| head.peel_to_commit().ok()) else {
+        repo.set_head(&refname)
+            .map_err(|err| checkout_failure(SwitchError::Git(err), "Failed to create branch"))?;
+        return Ok(());
+    };
+    let mut branch = repo
+        .branch(name, &commit, false)
+        .map_err(|err| anyhow!("Failed to create branch: {}", err.message()))?;
+    if let Err(err) = switch_to(repo, Some(&refname), &commit) {
+        let _ = branch.delete();
+        return Err(checkout_failure(err, "Failed to create branch"));
+    }
+    Ok(())
+}
+
+/// Applies the stash `name`, like `stash@{1}`, to the working tree and keeps
+/// it, like `git stash apply`. Local changes it would overwrite stop it before
+/// anything changes; changes it conflicts with are left for fixing.
+fn apply_stash(repo: &mut Repository, name: &str) -> Result<()> {
+    let index = stash_index(name)?;
+
+    let mut conflicts = Vec::new();
+    let applied = {
+        let mut checkout = git2::build::CheckoutBuilder::new();
+        checkout
+            .safe()
+            .notify_on(git2::CheckoutNotificationType::CONFLICT)
+            .notify(|_, path, _, _, _| {
+                conflicts.extend(path.map(Path::to_path_buf));
+                true
+            });
+        let mut options = git2::StashApplyOptions::new();
+        options.checkout_options(checkout);
+        repo.stash_apply(index, Some(&mut options))
+    };
+    match applied {
+        Err(_) if !conflicts.is_empty() => Err(SwitchError::Conflicts(conflicts)),
+        Err(err) => Err(SwitchError::Git(err)),
+        Ok(()) => Ok(()),
+    }
+    .map_err(|err| checkout_failure(err, "Failed to apply stash"))?;
+
+    if repo.index()?.has_conflicts() {
+        bail!(
+            "Applied {}, but it conflicts with your changes, fix the conflicts",
+            name
+        );
+    }
+    Ok(())
+}
+
+/// Where the stash `name`, like `stash@{1}`, is in the stash list
+fn stash_index(name: &str) -> Result<usize> {
+    name.strip_prefix("stash@{")
+        .and_then(|rest| rest.strip_suffix('}'))
+        .and_then(|index| index.parse().ok())
+        .ok_or_else(|| anyhow!("{} is not a stash", name))
+}
+
+/// Sets the upstream of the local branch `name` to the remote branch
+/// `upstream`, like `git branch --set-upstream-to`, by writing its
+/// `branch.<name>.remote` and `branch.<name>.merge` config, or unsets it
+fn set_upstream(repo: &Repository, name: &str, upstream: Option<&str>) -> Result<()> {
+    let mut branch = repo
+        .find_branch(name, BranchType::Local)
+        .map_err(|err| anyhow!("Failed to find {}: {}", name, err.message()))?;
+    branch
+        .set_upstream(upstream)
+        .map_err(|err| anyhow!("Failed to set the upstream of {}: {}", name, err.message()))
+}
+
+/// Renames the local branch `old` to `new` like `git branch -m`, which also
+/// moves its config, upstream included, and its reflog, and HEAD along with
+/// it when it's checked out
+fn rename_branch(repo: &Repository, old: &str, new: &str) -> Result<()> {
+    let mut branch = repo
+        .find_branch(old, BranchType::Local)
+        .map_err(|err| anyhow!("Failed to rename {}: {}", old, err.message()))?;
+    branch
+        .rename(new, false)
+        .map_err(|err| anyhow!("Failed to rename {}: {}", old, err.message()))?;
+    Ok(())
+}
+
+/// The git arguments that check out `name` of the given kind. A remote branch
+/// switches to the local branch tracking it, creating that if there isn't one
+/// yet; a tag is checked out on a detached HEAD and a stash is applied to the
+/// working tree. `Y` copies these, and they do what [`checkout_ref`] does.
+fn checkout_args(kind: RefKind, name: &str) -> Result<Vec<String>> {
+    // Stashes are named like `stash@{0}`, which isn't a valid ref name
+    if kind != RefKind::Stash {
+        validate_branch_name(name)?;
+    }
+
+    // The trailing `--` makes git treat `name` as a ref, never as a path
+    let args = match kind {
+        RefKind::Local => vec!["checkout".to_string(), name.to_string()],
+        RefKind::Remote => {
+            let repo = open_repo()?;
+            let local = local_name_for_remote(&repo, name)?;
+            if repo.find_branch(&local, BranchType::Local).is_ok() {
+                vec!["checkout".to_string(), local]
+            } else {
+                vec![
+                    "checkout".to_string(),
+                    "--track".to_string(),
+                    name.to_string(),
+                ]
+            }
+        }
+        RefKind::Tag => vec![
+            "checkout".to_string(),
+            "--detach".to_string(),
+            format!("refs/tags/{}", name),
+        ],
+        RefKind::Head => vec![
+            "checkout".to_string(),
+            "--detach".to_string(),
+            name.to_string(),
+        ],
+        RefKind::Stash => {
+            return Ok(vec![
+                "stash".to_string(),
+                "apply".to_string(),
+                name.to_string(),
+            ]);
+        }
+    };
+    Ok(args.into_iter().chain(["--".to_string()]).collect())
+}
+
+/// The local branch name for the remote branch `name`, e.g. `feature` for
+/// `origin/feature`
+fn local_name_for_remote(repo: &Repository, name: &str) -> Result<String> {
+    let remote = repo.branch_remote_name(&format!("refs/remotes/{}", name))?;
+    let remote = remote
+        .as_str()
+        .ok_or_else(|| anyhow!("Remote name for {} is not valid UTF-8", name))?;
+    Ok(name
+        .strip_prefix(remote)
+        .and_then(|rest| rest.strip_prefix('/'))
+        .unwrap_or(name)
+        .to_string())
+}
+
+/// Checks out `name` with the changes in `stash@{0}` stashed just before,
+/// putting them back where they were if the checkout doesn't happen
+fn checkout_over_stash(repo: &mut Repository, kind: RefKind, name: &str) -> Result<String> {
+    let head = |repo: &Repository| {
+        repo.head()
+            .ok()
+            .and_then(|head| head.name().map(str::to_string))
+    };
+    let before = head(repo);
+    match checkout_ref(repo, kind, name) {
+        Ok(checked_out) => Ok(checked_out),
+        Err(err) if head(repo) == before => {
+            repo.stash_pop(0, None)?;
+            Err(err)
+        }
+        // Checked out after all, only the hook failed
+        Err(err) => Err(err.context("Your changes are stashed as stash@{0}")),
+    }
+}
+
+/// Checks out `name` with the uncommitted changes merged into it, like `git
+/// checkout --merge`, when they touch files that differ between the
+/// branches. libgit2 only merges changes back in from a stash, so they go
+/// through one: a file both sides changed is left with conflict markers, and
+/// the note returned names them. Untracked files stay where they are. If
+/// the changes don't merge at all, they're left stashed and the note says so.
+fn merge_and_switch(
+    repo: &mut Repository,
+    kind: RefKind,
+    name: &str,
+) -> Result<(String, Option<String>)> {
+    let signature = repo.signature()?;
+    match repo.stash_save(&signature, &format!("gb: merging into {}", name), None) {
+        Ok(_) => {}
+        // Only untracked files, which checking out leaves alone anyway
+        Err(err) if err.code() == ErrorCode::NotFound => {
+            return Ok((checkout_ref(repo, kind, name)?, None));
+        }
+        Err(err) => bail!("Failed to stash the changes: {}", err.message()),
+    }
+    let checked_out = checkout_over_stash(repo, kind, name)?;
+
+    let merged = {
+        let mut checkout = git2::build::CheckoutBuilder::new();
+        checkout.allow_conflicts(true).conflict_style_merge(true);
+        let mut options = git2::StashApplyOptions::new();
+        options.checkout_options(checkout);
+        repo.stash_apply(0, Some(&mut options))
+            .map_err(SwitchError::Git)
+            .and_then(|()| match conflicted_paths(repo) {
+                Ok(paths) if paths.is_empty() => Ok(()),
+                Ok(paths) => Err(SwitchError::Conflicts(paths)),
+                Err(err) => Err(SwitchError::Git(err)),
+            })
+    };
+    let note = match merged {
+        Ok(()) => None,
+        Err(SwitchError::Conflicts(paths)) => Some(format!(
+            "Your changes conflict with {} in {}, fix the conflict markers there",
+            checked_out,
+            paths
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+        Err(err) => {
+            let note = format!(
+                "Your changes are still stashed as stash@{{0}}, they didn't merge into {}: {}",
+                checked_out,
+                checkout_failure(err, "Failed to merge them")
+            );
+            return Ok((checked_out, Some(note)));
+        }
+    };
+    repo.stash_drop(0)?;
+    Ok((checked_out, note))
+}
+
+/// The paths the index holds conflicts for, as a merge leaves them
+fn conflicted_paths(repo: &Repository) -> std::result::Result<Vec<PathBuf>, git2::Error> {
+    let mut paths = Vec::new();
+    for conflict in repo.index()?.conflicts()? {
+        let conflict = conflict?;
+        if let Some(entry) = conflict.our.or(conflict.their).or(conflict.ancestor) {
+            paths.push(PathBuf::from(
+                String::from_utf8_lossy(&entry.path).into_owned(),
+            ));
+        }
+    }
+    Ok(paths)
+}
+
+/// Stashes the uncommitted changes and untracked files, checks out `name`
+/// and, when `pop`, puts the changes back on top of it, as
+/// `git stash -u && git switch && git stash pop` would. If the checkout doesn't happen, the changes
+/// go back where they were. Returns the branch checked out and, when the
+/// changes are left in the stash, a note saying so.
+fn stash_and_switch(
+    repo: &mut Repository,
+    kind: RefKind,
+    name: &str,
+    pop: bool,
+) -> Result<(String, Option<String>)> {
+    let signature = repo.signature()?;
+    repo.stash_save(
+        &signature,
+        &format!("gb: before switching to {}", name),
+        Some(git2::StashFlags::INCLUDE_UNTRACKED),
+    )
+    .map_err(|err| anyhow!("Failed to stash the changes: {}", err.message()))?;
+
+    let checked_out = checkout_over_stash(repo, kind, name)?;
+    if !pop {
+        return Ok((
+            checked_out,
+            Some("Your changes are stashed as stash@{0}".to_string()),
+        ));
+    }
+    // Applied and dropped separately, as libgit2 drops the stash even when
+    // applying it left conflicts; `git stash pop` keeps it then
+    if let Err(err) = repo.stash_apply(0, None) {
+        let note = format!(
+            "Your changes are still stashed as stash@{{0}}, they didn't apply onto {}: {}",
+            checked_out,
+            err.message()
+        );
+        return Ok((checked_out, Some(note)));
+    }
+    if repo.index()?.has_conflicts() {
+        let note = format!(
+            "Your changes conflict with {}, fix the conflicts; they're also still stashed as stash@{{0}}",
+            checked_out
+        );
+        return Ok((checked_out, Some(note)));
+    }
+    repo.stash_drop(0)?;
+    Ok((checked_out, None))
+}
+
+/// Checks out `name` of the given kind with libgit2, the way [`checkout_args`]
+/// would with git, returning the branch (or tag) that ends up checked out. A
+/// remote branch switches to the local branch tracking it, creating that if
+/// there isn't one yet.
+fn checkout_ref(repo: &Repository, kind: RefKind, name: &str) -> Result<String> {
+    validate_branch_name(name)?;
+    require_work_tree(repo)?;
+
+    match kind {
+        RefKind::Local => {
+            let branch = repo
+                .find_branch(name, BranchType::Local)
+                .map_err(|err| anyhow!("Failed to checkout branch: {}", err.message()))?;
+            let commit = branch.get().peel_to_commit()?;
+            switch_to(repo, Some(&format!("refs/heads/{}", name)), &commit)
+                .map_err(|err| checkout_failure(err, "Failed to checkout branch"))?;
+            Ok(name.to_string())
+        }
+        RefKind::Remote => {
+            let local = local_name_for_remote(repo, name)?;
+            if repo.find_branch(&local, BranchType::Local).is_ok() {
+                return checkout_ref(repo, RefKind::Local, &local);
+            }
+            let commit = repo
+                .find_reference(&format!("refs/remotes/{}", name))?
+                .peel_to_commit()?;
+            let mut branch = repo.branch(&local, &commit, false)?;
+            branch.set_upstream(Some(name))?;
+            // Like `git checkout --track`, leave no branch behind when the
+            // checkout itself doesn't happen
+            if let Err(err) = switch_to(repo, Some(&format!("refs/heads/{}", local)), &commit) {
+                let _ = branch.delete();
+                return Err(checkout_failure(err, "Failed to checkout remote branch"));
+            }
+            Ok(local)
+        }
+        RefKind::Tag => {
+            let commit = repo
+                .find_reference(&format!("refs/tags/{}", name))?
+                .peel_to_commit()?;
+            switch_to(repo, None, &commit)
+                .map_err(|err| checkout_failure(err, "Failed to checkout tag"))?;
+            Ok(name.to_string())
+        }
+        RefKind::Head => {
+            let commit = repo.revparse_single(name)?.peel_to_commit()?;
+            switch_to(repo, None, &commit)
+                .map_err(|err| checkout_failure(err, "Failed to checkout commit"))?;
+            Ok(name.to_string())
+        }
+        RefKind::Stash => bail!("Stashes are applied, not checked out"),
+    }
+}
+
+/// Fails on a bare repository, which has nowhere to check anything out to
+fn require_work_tree(repo: &Repository) -> Result<()> {
+    if repo.is_bare() {
+        bail!(
+            "{} is a bare repository, there's no working tree to check out into",
+            repo.path().display()
+        );
+    }
+    Ok(())
+}
+
+/// Why a checkout failed, before it's told apart from a locked repository
+enum SwitchError {
+    Conflicts(Vec<PathBuf>),
+    Git(git2::Error),
+    Hook(String),
+}
+
+/// Moves the index, working tree and HEAD to `commit`, leaving HEAD on
+/// `refname` or, without one, detached. Local changes are carried over unless
+/// the switch would overwrite them, which stops it before anything changes,
+/// just like `git checkout`. Afterwards runs the `post-checkout` hook.
+fn switch_to(
+    repo: &Repository,
+    refname: Option<&str>,
+    commit: &git2::Commit,
+) -> std::result::Result<(), SwitchError> {
+    let previous = repo
+        .head()
+        .ok()
+        .and_then(|head| head.target())
+        .unwrap_or(Oid::zero());
+
+    // The tree goes first: checking out against the old HEAD is what tells
+    // files to update apart from local changes to keep
+    let mut conflicts = Vec::new();
+    let checked_out = {
+        let mut checkout = git2::build::CheckoutBuilder::new();
+        checkout
+            .safe()
+            .notify_on(git2::CheckoutNotificationType::CONFLICT)
+            .notify(|_, path, _, _, _| {
+                conflicts.extend(path.map(Path::to_path_buf));
+                true
+            });
+        repo.checkout_tree(commit.as_object(), Some(&mut checkout))
+    };
+    match checked_out {
+        Err(_) if !conflicts.is_empty() => return Err(SwitchError::Conflicts(conflicts)),
+        Err(err) => return Err(SwitchError::Git(err)),
+        Ok(()) => {}
+    }
+
+    match refname {
+        Some(refname) => repo.set_head(refname),
+        None => repo.set_head_detached(commit.id()),
+    }
+    .map_err(SwitchError::Git)?;
+
+    run_post_checkout_hook(repo, previous, commit.id()).map_err(SwitchError::Hook)
+}
+
+/// Runs the `post-checkout` hook, from `core.hooksPath` if that's set, the
+/// way git would after a branch checkout. Missing and non-executable hooks
+/// are skipped, as git skips them too.
+fn run_post_checkout_hook(
+    repo: &Repository,
+    previous: Oid,
+    new: Oid,
+) -> std::result::Result<(), String> {
+    let workdir = repo.workdir().unwrap_or(repo.path());
+    let hooks = match repo
+        .config()
+        .and_then(|config| config.get_path("core.hooksPath"))
+    {
+        // Relative to where hooks run, the top of the working tree
+        Ok(path) => workdir.join(path),
+        Err(_) => common_dir(repo).unwrap_or(repo.path()).join("hooks"),
+    };
+
+    // Captured, as anything printed would land in the middle of the TUI
+    let output = Command::new(hooks.join("post-checkout"))
+        .args([previous.to_string(), new.to_string(), "1".to_string()])
+        .current_dir(workdir)
+        .stdin(Stdio::null())
+        .output();
+    match output {
+        Ok(output) if output.status.success() => Ok(()),
+        Ok(output) => Err(format!(
+            "the post-checkout hook failed: {}",
+            one_line(&String::from_utf8_lossy(&output.stderr))
+        )),
+        Err(err)
+            if matches!(
+                err.kind(),
+                io::ErrorKind::NotFound | io::ErrorKind::PermissionDenied
+            ) =>
+        {
+            Ok(())
+        }
+        Err(err) => Err(format!("the post-checkout hook didn't run: {}", err)),
+    }
+}
+
+/// Words a failed [`switch_to`] like git would, or as [`RepoLocked`] when
+/// another git process holds the index
+fn checkout_failure(err: SwitchError, failure: &str) -> anyhow::Error {
+    match err {
+        SwitchError::Conflicts(paths) => anyhow!(
+            "{}: your local changes to {} would be overwritten, commit or stash them first",
+            failure,
+            paths
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        SwitchError::Git(err) if err.code() == ErrorCode::Locked => {
+            match locked_file(err.message()) {
+                Some(lock_file) => RepoLocked { lock_file }.into(),
+                None => anyhow!("{}: {}", failure, err.message()),
+            }
+        }
+        SwitchError::Git(err) => anyhow!("{}: {}", failure, err.message()),
+        // The checkout itself happened, but it's worth knowing
+        SwitchError::Hook(message) => anyhow!("Checked out, but {}", message),
+    }
+}
+
+/// The lock file in libgit2's "failed to create locked file '<path>.lock'"
+fn locked_file(message: &str) -> Option<PathBuf> {
+    let (_, rest) = message.split_once('\'')?;
+    let (path, _) = rest.split_once('\'')?;
+    path.ends_with(".lock").then(|| PathBuf::from(path))
+}
+
+/// Git failed because another git process holds a lock on the repository
+#[derive(Debug)]
+struct RepoLocked {
+    lock_file: PathBuf,
+}
+
+impl std::fmt::Display for RepoLocked {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "Repository is locked by another git process ({})",
+            self.lock_file.display()
+        )
+    }
+}
+
+impl std::error::Error for RepoLocked {}
+
+/// How many remote-tracking branches `git fetch --prune` deleted, from the
+/// ` - [deleted]  (none) -> origin/feat` lines it prints
+fn pruned_count(stderr: &str) -> usize {
+    stderr
+        .lines()
+        .filter(|line| line.trim_start().starts_with("- [deleted]"))
+        .count()
+}
+
+/// Git's multi-line messages joined into one, to fit the status line
+fn one_line(text: &str) -> String {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Checks `name` against git's ref-name rules (see `git check-ref-format`),
+/// so bad names get a clear message before git is ever invoked. This also
+/// keeps names starting with `-` from being read as options by `git`.
+fn validate_branch_name(name: &str) -> Result<()> {
+    let reason = if name.is_empty() {
+        Some("it is empty")
+    } else if name.starts_with('-') {
+        Some("it starts with '-'")
+    } else if name == "@" || name == "HEAD" {
+        Some("it is reserved")
+    } else if name.contains("..") {
+        Some("it contains '..'")
+    } else if name.contains("@{") {
+        Some("it contains '@{'")
+    } else if name.contains("//") || name.starts_with('/') || name.ends_with('/') {
+        Some("it has an empty path component")
+    } else if name.ends_with('.') {
+        Some("it ends with '.'")
+    } else if name.split('/').any(|part| part.starts_with('.')) {
+        Some("a path component starts with '.'")
+    } else if name.split('/').any(|part| part.ends_with(".lock")) {
+        Some("a path component ends with '.lock'")
+    } else if name.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        Some("it contains whitespace or control characters")
+    } else if name
+        .chars()
+        .any(|c| matches!(c, '~' | '^' | ':' | '?' | '*' | '[' | '\\'))
+    {
+        Some("it contains one of ~ ^ : ? * [ \\")
+    } else if !git2::Branch::name_is_valid(name).unwrap_or(false) {
+        // Whatever the rules above missed
+        Some("git doesn't allow it")
+    } else {
+        None
+    };
+
+    match reason {
+        Some(reason) => bail!("Invalid branch name '{}': {}", name, reason),
+        None => Ok(()),
+    }
+}
+
+/// Opens the repository the working directory is in, looking through the
+/// parent directories and honoring `GIT_DIR` and `GIT_CEILING_DIRECTORIES`
+/// the way git does, so gb works from anywhere inside it
+fn open_repo() -> Result<Repository> {
+    #[cfg(test)]
+    if let Some(dir) = tests::OPEN_REPO.with_borrow(Clone::clone) {
+        return Ok(Repository::open(dir)?);
+    }
+    Repository::open_from_env().map_err(|err| match err.code() {
+        ErrorCode::NotFound => anyhow!("not a git repository (or any of the parent directories)"),
+        _ => anyhow!("Failed to open the repository: {}", err.message()),
+    })
+}
+
+/// Names of the branches that have stashes, parsed from the stash messages git
+/// writes ("WIP on <branch>: ..." or "On <branch>: ...")
+fn stashed_branches(repo: &mut Repository) -> Result<HashSet<String>> {
+    let mut branches = HashSet::new();
+    repo.stash_foreach(|_, message, _| {
+        if let Some(rest) = message
+            .strip_prefix("WIP on ")
+            .or_else(|| message.strip_prefix("On "))
+            && let Some((branch, _)) = rest.split_once(':')
+        {
+            branches.insert(branch.to_string());
+        }
+        true
+    })?;
+    Ok(branches)
+}
+
+/// The git dir shared by all worktrees, where the refs and hooks live
+fn common_dir(repo: &Repository) -> Option<&Path> {
+    // A linked worktree's git dir is `<common dir>/worktrees/<name>`
+    if repo.is_worktree() {
+        repo.path().parent()?.parent()
+    } else {
+        Some(repo.path())
+    }
+}
+
+/// Starts watching `refs/`, `packed-refs` and `HEAD` of the current
+/// repository. The receiver gets a message for every change to them.
+fn watch_refs() -> Result<(RecommendedWatcher, Receiver<()>)> {
+    let repo = open_repo()?;
+    let git_dir = repo.path().to_path_buf();
+    let common_dir = common_dir(&repo)
+        .ok_or_else(|| anyhow!("Can't find the git directory"))?
+        .to_path_buf();
+
+    let refs_dir = common_dir.join("refs");
+    let is_ref_file = move |path: &Path| {
+        // Git writes `<ref>.lock` and renames it into place; the rename is
+        // enough to notice
+        path.extension().is_none_or(|ext| ext != "lock")
+            && (path.starts_with(&refs_dir)
+                || path
+                    .file_name()
+                    .is_some_and(|name| name == "packed-refs" || name == "HEAD"))
+    };
+
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        let Ok(event) = event else {
+            return;
+        };
+        let relevant = !matches!(event.kind, EventKind::Access(_))
+            && event.paths.iter().any(|path| is_ref_file(path));
+        if relevant {
+            let _ = tx.send(());
+        }
+    })?;
+
+    watcher.watch(&common_dir.join("refs"), RecursiveMode::Recursive)?;
+    // `packed-refs` and `HEAD` are replaced rather than written in place, so
+    // watch the directories holding them rather than the files themselves
+    watcher.watch(&common_dir, RecursiveMode::NonRecursive)?;
+    if git_dir != common_dir {
+        watcher.watch(&git_dir, RecursiveMode::NonRecursive)?;
+    }
+    Ok((watcher, rx))
+}
+
+/// The main working tree and every linked one, opened
+fn checkouts(repo: &Repository) -> Vec<Repository> {
+    let Some(main_repo) = common_dir(repo).and_then(|dir| Repository::open(dir).ok()) else {
+        return Vec::new();
+    };
+
+    let mut checkouts = vec![main_repo];
+    if let Ok(names) = checkouts[0].worktrees() {
+        for name in names.iter().flatten() {
+            if let Ok(worktree) = checkouts[0].find_worktree(name)
+                && let Ok(worktree_repo) = Repository::open_from_worktree(&worktree)
+            {
+                checkouts.push(worktree_repo);
+            }
+        }
+    }
+    checkouts
+}
+
+/// Where `branch` is checked out, in the main working tree or a linked one
+fn worktree_for_branch(repo: &Repository, branch: &str) -> Option<PathBuf> {
+    checkouts(repo).iter().find_map(|checkout| {
+        (head_branch(checkout).as_deref() == Some(branch))
+            .then(|| checkout.workdir().map(Path::to_path_buf))
+            .flatten()
+    })
+}
+
+/// The branches checked out in working trees other than `repo`'s own, and
+/// where, as git won't check a branch out twice
+fn other_worktrees(repo: &Repository) -> HashMap<String, PathBuf> {
+    let own = repo.workdir().and_then(|dir| dir.canonicalize().ok());
+    checkouts(repo)
+        .iter()
+        .filter_map(|checkout| {
+            let dir = checkout.workdir()?.canonicalize().ok()?;
+            (Some(&dir) != own.as_ref()).then_some((head_branch(checkout)?, dir))
+        })
+        .collect()
+}
+
+/// Runs a user's command through `sh` with inherited stdio, exiting like it
+/// did. `branch`, if any, is passed in `$GB_BRANCH`.
+fn run_shell(command: &str, branch: Option<&str>) -> Result<ExitCode> {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    if let Some(branch) = branch {
+        shell.env("GB_BRANCH", branch);
+    }
+
+    let status = shell.status()?;
+    Ok(match status.code() {
+        Some(code) => ExitCode::from(code as u8),
+        None => ExitCode::FAILURE,
+    })
+}
+
+/// Fills in a user's command template, quoting the branch name for the shell
+fn expand_template(template: &str, branch: &str) -> String {
+    template.replace("{branch}", &shell_quote(branch))
+}
+
+/// Quotes `arg` for a POSIX shell, if it needs it
+fn shell_quote(arg: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "-_./@+=:,%".contains(c);
+    if !arg.is_empty() && arg.chars().all(safe) {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), std::env::var_os("HOME")) {
+        (Some(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// The branch others get merged into: whatever `origin/HEAD` points at,
+/// falling back to a local `main` or `master`
+fn default_branch(repo: &Repository) -> Option<String> {
+    if let Ok(reference) = repo.find_reference("refs/remotes/origin/HEAD")
+        && let Some(target) = reference.symbolic_target()
+        && let Some(name) = target.strip_prefix("refs/remotes/origin/")
+        && repo.find_branch(name, BranchType::Local).is_ok()
+    {
+        return Some(name.to_string());
+    }
+
+    ["main", "master"]
+        .into_iter()
+        .find(|name| repo.find_branch(name, BranchType::Local).is_ok())
+        .map(|name| name.to_string())
+}
+
+/// Whether HEAD or the local branch `base` has every commit of `tip`, so
+/// deleting a branch at `tip` loses nothing
+fn merged_into_head_or(repo: &Repository, tip: Oid, base: Option<&str>) -> bool {
+    let contains_tip = |oid: Option<Oid>| {
+        oid.is_some_and(|oid| oid == tip || repo.graph_descendant_of(oid, tip).unwrap_or(false))
+    };
+    let head = repo.head().ok().and_then(|head| peeled_tip(&head));
+    let base_tip = base
+        .and_then(|base| repo.find_branch(base, BranchType::Local).ok())
+        .and_then(|base| peeled_tip(base.get()));
+    contains_tip(head) || contains_tip(base_tip)
+}
+
+/// Deletes the local branch `name` the way `git branch -d` does: only when
+/// HEAD or the base branch has its commits, so nothing is lost, unless
+/// `force`d like `git branch -D`. Returns the short hash it pointed at.
+fn delete_branch(repo: &Repository, name: &str, base: Option<&str>, force: bool) -> Result<String> {
+    let mut branch = repo.find_branch(name, BranchType::Local)?;
+    let tip = peeled_tip(branch.get()).ok_or_else(|| anyhow!("{} isn't a commit", name))?;
+    if !force && !merged_into_head_or(repo, tip, base) {
+        bail!(
+            "{} isn't merged into HEAD or {}, so it wasn't deleted",
+            name,
+            base.unwrap_or("the base branch")
+        );
+    }
+
+    let short_id = repo.find_object(tip, None)?.short_id()?;
+    branch.delete()?;
+    Ok(short_id.as_str().unwrap_or_default().to_string())
+}
+
+/// Whether the working tree has changes, staged or not, or untracked files.
+/// Bare repositories and ones whose status can't be read count as clean.
+fn is_dirty(repo: &Repository) -> bool {
+    let mut options = git2::StatusOptions::new();
+    options.include_untracked(true).include_ignored(false);
+    repo.statuses(Some(&mut options))
+        .is_ok_and(|statuses| !statuses.is_empty())
+}
+
+/// The commit `reference` points at, peeling annotated tags on the way, or
+/// nothing when it points at something else, like a tree or a blob
+fn peeled_tip(reference: &git2::Reference) -> Option<Oid> {
+    reference.peel_to_commit().ok().map(|commit| commit.id())
+}
+
+/// When `tip` was committed (or authored), or nothing when it isn't a commit
+fn commit_time(
+    repo: &Repository,
+    tip: Option<Oid>,
+    time_source: TimeSource,
+) -> Option<DateTime<Utc>> {
+    let commit = repo.find_commit(tip?).ok()?;
+    let timestamp = match time_source {
+        TimeSource::Committer => commit.time(),
+        TimeSource::Author => commit.author().when(),
+    };
+    DateTime::from_timestamp(timestamp.seconds(), 0)
+}
+
+/// What `tip` changed since it forked from `head`, like `git diff --stat
+/// head...tip`
+fn diff_stat(repo: &Repository, head: Oid, tip: Oid) -> Result<DiffStat> {
+    let base = repo
+        .merge_base(head, tip)
+        .map_err(|_| anyhow!("It shares no history with the current branch"))?;
+    tree_diff_stat(repo, base, tip)
+}
+
+/// What the stash `tip` changed in the working tree it was made from, like
+/// `git stash show`
+fn stash_diff_stat(repo: &Repository, tip: Oid) -> Result<DiffStat> {
+    let base = repo.find_commit(tip)?.parent_id(0)?;
+    tree_diff_stat(repo, base, tip)
+}
+
+fn tree_diff_stat(repo: &Repository, base: Oid, tip: Oid) -> Result<DiffStat> {
+    let base_tree = repo.find_commit(base)?.tree()?;
+    let tip_tree = repo.find_commit(tip)?.tree()?;
+    let mut diff = repo.diff_tree_to_tree(Some(&base_tree), Some(&tip_tree), None)?;
+    diff.find_similar(None)?;
+
+    let mut files = Vec::new();
+    for index in 0..diff.deltas().len() {
+        let Some(patch) = git2::Patch::from_diff(&diff, index)? else {
+            continue;
+        };
+        let delta = patch.delta();
+        let path = |file: git2::DiffFile| {
+            file.path()
+                .map(|path| path.display().to_string())
+                .unwrap_or_default()
+        };
+        let (old, new) = (path(delta.old_file()), path(delta.new_file()));
+        let lines = if delta.flags().is_binary() {
+            None
+        } else {
+            let (_, insertions, deletions) = patch.line_stats()?;
+            Some((insertions, deletions))
+        };
+        files.push(FileStat {
+            path: if old == new || old.is_empty() {
+                new
+            } else if new.is_empty() {
+                old
+            } else {
+                format!("{} → {}", old, new)
+            },
+            lines,
+        });
+    }
+    let stats = diff.stats()?;
+    Ok(DiffStat {
+        files,
+        insertions: stats.insertions(),
+        deletions: stats.deletions(),
+    })
+}
+
+/// Up to `limit` commits reachable from `tip`, newest first
+fn recent_commits(repo: &Repository, tip: Oid, limit: usize) -> Result<Vec<PreviewCommit>> {
+    let mut walk = repo.revwalk()?;
+    walk.set_sorting(git2::Sort::TIME)?;
+    walk.push(tip)?;
+
+    walk.take(limit)
+        .map(|oid| {
+            let commit = repo.find_commit(oid?)?;
+            let short_id = commit.as_object().short_id()?;
+            Ok(PreviewCommit {
+                short_id: short_id.as_str().unwrap_or_default().to_string(),
+                author: commit.author().name().unwrap_or_default().to_string(),
+                time: DateTime::from_timestamp(commit.time().seconds(), 0).unwrap_or_default(),
+                subject: commit.summary().unwrap_or_default().to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Who wrote `tip` and the first line of its message, when it's a commit
+fn commit_summary(repo: &Repository, tip: Option<Oid>) -> (Option<String>, Option<String>) {
+    let Some(commit) = tip.and_then(|tip| repo.find_commit(tip).ok()) else {
+        return (None, None);
+    };
+    (
+        commit.author().name().map(str::to_string),
+        commit.summary().map(str::to_string),
+    )
+}
+
+/// When each branch was last checked out, from the `checkout: moving from
+/// <old> to <new>` entries in the HEAD reflog
+fn checkout_times(repo: &Repository) -> HashMap<String, DateTime<Utc>> {
+    let mut times = HashMap::new();
+    let Ok(reflog) = repo.reflog("HEAD") else {
+        return times;
+    };
+
+    // Newest first, so the first entry seen for a branch is its latest
+    for entry in reflog.iter() {
+        if let Some(message) = entry.message()
+            && let Some((_, to)) = message
+                .strip_prefix("checkout: moving from ")
+                .and_then(|rest| rest.split_once(" to "))
+            && let Some(time) = DateTime::from_timestamp(entry.committer().when().seconds(), 0)
+        {
+            times.entry(to.to_string()).or_insert(time);
+        }
+    }
+    times
+}
+
+/// When the branch itself last moved (a commit, reset, rebase, ...), from the
+/// newest entry in its own reflog
+fn reflog_time(repo: &Repository, name: &str) -> Option<DateTime<Utc>> {
+    let reflog = repo.reflog(&format!("refs/heads/{}", name)).ok()?;
+    let entry = reflog.get(0)?;
+    DateTime::from_timestamp(entry.committer().when().seconds(), 0)
+}
+
+/// Whether the commit `tip` is signed at all; verifying the signature would
+/// need gpg or ssh-keygen, and presence is what reviewers look for
+fn is_signed(repo: &Repository, tip: Option<Oid>) -> bool {
+    tip.is_some_and(|tip| repo.extract_signature(&tip, None).is_ok())
+}
+
+/// Whether `branch` is configured to track an upstream whose remote-tracking
+/// ref is missing, what `git branch -vv` shows as `[gone]`
+fn upstream_gone(config: &git2::Config, branch: &git2::Branch, name: &str) -> bool {
+    config.get_string(&format!("branch.{}.merge", name)).is_ok()
+        && branch
+            .upstream()
+            .is_err_and(|err| err.code() == ErrorCode::NotFound)
+}
+
+/// How many commits `branch` is ahead of and behind its upstream
+fn ahead_behind(repo: &Repository, branch: &git2::Branch) -> Option<(usize, usize)> {
+    let (local, upstream) = upstream_tips(branch)?;
+    repo.graph_ahead_behind(local, upstream).ok()
+}
+
+/// The tips of `branch` and of its upstream, if it tracks one
+fn upstream_tips(branch: &git2::Branch) -> Option<(Oid, Oid)> {
+    let local = branch.get().target()?;
+    let upstream = branch.upstream().ok()?.get().target()?;
+    Some((local, upstream))
+}
+
+/// A branch, the tip it was counted from and how far that's ahead of and
+/// behind its upstream
+type DivergenceCount = (String, Oid, (usize, usize));
+
+/// Counts how far each branch is ahead of and behind its upstream on another
+/// thread, as walking long histories for that would slow down opening the
+/// picker. Each count is sent as soon as it's done, until the receiver is
+/// dropped.
+fn count_divergence(
+    git_dir: &Path,
+    uncounted: Vec<(String, (Oid, Oid))>,
+) -> Receiver<DivergenceCount> {
+    let (tx, rx) = mpsc::channel();
+    let git_dir = git_dir.to_path_buf();
+    thread::spawn(move || {
+        let Ok(repo) = Repository::open(&git_dir) else {
+            return;
+        };
+        for (name, (local, upstream)) in uncounted {
+            if let Ok(counts) = repo.graph_ahead_behind(local, upstream)
+                && tx.send((name, local, counts)).is_err()
+            {
+                return;
+            }
+        }
+    });
+    rx
+}
+
+/// What a local branch's tip commit and reflog say about it
+struct CommitDetails {
+    time: Option<DateTime<Utc>>,
+    author: Option<String>,
+    subject: Option<String>,
+    signed: bool,
+    reflog_time: Option<DateTime<Utc>>,
+}
+
+impl CommitDetails {
+    /// Reads them for the branch `name` at `tip`, last checked out at
+    /// `checked_out` according to the HEAD reflog
+    fn read(
+        repo: &Repository,
+        name: &str,
+        tip: Option<Oid>,
+        time_source: TimeSource,
+        checked_out: Option<DateTime<Utc>>,
+    ) -> CommitDetails {
+        let (author, subject) = commit_summary(repo, tip);
+        CommitDetails {
+            time: commit_time(repo, tip, time_source),
+            author,
+            subject,
+            signed: is_signed(repo, tip),
+            reflog_time: reflog_time(repo, name).max(checked_out),
+        }
+    }
+
+    /// The ones `branch` already shows
+    fn of(branch: &GitBranch) -> CommitDetails {
+        CommitDetails {
+            time: (!branch.not_a_commit).then_some(branch.last_commit_time),
+            author: branch.author.clone(),
+            subject: branch.subject.clone(),
+            signed: branch.tip_signed,
+            reflog_time: branch.last_reflog_time,
+        }
+    }
+
+    fn apply(self, branch: &mut GitBranch) {
+        branch.last_commit_time = self.time.unwrap_or(DateTime::UNIX_EPOCH);
+        branch.not_a_commit = self.time.is_none();
+        branch.author = self.author;
+        branch.subject = self.subject;
+        branch.tip_signed = self.signed;
+        branch.last_reflog_time = self.reflog_time;
+    }
+}
+
+/// What `read_details` sends back
+enum BranchDetail {
+    /// A branch, the tip it was read at and what that says
+    Commit(String, Oid, CommitDetails),
+    /// The tips merged into the base branch, once every branch is read
+    Merged(HashSet<Oid>),
+}
+
+/// Reads each branch's commit and reflog on another thread, then which of
+/// them are merged into `base`, so the picker can open on a list of names
+/// and tips. On thousands of branches that took seconds. Each branch is
+/// sent as soon as it's read, until the receiver is dropped.
+fn read_details(
+    git_dir: &Path,
+    undetailed: Vec<(String, Oid)>,
+    base: Option<Oid>,
+    time_source: TimeSource,
+    checkouts: HashMap<String, DateTime<Utc>>,
+) -> Receiver<BranchDetail> {
+    let (tx, rx) = mpsc::channel();
+    let git_dir = git_dir.to_path_buf();
+    thread::spawn(move || {
+        let Ok(repo) = Repository::open(&git_dir) else {
+            return;
+        };
+        let tips: HashSet<Oid> = undetailed.iter().map(|&(_, tip)| tip).collect();
+        for (name, tip) in undetailed {
+            let checked_out = checkouts.get(&name).copied();
+            let details = CommitDetails::read(&repo, &name, Some(tip), time_source, checked_out);
+            if tx.send(BranchDetail::Commit(name, tip, details)).is_err() {
+                return;
+            }
+        }
+        let merged = base.map_or_else(HashSet::new, |base| merged_tips(&repo, base, &tips));
+        let _ = tx.send(BranchDetail::Merged(merged));
+    });
+    rx
+}
+
+/// Which of `tips` are in the history of `base`, walking it once for all of
+/// them rather than asking about each
+fn merged_tips(repo: &Repository, base: Oid, tips: &HashSet<Oid>) -> HashSet<Oid> {
+    let mut merged = HashSet::new();
+    let Ok(mut walk) = repo.revwalk() else {
+        return merged;
+    };
+    if walk.push(base).is_err() {
+        return merged;
+    }
+    for oid in walk.flatten() {
+        if tips.contains(&oid) {
+            merged.insert(oid);
+            if merged.len() == tips.len() {
+                break;
+            }
+        }
+    }
+    merged
+}
+
+/// The branch HEAD points at when it has no commits yet, e.g. after `git init`
+/// or `git checkout --orphan`
+fn unborn_branch(repo: &Repository) -> Option<String> {
+    match repo.head() {
+        Err(err) if err.code() == ErrorCode::UnbornBranch => {}
+        _ => return None,
+    }
+    let head = repo.find_reference("HEAD").ok()?;
+    let target = head.symbolic_target()?;
+    target.strip_prefix("refs/heads/").map(str::to_string)
+}
+
+fn head_branch(repo: &Repository) -> Option<String> {
+    let head = repo.head().ok()?;
+    if !head.is_branch() {
+        return None;
+    }
+    head.shorthand().map(|name| name.to_string())
+}
+
+/// The most recent branch HEAD moved away from, other than `current`.
+///
+/// Read from the `checkout: moving from <old> to <new>` entries git writes to
+/// the HEAD reflog, so it also covers switches made outside gb. Branches that
+/// have since been deleted are skipped.
+fn previous_checkout(repo: &Repository, current: Option<&str>) -> Option<String> {
+    let reflog = repo.reflog("HEAD").ok()?;
+    reflog.iter().find_map(|entry| {
+        let message = entry.message()?;
+        let (from, _) = message
+            .strip_prefix("checkout: moving from ")?
+            .split_once(" to ")?;
+        (Some(from) != current && repo.find_branch(from, BranchType::Local).is_ok())
+            .then(|| from.to_string())
+    })
+}
+
+/// Remembers the checkout of `branch`, for `--checkout-last` to go back to
+/// `previous` and for sorting by frecency
+fn remember_checkout(previous: Option<&str>, branch: &str) -> Result<()> {
+    let repo = open_repo()?;
+    let mut state = State::load()?;
+    state
+        .repo_mut(&repo)
+        .record_checkout(previous, branch, Utc::now().timestamp());
+    state.save()
+}
+
+/// Checks out `name` without the TUI, remembering the branch we came from
+fn switch_branch(name: &str) -> Result<()> {
+    let current = head_branch(&open_repo()?);
+    checkout_branch(name)?;
+    remember_checkout(current.as_deref(), name)
+}
+
+fn checkout_last() -> Result<()> {
+    let repo = open_repo()?;
+    let Some(previous) = State::load()?.repo(&repo).previous_branch else {
+        bail!("No previous branch recorded for this repository");
+    };
+    switch_branch(&previous)
+}
+
+/// Prints the checked out branch, or the short hash of a detached HEAD
+fn print_current() -> Result<()> {
+    let repo = open_repo()?;
+    if let Some(name) = unborn_branch(&repo) {
+        println!("{}", name);
+        return Ok(());
+    }
+    let head = repo.head()?;
+
+    if head.is_branch()
+        && let Some(name) = head.shorthand()
+    {
+        println!("{}", name);
+    } else {
+        let commit = head.peel_to_commit()?;
+        println!(
+            "{}",
+            commit.as_object().short_id()?.as_str().unwrap_or_default()
+        );
+    }
+    Ok(())
+}
+
+/// A branch as `--jsonl` prints it
+#[derive(Serialize)]
+struct BranchRecord<'a> {
+    name: &'a str,
+    kind: &'static str,
+    current: bool,
+    tip: Option<String>,
+    /// RFC 3339, by `time_source`, without one when there's no commit
+    time: Option<String>,
+    author: Option<&'a str>,
+    subject: Option<&'a str>,
+    description: Option<&'a str>,
+    /// Commits ahead of and behind the upstream, without one when there's none
+    upstream: Option<&'a str>,
+    ahead: Option<usize>,
+    behind: Option<usize>,
+    upstream_gone: bool,
+    merged: bool,
+    protected: bool,
+    stashed: bool,
+}
+
+impl<'a> From<&'a GitBranch> for BranchRecord<'a> {
+    fn from(branch: &'a GitBranch) -> Self {
+        BranchRecord {
+            name: &branch.name,
+            kind: branch.ref_kind.badge(),
+            current: branch.is_current,
+            tip: branch.tip.map(|oid| oid.to_string()),
+            time: (!branch.is_unborn && !branch.not_a_commit)
+                .then(|| branch.last_commit_time.to_rfc3339()),
+            author: branch.author.as_deref(),
+            subject: branch.subject.as_deref(),
+            description: branch.description.as_deref(),
+            upstream: branch.upstream.as_deref(),
+            ahead: branch.ahead_behind.map(|(ahead, _)| ahead),
+            behind: branch.ahead_behind.map(|(_, behind)| behind),
+            upstream_gone: branch.upstream_gone,
+            merged: branch.is_merged,
+            protected: branch.is_protected,
+            stashed: branch.has_stash,
+        }
+    }
+}
+
+/// Prints a line of JSON for each branch as soon as it's read, so something
+/// like `jq` can get going on a huge repository before the last one is in.
+/// That's why they come unsorted, in the order git lists them.
+fn print_jsonl(args: &Args) -> Result<()> {
+    let mut repo = open_repo()?;
+    let mut config = Config::load()?;
+    config.apply_git_config(&repo.config()?)?;
+    let mut app = App::with_branches(Vec::new(), config);
+    app.only = args.only.clone();
+    app.source = ListSource::starting(args, &app.config);
+
+    // Line buffered, so every branch goes out as soon as it's written
+    let mut stdout = io::stdout().lock();
+    let result = app.read_branches(&mut repo, &mut |branch| {
+        let line = serde_json::to_string(&BranchRecord::from(&branch))?;
+        writeln!(stdout, "{}", line)?;
+        Ok(())
+    });
+    match result {
+        // Closing the pipe early, like `| head` does, isn't an error
+        Err(err)
+            if err
+                .downcast_ref::<io::Error>()
+                .is_some_and(|err| err.kind() == io::ErrorKind::BrokenPipe) =>
+        {
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+/// Prints what the picker would list, names only or with `--json` as an
+/// array of the same records `--jsonl` prints
+fn print_list(app: &App, json: bool) -> Result<()> {
+    let listed = app.filtered_branches.iter().map(|&i| &app.branches[i]);
+    let mut output = if json {
+        serde_json::to_string_pretty(&listed.map(BranchRecord::from).collect::<Vec<_>>())?
+    } else {
+        listed
+            .map(|branch| branch.name.as_str())
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+    if !output.is_empty() {
+        output.push('\n');
+    }
+    match io::stdout().lock().write_all(output.as_bytes()) {
+        // Closing the pipe early, like `| head` does, isn't an error
+        Err(err) if err.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        result => Ok(result?),
+    }
+}
+
+/// Runs a non-interactive command, reporting failures on stderr
+fn run_command(verbose: bool, command: impl FnOnce() -> Result<()>) -> ExitCode {
+    match command() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            report_error(&err, verbose);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Prints an error as its chain of causes on one line, or with `--verbose`
+/// in anyhow's Debug form, which adds the backtrace when one was captured
+fn report_error(err: &anyhow::Error, verbose: bool) {
+    if verbose {
+        eprintln!("gb: {:?}", err);
+    } else {
+        eprintln!("gb: {:#}", err);
+    }
+}
+
+fn is_favorite(favorites: &BTreeSet<String>, branch: &GitBranch) -> bool {
+    branch.ref_kind == RefKind::Local && favorites.contains(&branch.name)
+}
+
+fn truncate(text: &str, max_chars: usize) -> String {
+    if text.chars().count() <= max_chars {
+        return text.to_string();
+    }
+    let truncated: String = text.chars().take(max_chars.saturating_sub(1)).collect();
+    format!("{}…", truncated)
+}
+
+/// The tip's subject, cut to `room` characters, or nothing when there's too
+/// little room left for it to say anything
+fn subject_label(subject: &str, room: usize) -> Option<String> {
+    const MIN_SUBJECT_WIDTH: usize = 8;
+
+    (room >= MIN_SUBJECT_WIDTH).then(|| truncate(subject, room))
+}
+
+/// `text` split into spans by which characters `is_highlighted`, in `style`
+/// or `highlight`
+fn highlighted_spans(
+    text: &str,
+    is_highlighted: impl Fn(usize) -> bool,
+    style: Style,
+    highlight: Style,
+) -> Vec<Span<'static>> {
+    let mut spans: Vec<Span<'static>> = Vec::new();
+    let mut run = String::new();
+    let mut run_highlighted = false;
+    for (at, c) in text.chars().enumerate() {
+        let highlighted = is_highlighted(at);
+        if highlighted != run_highlighted && !run.is_empty() {
+            let style = if run_highlighted { highlight } else { style };
+            spans.push(Span::styled(std::mem::take(&mut run), style));
+        }
+        run_highlighted = highlighted;
+        run.push(c);
+    }
+    if !run.is_empty() {
+        spans.push(Span::styled(
+            run,
+            if run_highlighted { highlight } else { style },
+        ));
+    }
+    spans
+}
+
+fn ui(f: &mut Frame, app: &mut App) {
+    let palette = app.palette;
+    // Status line at the bottom, only while there's something to report
+    let status = if let Some(input) = &app.input {
+        Some(Line::from(vec![
+            Span::styled(input.label(), palette.prompt),
+            Span::raw(input.value.clone()),
+        ]))
+    } else if let Some(prompt) = app.prompt {
+        let selected_name = app
+            .selected_branch()
+            .map_or("", |branch| branch.name.as_str());
+        let question = match prompt {
+            Prompt::DeleteMarked if app.marked_unmerged() => format!(
+                "Delete {} marked {}, or only the merged ones? (y/m/n)",
+                app.marked.len(),
+                branches(app.marked.len())
+            ),
+            Prompt::DeleteMarked => format!(
+                "Delete {} marked {}? (y/n)",
+                app.marked.len(),
+                branches(app.marked.len())
+            ),
+            Prompt::RetryLocked => match &app.lock_file {
+                Some(lock) if lock.exists() => format!(
+                    "Repository is locked by another git process ({}), retry? (y/n)",
+                    lock.display()
+                ),
+                _ => "The other git process is done, retry? (y/n)".to_string(),
+            },
+            Prompt::DirtyCheckout => {
+                "Working tree is dirty: y takes the changes along, m merges them in \
+                 like --merge, s stashes them, p stashes them and pops them after \
+                 switching, n cancels"
+                    .to_string()
+            }
+            Prompt::DetachedCheckout => format!(
+                "{} is a tag: HEAD gets detached, so new commits are on no branch. Check it out? (y/n)",
+                selected_name
+            ),
+            Prompt::DeleteSelected => format!("Delete branch {}? (y/n)", selected_name),
+            Prompt::DropStash => format!(
+                "Drop {}? It can only be recovered by hash then (y/n)",
+                selected_name
+            ),
+            Prompt::DeleteUnmerged => format!(
+                "{} isn't merged into HEAD or {}, delete it anyway? (y/n)",
+                selected_name,
+                app.base_branch.as_deref().unwrap_or("the base branch")
+            ),
+            Prompt::CreateBranch => {
+                format!(
+                    "Create branch {} from HEAD and switch to it? (y/n)",
+                    app.filter
+                )
+            }
+            Prompt::ForceDeleteSelected => format!(
+                "Its unmerged commits can only be recovered by hash then. Really delete {}? (y/n)",
+                selected_name
+            ),
+            Prompt::Integrate(Integration::Rebase) => format!(
+                "Rebase {} onto {}? (y/n)",
+                app.current_branch.as_deref().unwrap_or("HEAD"),
+                selected_name
+            ),
+            Prompt::Integrate(Integration::Merge) => format!(
+                "Merge {} into {}? (y/n)",
+                selected_name,
+                app.current_branch.as_deref().unwrap_or("HEAD")
+            ),
+        };
+        Some(Line::from(Span::styled(question, palette.prompt)))
+    } else if let Some(error) = &app.error_message {
+        Some(Line::from(Span::styled(error.clone(), palette.danger)))
+    } else if app.fetch_task.is_some() {
+        Some(Line::from(vec![
+            Span::styled(
+                format!("{} ", SPINNER_FRAMES[app.spinner_frame]),
+                palette.accent,
+            ),
+            Span::styled("Fetching…", palette.dim),
+        ]))
+    } else {
+        // What a `key:value` term in the filter can be, while one's typed
+        let message = Filter::wants_hint(&app.filter)
+            .then(|| filter::TERMS_HINT.to_string())
+            .or_else(|| app.status_message.clone());
+        let message = message.or_else(|| {
+            app.cleanup.then(|| match &app.base_branch {
+                None => "Couldn't find a main or master branch to compare against".to_string(),
+                Some(base) if app.branches.is_empty() => {
+                    format!("No branches merged into {} to clean up", base)
+                }
+                Some(base) => format!(
+                    "Merged into {}: {} marked · space to toggle · enter to delete",
+                    base,
+                    app.marked.len()
+                ),
+            })
+        });
+        let message = message.or_else(|| {
+            (!app.marked.is_empty()).then(|| {
+                format!(
+                    "{} marked · space to toggle · X to delete them",
+                    app.marked.len()
+                )
+            })
+        });
+        let message = message.or_else(|| {
+            (app.filtered_branches.is_empty() && !app.filter.is_empty())
+                .then(|| app.no_match_hint())
+        });
+        let message = message.or_else(|| {
+            app.config
+                .show_timestamp
+                .then(|| app.selected_branch())
+                .flatten()
+                .filter(|branch| !app.is_pending(branch))
+                .and_then(|branch| branch.timestamp_label(app.sort_mode, &Local))
+        });
+        let message = message.or_else(|| {
+            app.config
+                .key_hints
+                .then(|| app.key_hints(f.area().width as usize))
+                .filter(|hints| !hints.is_empty())
+        });
+        message.map(|message| Line::from(Span::styled(message, palette.dim)))
+    };
+
+    let area = f.area();
+    if area.height == 0 {
+        return;
+    }
+
+    // Everything but the list is optional. When rows run short the header goes
+    // first, then the filter, and the status line (which may hold a prompt)
+    // last, so at least the selected branch always stays visible.
+    let mut spare_rows = area.height - 1;
+    let mut take_row = |wanted: bool| {
+        let granted = wanted && spare_rows > 0;
+        if granted {
+            spare_rows -= 1;
+        }
+        granted
+    };
+    let status = status.filter(|_| take_row(true));
+    let show_filter = take_row(!app.filter.is_empty() || app.filter_focused);
+
+    // Subtle header naming the repository, to tell several gb windows apart,
+    // and saying so when the cap leaves branches out
+    let mut header_parts = Vec::new();
+    // Marked with a `*` when there are uncommitted changes, like shell prompts do
+    let dirty_marker = if app.dirty { "*" } else { "" };
+    if app.config.show_repo_name
+        && let Some(repo_name) = &app.repo_name
+    {
+        header_parts.push(format!("{}{}", repo_name, dirty_marker));
+    } else if app.dirty {
+        header_parts.push(dirty_marker.to_string());
+    }
+    if app.source != ListSource::Local {
+        header_parts.push(app.source.label().to_string());
+    }
+    if !app.checkouts_listed.is_empty() {
+        header_parts.push(format!("{} repositories", app.checkouts_listed.len()));
+    }
+    if app.unpushed_only {
+        header_parts.push("unpushed".to_string());
+    }
+    if app.read_only {
+        header_parts.push("read-only".to_string());
+    }
+    let (listed, total) = app.listed_count();
+    if app.quick {
+        header_parts.push("quick switch".to_string());
+    } else if listed < total {
+        header_parts.push(format!("showing {} of {}", listed, total));
+    }
+    let header =
+        Some(header_parts.join(" · ")).filter(|header| !header.is_empty() && take_row(true));
+
+    // A long message, like git explaining why a checkout failed, wraps onto
+    // a few more rows if they can be spared
+    let mut status_height = 0;
+    if let Some(status) = &status {
+        let wanted = status
+            .width()
+            .div_ceil(area.width.max(1) as usize)
+            .clamp(1, 3);
+        status_height = 1;
+        while status_height < wanted && take_row(true) {
+            status_height += 1;
+        }
+    }
+
+    let mut constraints = Vec::new();
+    if header.is_some() {
+        constraints.push(Constraint::Length(1));
+    }
+    if show_filter {
+        constraints.push(Constraint::Length(1));
+    }
+    constraints.push(Constraint::Min(1));
+    if status.is_some() {
+        constraints.push(Constraint::Length(status_height as u16));
+    }
+
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints(constraints)
+        .split(area);
+    let mut chunks = chunks.iter().copied();
+
+    if let Some(header) = header
+        && let Some(header_area) = chunks.next()
+    {
+        let header = Paragraph::new(header).style(palette.dim);
+        f.render_widget(header, header_area);
+    }
+
+    // Only show filter if there's text
+    if show_filter && let Some(filter_area) = chunks.next() {
+        let filter_text = format!("Filter: {}", app.filter);
+        if app.filter_focused && app.input.is_none() {
+            f.set_cursor_position((
+                filter_area.x + filter_text.chars().count() as u16,
+                filter_area.y,
+            ));
+        }
+        let filter_paragraph = Paragraph::new(filter_text).style(palette.filter);
+        f.render_widget(filter_paragraph, filter_area);
+    }
+
+    let main_area = chunks.next().unwrap_or(area);
+    // The preview takes the right half, when it's on and there's room
+    let (list_area, preview_area) = if app.show_preview && main_area.width >= MIN_PREVIEW_WIDTH {
+        let halves = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+            .split(main_area);
+        (halves[0], Some(halves[1]))
+    } else {
+        (main_area, None)
+    };
+
+    if let Some(status) = status
+        && let Some(status_area) = chunks.next()
+    {
+        if app.input.is_some() {
+            f.set_cursor_position((status_area.x + status.width() as u16, status_area.y));
+        }
+        f.render_widget(
+            Paragraph::new(status).wrap(Wrap { trim: true }),
+            status_area,
+        );
+    }
+
+    // Line the metadata up into columns: names are padded to the longest
+    // visible one (up to the configured cap) and the times are right-aligned
+    let now = Utc::now();
+    let name_width = app
+        .filtered_branches
+        .iter()
+        .map(|&i| app.branches[i].display_name(app.full_names).chars().count())
+        .max()
+        .unwrap_or(0)
+        .min(app.config.max_name_width);
+    let protected_column = app
+        .filtered_branches
+        .iter()
+        .any(|&i| app.branches[i].is_protected);
+    let worktree_column = app
+        .filtered_branches
+        .iter()
+        .any(|&i| app.in_other_worktree(&app.branches[i]).is_some());
+    let merged_column = app
+        .filtered_branches
+        .iter()
+        .any(|&i| app.shows_merged(&app.branches[i]));
+    let pull_request_width = app
+        .filtered_branches
+        .iter()
+        .filter_map(|&i| app.pull_request(&app.branches[i]))
+        .map(|pull_request| {
+            let spans = pull_request.spans(&palette);
+            spans.iter().map(Span::width).sum::<usize>()
+        })
+        .max()
+        .unwrap_or(0);
+    let stash_column = app
+        .filtered_branches
+        .iter()
+        .any(|&i| app.branches[i].has_stash);
+    let signed_column = app
+        .filtered_branches
+        .iter()
+        .any(|&i| app.branches[i].tip_signed);
+    let time_width = app
+        .filtered_branches
+        .iter()
+        .filter(|_| app.config.shows_column(Column::Time))
+        .map(|&i| app.age_label(&app.branches[i], now).chars().count())
+        .max()
+        .unwrap_or(0);
+    let author_width = app
+        .filtered_branches
+        .iter()
+        .filter(|_| app.config.shows_column(Column::Author))
+        .filter_map(|&i| app.branches[i].author.as_deref())
+        .map(|author| author.chars().count().min(MAX_AUTHOR_WIDTH))
+        .max()
+        .unwrap_or(0);
+    let upstream_width = app
+        .filtered_branches
+        .iter()
+        .filter(|_| app.config.shows_column(Column::Upstream))
+        .filter_map(|&i| app.branches[i].upstream.as_deref())
+        .map(|upstream| upstream.chars().count().min(MAX_UPSTREAM_WIDTH))
+        .max()
+        .unwrap_or(0);
+    let favorite_column = app
+        .filtered_branches
+        .iter()
+        .any(|&i| app.is_favorite(&app.branches[i]));
+    let divergence_width = app
+        .filtered_branches
+        .iter()
+        .filter_map(|&i| app.branches[i].upstream_label(app.config.divergence_style))
+        .map(|label| label.chars().count())
+        .max()
+        .unwrap_or(0);
+
+    // What the names are matched against, to highlight the matched characters
+    let match_query = app
+        .ranked_by_filter()
+        .then(|| Filter::parse(&app.filter).text);
+
+    // Where the names start, which the tree view's groups line up with
+    let name_column =
+        4 + if app.cleanup || !app.marked.is_empty() {
+            4
+        } else {
+            0
+        } + if favorite_column { 2 } else { 0 }
+            + if app.source == ListSource::All { 7 } else { 0 };
+
+    // The headers that go above each row: the day, week or month it starts,
+    // or in the tree view, the groups it opens. Headers are only drawn, never
+    // selected, so navigation doesn't need to know about them.
+    let local_now = Local::now();
+    let mut previous_group = None;
+    let headers: Vec<Vec<Line>> = app
+        .filtered_branches
+        .iter()
+        .enumerate()
+        .map(|(idx, &i)| {
+            if let Some(row) = app.tree_rows.get(idx) {
+                return row
+                    .headers
+                    .iter()
+                    .map(|group| {
+                        Line::from(vec![
+                            Span::raw(" ".repeat(name_column + 2 * group.depth)),
+                            Span::styled(format!("▾ {}", group.label()), palette.dim),
+                            Span::styled(format!(" {}", group.count), palette.dim),
+                        ])
+                    })
+                    .collect();
+            }
+            if !(app.date_headers && app.sort_mode == SortMode::Recent && !app.ranked_by_filter()) {
+                return Vec::new();
+            }
+            let group = DateGroup::of(local_now, app.branches[i].last_commit_time);
+            (previous_group.replace(group) != Some(group))
+                .then(|| Line::from(Span::styled(format!("  {}", group.label()), palette.dim)))
+                .into_iter()
+                .collect()
+        })
+        .collect();
+
+    // Select the row, counting the headers above it
+    let mut header_count = 0;
+    app.list_rows = headers
+        .iter()
+        .enumerate()
+        .map(|(idx, header)| {
+            header_count += header.len();
+            idx + header_count
+        })
+        .collect();
+    app.list_height = list_area.height as usize;
+    app.list_area = list_area;
+    app.preview_area = preview_area;
+    let selected_row = app.list_state.selected().map(|idx| app.list_rows[idx]);
+    let offset = app.view_offset(selected_row);
+    *app.list_state.offset_mut() = offset;
+    let in_view = |row: usize| row >= offset && row < offset + app.list_height;
+
+    // Only the rows on screen are built, so a list of thousands of branches
+    // costs no more to draw than a screenful
+    let items: Vec<ListItem> = app
+        .filtered_branches
+        .iter()
+        .enumerate()
+        .filter(|&(idx, _)| {
+            let row = app.list_rows[idx];
+            (row - headers[idx].len()..=row).any(in_view)
+        })
+        .flat_map(|(idx, &i)| {
+            let branch = &app.branches[i];
+            let is_selected = app.list_state.selected() == Some(idx);
+            let first_header_row = app.list_rows[idx] - headers[idx].len();
+            let header: Vec<ListItem> = headers[idx]
+                .iter()
+                .enumerate()
+                .filter(|&(k, _)| in_view(first_header_row + k))
+                .map(|(_, line)| ListItem::new(line.clone()))
+                .collect();
+
+            let mut spans = vec![];
+
+            // Selection indicator (like gum)
+            if is_selected {
+                spans.push(Span::styled("❯ ", palette.accent));
+            } else {
+                spans.push(Span::raw("  "));
+            }
+
+            // A collapsed group is its name and count, none of the columns
+            if let Some(row) = app.tree_rows.get(idx)
+                && let Some(group) = &row.collapsed
+            {
+                let name_style = if is_selected {
+                    palette.selected
+                } else {
+                    palette.name
+                };
+                spans.push(Span::raw(" ".repeat(name_column - 2 + 2 * row.depth)));
+                spans.push(Span::styled(format!("▸ {}", group.label()), name_style));
+                spans.push(Span::styled(format!(" {}", group.count), palette.dim));
+                return header
+                    .into_iter()
+                    .chain(std::iter::once(ListItem::new(Line::from(spans))));
+            }
+
+            if app.cleanup || !app.marked.is_empty() {
+                if app.marked.contains(&branch.name) {
+                    spans.push(Span::styled("[x] ", palette.danger));
+                } else {
+                    spans.push(Span::styled("[ ] ", palette.dim));
+                }
+            }
+
+            // Current branch indicator, and the one `-` goes back to
+            if branch.is_current {
+                spans.push(Span::styled("● ", palette.current));
+            } else if app.is_previous(branch) {
+                spans.push(Span::styled("- ", palette.dim));
+            } else {
+                spans.push(Span::raw("  "));
+            }
+
+            if app.is_favorite(branch) {
+                spans.push(Span::styled("★ ", palette.notice));
+            } else if favorite_column {
+                spans.push(Span::raw("  "));
+            }
+
+            if app.source == ListSource::All {
+                spans.push(Span::styled(
+                    format!("{:<7}", branch.ref_kind.badge()),
+                    palette.dim,
+                ));
+            }
+
+            // Branch name
+            let name_style = if is_selected {
+                palette.selected
+            } else if branch.is_current {
+                palette.current
+            } else {
+                palette.name
+            };
+
+            // Under its group in the tree view, without the group's prefix
+            let display_name = match app.tree_rows.get(idx) {
+                Some(row) => Cow::Owned(format!(
+                    "{}{}",
+                    "  ".repeat(row.depth),
+                    &branch.name[row.label_start..]
+                )),
+                None => branch.display_name(app.full_names),
+            };
+            let shown = truncate(&display_name, name_width);
+            let positions = match &match_query {
+                Some(query) => match_positions(&branch.name, query),
+                None => Vec::new(),
+            };
+            if positions.is_empty() {
+                spans.push(Span::styled(
+                    format!("{:<width$}", shown, width = name_width),
+                    name_style,
+                ));
+            } else {
+                // Positions count from the short name, which ends the full one
+                let offset = display_name.chars().count() - branch.name.chars().count();
+                // Nothing's highlighted under the `…` of a truncated name
+                let visible = if shown == display_name {
+                    usize::MAX
+                } else {
+                    shown.chars().count() - 1
+                };
+                spans.extend(highlighted_spans(
+                    &shown,
+                    |at| at < visible && at >= offset && positions.contains(&(at - offset)),
+                    name_style,
+                    name_style.patch(palette.matched),
+                ));
+                let padding = name_width.saturating_sub(shown.chars().count());
+                spans.push(Span::raw(" ".repeat(padding)));
+            }
+
+            // Time ago (more subtle), unless there's no commit to date it by
+            if time_width > 0 {
+                spans.push(Span::styled(
+                    format!(
+                        " {:>width$}",
+                        app.age_label(branch, now),
+                        width = time_width
+                    ),
+                    if branch.not_a_commit {
+                        palette.danger
+                    } else {
+                        palette.dim
+                    },
+                ));
+            }
+
+            // Every row has the same room left here, so the column either
+            // fits in all of them or in none
+            let used: usize = spans.iter().map(Span::width).sum();
+            if author_width > 0 && used + 1 + author_width <= list_area.width as usize {
+                let author = branch.author.as_deref().unwrap_or_default();
+                spans.push(Span::styled(
+                    format!(
+                        " {:<width$}",
+                        truncate(author, author_width),
+                        width = author_width
+                    ),
+                    palette.dim,
+                ));
+            }
+
+            let used: usize = spans.iter().map(Span::width).sum();
+            if upstream_width > 0 && used + 1 + upstream_width <= list_area.width as usize {
+                let upstream = branch.upstream.as_deref().unwrap_or_default();
+                spans.push(Span::styled(
+                    format!(
+                        " {:<width$}",
+                        truncate(upstream, upstream_width),
+                        width = upstream_width
+                    ),
+                    palette.dim,
+                ));
+            }
+
+            // Nothing at all for branches without an upstream
+            if divergence_width > 0 {
+                let label = branch
+                    .upstream_label(app.config.divergence_style)
+                    .unwrap_or_default();
+                // Gone upstreams are the ones worth cleaning up
+                let style = if branch.upstream_gone {
+                    palette.danger
+                } else {
+                    palette.notice
+                };
+                spans.push(Span::styled(
+                    format!(" {:<width$}", label, width = divergence_width),
+                    style,
+                ));
+            }
+
+            if app.shows_merged(branch) {
+                spans.push(Span::styled(" merged", palette.dim));
+            } else if merged_column {
+                spans.push(Span::raw("       "));
+            }
+
+            if pull_request_width > 0 {
+                let pull_request = app
+                    .pull_request(branch)
+                    .map(|pull_request| pull_request.spans(&palette))
+                    .unwrap_or_default();
+                let width: usize = pull_request.iter().map(Span::width).sum();
+                spans.push(Span::raw(" "));
+                spans.extend(pull_request);
+                spans.push(Span::raw(" ".repeat(pull_request_width - width)));
+            }
+
+            if branch.is_protected {
+                spans.push(Span::styled(" 🔒", palette.dim));
+            } else if protected_column {
+                spans.push(Span::raw("   "));
+            }
+
+            if branch.tip_signed {
+                spans.push(Span::styled(" 🔑", palette.dim));
+            } else if signed_column {
+                spans.push(Span::raw("   "));
+            }
+
+            // There's no checking it out here, only going there
+            if app.in_other_worktree(branch).is_some() {
+                spans.push(Span::styled(" ⌂", palette.notice));
+            } else if worktree_column {
+                spans.push(Span::raw("  "));
+            }
+
+            // Stashed work makes switching away riskier, so flag it
+            if branch.has_stash {
+                spans.push(Span::styled(" ⚑", palette.notice));
+            } else if stash_column {
+                spans.push(Span::raw("  "));
+            }
+
+            if let Some(description) = &branch.description {
+                spans.push(Span::styled(
+                    format!(" {}", truncate(description, MAX_DESCRIPTION_WIDTH)),
+                    palette.description,
+                ));
+            }
+
+            // Whatever room the row has left, so long subjects never wrap
+            if app.config.shows_column(Column::Subject)
+                && let Some(subject) = &branch.subject
+            {
+                let used: usize = spans.iter().map(Span::width).sum();
+                let room = (list_area.width as usize).saturating_sub(used + 1);
+                if let Some(label) = subject_label(subject, room) {
+                    spans.push(Span::styled(format!(" {}", label), palette.dim));
+                }
+            }
+
+            header
+                .into_iter()
+                .chain(std::iter::once(ListItem::new(Line::from(spans))))
+        })
+        .collect();
+
+    // Clean list without borders, already scrolled to the rows it's given
+    let list = List::new(items)
+        .highlight_style(Style::default()) // No background highlight
+        .highlight_symbol(""); // No symbol since we handle it manually
+    let mut window = ListState::default().with_selected(selected_row.map(|row| row - offset));
+    f.render_stateful_widget(list, list_area, &mut window);
+
+    if let Some(preview_area) = preview_area {
+        draw_preview(f, app, preview_area);
+    }
+    if let Some(highlighted) = app.source_menu {
+        draw_source_menu(f, &app.palette, app.source, highlighted);
+    }
+    if let Some(menu) = &app.upstream_menu {
+        draw_upstream_menu(f, &app.palette, menu);
+    }
+    if app.prompt == Some(Prompt::DeleteMarked) {
+        draw_deletion_summary(f, app, main_area);
+    }
+    if app.show_stats {
+        draw_stats(f, app, main_area);
+    }
+}
+
+/// The selected branch's latest commits, newest first, beside the list
+fn draw_preview(f: &mut Frame, app: &mut App, area: Rect) {
+    if app.preview_kind == PreviewKind::DiffStat {
+        return draw_diff_stat(f, app, area);
+    }
+    let palette = app.palette;
+    let tip = app.selected_branch().and_then(|branch| branch.tip);
+    if app.preview.as_ref().map(|(shown, _)| *shown) != tip {
+        let repo = app.selected_branch().map(|branch| app.repo_of(branch));
+        app.preview = tip.zip(repo).map(|(tip, repo)| {
+            let commits = repo.and_then(|repo| recent_commits(&repo, tip, PREVIEW_COMMITS));
+            (tip, commits.map_err(|err| format!("{:#}", err)))
+        });
+        app.preview_scroll = 0;
+    }
+
+    let now = Utc::now();
+    let lines: Vec<Line> = match &app.preview {
+        None => vec![Line::from(Span::styled(" No commits", palette.dim))],
+        Some((_, Err(err))) => vec![Line::from(Span::styled(
+            format!(" {}", err),
+            palette.danger,
+        ))],
+        Some((_, Ok(commits))) => commits
+            .iter()
+            .map(|commit| {
+                Line::from(vec![
+                    Span::styled(format!(" {}", commit.short_id), palette.notice),
+                    Span::styled(format!(" {}", time_ago(now, commit.time)), palette.dim),
+                    Span::styled(format!(" {}", commit.author), palette.dim),
+                    Span::styled(format!(" {}", commit.subject), palette.name),
+                ])
+            })
+            .collect(),
+    };
+
+    let title = match app.selected_branch() {
+        Some(branch) => format!(" {} · J/K scroll · L closes ", branch.name),
+        None => " L closes ".to_string(),
+    };
+    let preview = Paragraph::new(lines).scroll((app.preview_scroll, 0)).block(
+        Block::default()
+            .borders(Borders::LEFT)
+            .border_style(palette.dim)
+            .title(Span::styled(title, palette.dim)),
+    );
+    f.render_widget(preview, area);
+}
+
+/// The files the selected branch changed since it forked from HEAD, or the
+/// selected stash changed, with the lines each gained and lost, beside the list
+fn draw_diff_stat(f: &mut Frame, app: &mut App, area: Rect) {
+    let palette = app.palette;
+    let stash = app
+        .selected_branch()
+        .is_some_and(|branch| branch.ref_kind == RefKind::Stash);
+    let tip = app.selected_branch().and_then(|branch| branch.tip);
+    // A stash is shown against the commit it was made on, whatever HEAD is
+    let between = tip.zip(if stash { tip } else { app.head });
+    if app.diff_stat.as_ref().map(|(shown, _)| *shown) != between {
+        app.diff_stat = between.map(|(tip, head)| {
+            let stat = open_repo().and_then(|repo| {
+                if stash {
+                    stash_diff_stat(&repo, tip)
+                } else {
+                    diff_stat(&repo, head, tip)
+                }
+            });
+            ((tip, head), stat.map_err(|err| format!("{:#}", err)))
+        });
+        app.preview_scroll = 0;
+    }
+
+    let note = |text: &str| vec![Line::from(Span::styled(format!(" {}", text), palette.dim))];
+    let lines: Vec<Line> = match (&app.diff_stat, app.selected_branch()) {
+        (_, Some(branch)) if branch.is_current => note("This is the current branch"),
+        (None, _) => note("No commits"),
+        (Some((_, Err(err))), _) => vec![Line::from(Span::styled(
+            format!(" {}", err),
+            palette.danger,
+        ))],
+        (Some((_, Ok(stat))), _) if stat.files.is_empty() && stash => {
+            note("No changes to tracked files")
+        }
+        (Some((_, Ok(stat))), _) if stat.files.is_empty() => {
+            note("No changes since it forked from the current branch")
+        }
+        (Some((_, Ok(stat))), _) => {
+            let width = |count: usize| count.to_string().len();
+            let (added_width, removed_width) = stat
+                .files
+                .iter()
+                .filter_map(|file| file.lines)
+                .fold((1, 1), |(added, removed), (insertions, deletions)| {
+                    (added.max(width(insertions)), removed.max(width(deletions)))
+                });
+            let summary = format!(
+                " {} files changed, {} insertions(+), {} deletions(-)",
+                stat.files.len(),
+                stat.insertions,
+                stat.deletions
+            );
+            std::iter::once(Line::from(Span::styled(summary, palette.dim)))
+                .chain(stat.files.iter().map(|file| {
+                    let counts = match file.lines {
+                        Some((insertions, deletions)) => vec![
+                            Span::styled(
+                                format!(" +{:<width$}", insertions, width = added_width),
+                                palette.current,
+                            ),
+                            Span::styled(
+                                format!(" -{:<width$}", deletions, width = removed_width),
+                                palette.danger,
+                            ),
+                        ],
+                        None => vec![Span::styled(
+                            format!(
+                                " {:<width$}",
+                                "binary",
+                                width = added_width + removed_width + 3
+                            ),
+                            palette.dim,
+                        )],
+                    };
+                    Line::from(
+                        counts
+                            .into_iter()
+                            .chain([Span::styled(format!(" {}", file.path), palette.name)])
+                            .collect::<Vec<_>>(),
+                    )
+                }))
+                .collect()
+        }
+    };
+
+    let title = match app.selected_branch() {
+        Some(branch) if stash => format!(" {} · J/K scroll · D closes ", branch.name),
+        Some(branch) => format!(" {} since it forked · J/K scroll · D closes ", branch.name),
+        None => " D closes ".to_string(),
+    };
+    let preview = Paragraph::new(lines).scroll((app.preview_scroll, 0)).block(
+        Block::default()
+            .borders(Borders::LEFT)
+            .border_style(palette.dim)
+            .title(Span::styled(title, palette.dim)),
+    );
+    f.render_widget(preview, area);
+}
+
+/// Bar charts of the branches by age and by merged status, over the list
+fn draw_stats(f: &mut Frame, app: &App, area: Rect) {
+    let palette = app.palette;
+    let stats = app.branch_stats(Utc::now());
+    let base = app.base_branch.as_deref().unwrap_or("the base branch");
+
+    let mut rows: Vec<(&str, usize, Style)> = BranchStats::AGE_LABELS
+        .iter()
+        .zip(stats.ages)
+        .map(|(&label, count)| (label, count, palette.name))
+        .collect();
+    rows.push(("Merged", stats.merged, palette.current));
+    rows.push(("Not merged", stats.unmerged, palette.notice));
+
+    let label_width = rows
+        .iter()
+        .map(|(label, ..)| label.len())
+        .max()
+        .unwrap_or(0);
+    let most = rows.iter().map(|&(_, count, _)| count).max().unwrap_or(0);
+    let count_width = most.to_string().len();
+    // Borders, padding, label, spaces around the bar and the count
+    let bar_room = (area.width as usize).saturating_sub(label_width + count_width + 6);
+    let bar = |count: usize| {
+        // Any branch at all gets at least a sliver
+        let width = if most == 0 {
+            0
+        } else {
+            (count * bar_room).div_ceil(most)
+        };
+        "█".repeat(width)
+    };
+    let row = |(label, count, style): &(&str, usize, Style)| {
+        Line::from(vec![
+            Span::styled(format!(" {:<label_width$} ", label), palette.dim),
+            Span::styled(bar(*count), *style),
+            Span::styled(format!(" {}", count), palette.dim),
+        ])
+    };
+
+    let mut lines = vec![Line::from(Span::styled(" Last commit", palette.selected))];
+    lines.extend(rows[..4].iter().map(row));
+    lines.push(Line::raw(""));
+    lines.push(Line::from(Span::styled(
+        format!(" Merged into {}", base),
+        palette.selected,
+    )));
+    lines.extend(rows[4..].iter().map(row));
+
+    let overview = Paragraph::new(lines).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .border_style(palette.dim)
+            .title(" Local branches · any key to go back "),
+    );
+    f.render_widget(Clear, area);
+    f.render_widget(overview, area);
+}
+
+/// "branch" or "branches", to go after `count`
+fn branches(count: usize) -> &'static str {
+    if count == 1 { "branch" } else { "branches" }
+}
+
+/// Lists exactly what the deletion about to be confirmed will and won't
+/// delete, over the branch list
+fn draw_deletion_summary(f: &mut Frame, app: &mut App, area: Rect) {
+    let palette = app.palette;
+    let (deleted, skipped) = app.deletion_summary();
+    let name_width = deleted
+        .iter()
+        .copied()
+        .chain(skipped.iter().map(|&(name, _)| name))
+        .map(|name| name.chars().count())
+        .max()
+        .unwrap_or(0)
+        .min(app.config.max_name_width);
+
+    let mut lines = vec![Line::from(Span::styled(
+        format!("Delete {} {}", deleted.len(), branches(deleted.len())),
+        palette.danger,
+    ))];
+    let unmerged: HashSet<&str> = app
+        .branches
+        .iter()
+        .filter(|branch| branch.ref_kind == RefKind::Local && !branch.is_merged)
+        .map(|branch| branch.name.as_str())
+        .collect();
+    lines.extend(deleted.iter().map(|name| {
+        let mut spans = vec![Span::styled(
+            format!(
+                "  {:<width$}",
+                truncate(name, name_width),
+                width = name_width
+            ),
+            palette.name,
+        )];
+        if unmerged.contains(name) {
+            spans.push(Span::styled(
+                "  not merged, its commits go too",
+                palette.danger,
+            ));
+        }
+        Line::from(spans)
+    }));
+    if !skipped.is_empty() {
+        lines.push(Line::raw(""));
+        lines.push(Line::from(Span::styled(
+            format!("Keep {} {}", skipped.len(), branches(skipped.len())),
+            palette.dim,
+        )));
+        lines.extend(skipped.iter().map(|(name, reason)| {
+            Line::from(vec![
+                Span::styled(
+                    format!(
+                        "  {:<width$}",
+                        truncate(name, name_width),
+                        width = name_width
+                    ),
+                    palette.name,
+                ),
+                Span::styled(format!("  {}", reason), palette.dim),
+            ])
+        }));
+    }
+
+    // Don't scroll past the end
+    let inner_height = area.height.saturating_sub(2);
+    let max_scroll = (lines.len() as u16).saturating_sub(inner_height);
+    app.summary_scroll = app.summary_scroll.min(max_scroll);
+
+    let summary = Paragraph::new(lines).scroll((app.summary_scroll, 0)).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .border_style(palette.dim)
+            .title(if app.marked_unmerged() {
+                " y to delete all · m only the merged ones · j/k to scroll · any other key to cancel "
+            } else {
+                " y to delete · j/k to scroll · any other key to cancel "
+            }),
+    );
+    f.render_widget(Clear, area);
+    f.render_widget(summary, area);
+}
+
+/// The small `Tab` menu for picking what the list shows, over the middle of
+/// the list
+fn draw_source_menu(f: &mut Frame, palette: &Palette, current: ListSource, highlighted: usize) {
+    let lines: Vec<Line> = ListSource::ALL
+        .iter()
+        .enumerate()
+        .map(|(idx, &source)| {
+            let marker = if source == current { "● " } else { "  " };
+            let style = if idx == highlighted {
+                palette.selected
+            } else {
+                palette.name
+            };
+            Line::from(vec![
+                Span::styled(if idx == highlighted { "❯ " } else { "  " }, palette.accent),
+                Span::styled(marker, palette.current),
+                Span::styled(format!("{}  ", source.key()), palette.prompt),
+                Span::styled(source.label(), style),
+            ])
+        })
+        .collect();
+
+    let area = f.area();
+    let width = (lines.iter().map(Line::width).max().unwrap_or(0) as u16 + 4).min(area.width);
+    let height = (lines.len() as u16 + 2).min(area.height);
+    let menu_area = Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + (area.height - height) / 2,
+        width,
+        height,
+    };
+    let menu = Paragraph::new(lines).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .border_style(palette.dim)
+            .title(" Show "),
+    );
+    f.render_widget(Clear, menu_area);
+    f.render_widget(menu, menu_area);
+}
+
+fn draw_upstream_menu(f: &mut Frame, palette: &Palette, menu: &UpstreamMenu) {
+    let matches = menu.matches();
+    // Scrolled just enough to keep the highlighted one in view
+    let first = (menu.highlighted + 1).saturating_sub(UPSTREAM_MENU_ROWS);
+    let mut lines = vec![Line::from(vec![
+        Span::styled("> ", palette.prompt),
+        Span::styled(menu.query.clone(), palette.filter),
+    ])];
+    lines.extend(
+        matches
+            .iter()
+            .enumerate()
+            .skip(first)
+            .take(UPSTREAM_MENU_ROWS)
+            .map(|(idx, &name)| {
+                let highlighted = idx == menu.highlighted;
+                let marker = if Some(name) == menu.current.as_deref() {
+                    "● "
+                } else {
+                    "  "
+                };
+                let style = if highlighted {
+                    palette.selected
+                } else {
+                    palette.name
+                };
+                Line::from(vec![
+                    Span::styled(if highlighted { "❯ " } else { "  " }, palette.accent),
+                    Span::styled(marker, palette.current),
+                    Span::styled(name.to_string(), style),
+                ])
+            }),
+    );
+    if matches.is_empty() {
+        lines.push(Line::from(Span::styled(
+            "  No remote branch matches",
+            palette.dim,
+        )));
+    }
+    if menu.current.is_some() {
+        lines.push(Line::from(Span::styled(
+            "  delete stops tracking",
+            palette.dim,
+        )));
+    }
+
+    let area = f.area();
+    let title = format!(" Upstream for {} ", menu.branch);
+    let content_width = lines.iter().map(Line::width).max().unwrap_or(0);
+    let width = (content_width.max(title.chars().count()) as u16 + 4).min(area.width);
+    let height = (lines.len() as u16 + 2).min(area.height);
+    let menu_area = Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + (area.height - height) / 2,
+        width,
+        height,
+    };
+    let menu = Paragraph::new(lines).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .border_style(palette.dim)
+            .title(title),
+    );
+    f.render_widget(Clear, menu_area);
+    f.render_widget(menu, menu_area);
+}
+
+/// Runs git with inherited stdio, so pagers and editors get the real terminal
+fn run_git_interactive(args: &[String]) -> Result<ExitStatus> {
+    let mut command = Command::new("git");
+    command.args(args);
+
+    // git defaults to `LESS=FRX`, which quits straight away on short output and
+    // drops us back into the TUI before it could be read
+    if std::env::var_os("LESS").is_none() {
+        command.env("LESS", "R");
+    }
+    Ok(command.status()?)
+}
+
+/// Holds the terminal until `Enter`, for output to be read before the TUI
+/// comes back
+fn wait_for_enter() {
+    eprint!("\nPress Enter to go back to gb");
+    let _ = io::stdin().read_line(&mut String::new());
+}
+
+/// Leaves the TUI for the duration of `f` and restores it afterwards
+fn suspend<B: Backend, T>(
+    terminal: &mut Terminal<B>,
+    inline: bool,
+    f: impl FnOnce() -> T,
+) -> Result<T> {
+    disable_raw_mode()?;
+    // Inline, the picker lives on the main screen, so a pager gets the
+    // alternate one instead of scrolling the picker away
+    if inline {
+        execute!(io::stderr(), EnterAlternateScreen, DisableMouseCapture)?;
+    } else {
+        execute!(io::stderr(), LeaveAlternateScreen, DisableMouseCapture)?;
+    }
+    terminal.show_cursor()?;
+
+    let result = f();
+
+    enable_raw_mode()?;
+    if inline {
+        execute!(io::stderr(), LeaveAlternateScreen, EnableMouseCapture)?;
+    } else {
+        execute!(io::stderr(), EnterAlternateScreen, EnableMouseCapture)?;
+    }
+    terminal.clear()?;
+    Ok(result)
+}
+
+fn run_app<B: Backend + Write>(terminal: &mut Terminal<B>, app: &mut App) -> Result<Outcome> {
+    let mut title = None;
+    loop {
+        // Follows the current branch, which can change under us with `watch_refs`
+        if app.config.terminal_title {
+            let wanted = app.terminal_title();
+            if title.as_ref() != Some(&wanted) {
+                execute!(terminal.backend_mut(), SetTitle(&wanted))?;
+                title = Some(wanted);
+            }
+        }
+        terminal.draw(|f| ui(f, app))?;
+
+        if event::poll(TICK_RATE)? {
+            let handled = match event::read()? {
+                Event::Key(key) if key.kind == KeyEventKind::Press => {
+                    app.error_message = None;
+                    handle_key(app, key)
+                }
+                Event::Mouse(mouse) => handle_mouse(app, mouse),
+                // Repaint the new layout right away instead of on the next tick
+                Event::Resize(_, _) => {
+                    terminal.autoresize()?;
+                    Ok(None)
+                }
+                _ => Ok(None),
+            };
+            match handled {
+                Ok(Some(outcome)) => return Ok(outcome),
+                Ok(None) => {}
+                // A failed action, like a checkout git refused, is
+                // reported in place so something else can be picked
+                Err(err) => app.error_message = Some(format!("{:#}", err)),
+            }
+
+            if let Some(args) = app.pending_git.take() {
+                let result = suspend(terminal, app.inline, || {
+                    let result = run_git_interactive(&args);
+                    // What a failed rebase or merge printed, like the
+                    // conflicts, would be gone as soon as the TUI is back
+                    if Integration::of(&args).is_some()
+                        && !result.as_ref().is_ok_and(ExitStatus::success)
+                    {
+                        wait_for_enter();
+                    }
+                    result
+                })?;
+                app.finish_git(&args, result);
+            }
+        }
+
+        app.tick();
+    }
+}
+
+/// Clicking a row selects it and double-clicking does what `Enter` would.
+/// The wheel scrolls the list, or the preview when it's over it, and a click
+/// on the preview pages it, down in its lower half and up in its upper one.
+fn handle_mouse(app: &mut App, mouse: MouseEvent) -> Result<Option<Outcome>> {
+    // Questions, text entry and the overlays are answered with keys
+    if app.prompt.is_some()
+        || app.input.is_some()
+        || app.show_stats
+        || app.source_menu.is_some()
+        || app.upstream_menu.is_some()
+    {
+        return Ok(None);
+    }
+
+    let position = Position::new(mouse.column, mouse.row);
+    let preview = app.preview_area.filter(|area| area.contains(position));
+    match (mouse.kind, preview) {
+        (MouseEventKind::ScrollDown, Some(_)) => app.scroll_preview(MOUSE_SCROLL_LINES as i16),
+        (MouseEventKind::ScrollUp, Some(_)) => app.scroll_preview(-(MOUSE_SCROLL_LINES as i16)),
+        (MouseEventKind::ScrollDown, None) => app.scroll_lines(MOUSE_SCROLL_LINES),
+        (MouseEventKind::ScrollUp, None) => app.scroll_lines(-MOUSE_SCROLL_LINES),
+        (MouseEventKind::Down(MouseButton::Left), Some(area)) => {
+            let page = area.height.saturating_sub(1).max(1) as i16;
+            if mouse.row >= area.y + area.height / 2 {
+                app.scroll_preview(page);
+            } else {
+                app.scroll_preview(-page);
+            }
+        }
+        (MouseEventKind::Down(MouseButton::Left), None) => {
+            let Some(idx) = app.branch_at(position) else {
+                return Ok(None);
+            };
+            let now = Instant::now();
+            let double_click = app
+                .last_click
+                .is_some_and(|(clicked, at)| clicked == idx && now - at <= DOUBLE_CLICK);
+            app.list_state.select(Some(idx));
+            if double_click {
+                app.last_click = None;
+                return activate(app);
+            }
+            app.last_click = Some((idx, now));
+        }
+        _ => {}
+    }
+    Ok(None)
+}
+
+fn handle_key(app: &mut App, key: KeyEvent) -> Result<Option<Outcome>> {
+    if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+        return Ok(Some(Outcome::Cancelled));
+    }
+    // Typed text is taken as it is, remapped keys or not
+    let key = if app.input.is_none() && !app.filter_focused {
+        app.keymap.apply(key)
+    } else {
+        key
+    };
+    let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+    let alt = key.modifiers.contains(KeyModifiers::ALT);
+
+    if let Some(input) = &mut app.input {
+        match key.code {
+            KeyCode::Esc => app.input = None,
+            KeyCode::Enter => {
+                if let Some(input) = app.input.take() {
+                    return app.submit_input(input);
+                }
+            }
+            KeyCode::Backspace => {
+                input.value.pop();
+            }
+            KeyCode::Char(c) if !ctrl => input.value.push(c),
+            _ => {}
+        }
+        return Ok(None);
+    }
+
+    // Any key other than `y` dismisses an open prompt, except for scrolling
+    // through the deletion summary, deleting only the merged branches and
+    // stashing or merging before a checkout
+    if let Some(prompt) = app.prompt.take() {
+        match key.code {
+            KeyCode::Char('y') => {
+                return match prompt {
+                    Prompt::DeleteMarked => app.delete_marked(false),
+                    Prompt::RetryLocked | Prompt::DirtyCheckout => checkout(app),
+                    Prompt::DetachedCheckout => checkout_unless_dirty(app),
+                    Prompt::DeleteSelected => {
+                        app.delete_selected(false)?;
+                        Ok(None)
+                    }
+                    Prompt::DeleteUnmerged => {
+                        app.prompt = Some(Prompt::ForceDeleteSelected);
+                        Ok(None)
+                    }
+                    Prompt::ForceDeleteSelected => {
+                        app.delete_selected(true)?;
+                        Ok(None)
+                    }
+                    Prompt::CreateBranch => app.create_from_filter(),
+                    Prompt::Integrate(integration) => {
+                        app.integrate(integration);
+                        Ok(None)
+                    }
+                    Prompt::DropStash => {
+                        app.drop_selected_stash()?;
+                        Ok(None)
+                    }
+                };
+            }
+            KeyCode::Char('m') if prompt == Prompt::DeleteMarked => return app.delete_marked(true),
+            KeyCode::Char(c @ ('s' | 'p')) if prompt == Prompt::DirtyCheckout => {
+                return stash_and_checkout(app, c == 'p');
+            }
+            KeyCode::Char('m') if prompt == Prompt::DirtyCheckout => {
+                return merge_and_checkout(app);
+            }
+            KeyCode::Down | KeyCode::Char('j') if prompt == Prompt::DeleteMarked => {
+                app.summary_scroll = app.summary_scroll.saturating_add(1);
+                app.prompt = Some(prompt);
+            }
+            KeyCode::Up | KeyCode::Char('k') if prompt == Prompt::DeleteMarked => {
+                app.summary_scroll = app.summary_scroll.saturating_sub(1);
+                app.prompt = Some(prompt);
+            }
+            _ => {}
+        }
+        return Ok(None);
+    }
+
+    if app.show_stats {
+        app.show_stats = false;
+        return Ok(None);
+    }
+
+    if let Some(highlighted) = app.source_menu {
+        let last = ListSource::ALL.len() - 1;
+        match key.code {
+            KeyCode::Esc | KeyCode::Tab | KeyCode::Char('q') => app.source_menu = None,
+            KeyCode::Down | KeyCode::Char('j') => {
+                app.source_menu = Some(if highlighted == last {
+                    0
+                } else {
+                    highlighted + 1
+                })
+            }
+            KeyCode::Up | KeyCode::Char('k') => {
+                app.source_menu = Some(if highlighted == 0 {
+                    last
+                } else {
+                    highlighted - 1
+                })
+            }
+            KeyCode::Enter => app.switch_source(ListSource::ALL[highlighted])?,
+            KeyCode::Char(c) => {
+                if let Some(&source) = ListSource::ALL.iter().find(|source| source.key() == c) {
+                    app.switch_source(source)?;
+                }
+            }
+            _ => {}
+        }
+        return Ok(None);
+    }
+
+    if let Some(menu) = &mut app.upstream_menu {
+        let last = menu.matches().len().saturating_sub(1);
+        match key.code {
+            KeyCode::Esc => app.upstream_menu = None,
+            KeyCode::Down => menu.highlighted = (menu.highlighted + 1).min(last),
+            KeyCode::Char('n') if ctrl => menu.highlighted = (menu.highlighted + 1).min(last),
+            KeyCode::Up => menu.highlighted = menu.highlighted.saturating_sub(1),
+            KeyCode::Char('p') if ctrl => menu.highlighted = menu.highlighted.saturating_sub(1),
+            KeyCode::Enter => {
+                let branch = menu.branch.clone();
+                match menu
+                    .matches()
+                    .get(menu.highlighted)
+                    .map(|name| name.to_string())
+                {
+                    Some(upstream) => app.finish_upstream(&branch, Some(&upstream))?,
+                    None => app.upstream_menu = None,
+                }
+            }
+            // Stops tracking the one it tracks now
+            KeyCode::Delete if menu.current.is_some() => {
+                let branch = menu.branch.clone();
+                app.finish_upstream(&branch, None)?;
+            }
+            KeyCode::Backspace => {
+                menu.query.pop();
+                menu.highlighted = 0;
+            }
+            KeyCode::Char(c) if !ctrl => {
+                menu.query.push(c);
+                menu.highlighted = 0;
+            }
+            _ => {}
+        }
+        return Ok(None);
+    }
+
+    // Emacs-style, so the selection can move without leaving the filter
+    if ctrl {
+        match key.code {
+            // Works while typing too, keeping the filter text
+            KeyCode::Char('r') => {
+                app.reload()?;
+                return Ok(None);
+            }
+            KeyCode::Char('n') => {
+                app.next();
+                return Ok(None);
+            }
+            KeyCode::Char('p') => {
+                app.previous();
+                return Ok(None);
+            }
+            _ => {}
+        }
+    }
+
+    if app.filter_focused {
+        match key.code {
+            KeyCode::Esc => app.filter_focused = false,
+            KeyCode::Tab => app.open_source_menu(),
+            KeyCode::Char('b') if ctrl => return app.create_from_filter(),
+            KeyCode::Enter => return activate(app),
+            KeyCode::Down => app.next(),
+            KeyCode::Up => app.previous(),
+            KeyCode::PageDown => app.scroll_page(true),
+            KeyCode::PageUp => app.scroll_page(false),
+            KeyCode::Backspace => app.remove_char(),
+            KeyCode::Char(c) if !ctrl => app.add_char(c),
+            _ => {}
+        }
+        return Ok(None);
+    }
+
+    match key.code {
+        KeyCode::Char('q') | KeyCode::Esc => return Ok(Some(Outcome::Quit)),
+        KeyCode::Down | KeyCode::Char('j') => app.next(),
+        KeyCode::Up | KeyCode::Char('k') => app.previous(),
+        KeyCode::Char('e') if ctrl => app.scroll_lines(1),
+        KeyCode::Char('y') if ctrl => app.scroll_lines(-1),
+        KeyCode::Char('d') if ctrl => app.scroll_half_page(true),
+        KeyCode::Char('u') if ctrl => app.scroll_half_page(false),
+        KeyCode::PageDown => app.scroll_page(true),
+        KeyCode::PageUp => app.scroll_page(false),
+        KeyCode::Char('/') => app.filter_focused = true,
+        KeyCode::Tab => app.open_source_menu(),
+        KeyCode::Char('b') if ctrl => return app.create_from_filter(),
+        KeyCode::Char('F') | KeyCode::F(5) => app.start_fetch(),
+        KeyCode::Char('S') => app.show_selected(),
+        KeyCode::Char('D') => app.toggle_preview(PreviewKind::DiffStat),
+        KeyCode::Char('w') => return app.start_worktree(),
+        // Only until something's typed, as branch names are full of dashes
+        KeyCode::Char('-') if app.filter.is_empty() => return app.switch_back(),
+        KeyCode::Char('r') if alt => app.start_integration(Integration::Rebase),
+        KeyCode::Char('m') if alt => app.start_integration(Integration::Merge),
+        KeyCode::Char('r') => app.start_rename(),
+        KeyCode::Char('d') => app.start_delete()?,
+        KeyCode::Char('s') if ctrl => app.cycle_sort_mode(),
+        KeyCode::Char('s') => app.toggle_stashes()?,
+        KeyCode::Char('p') if app.source == ListSource::Stashes => {
+            return app.pop_selected_stash();
+        }
+        KeyCode::Char('O') => app.reverse_order(),
+        KeyCode::Char('P') => app.toggle_full_names(),
+        // Never part of a branch name, so it's safe to take from the filter
+        KeyCode::Char('*') => app.toggle_favorite()?,
+        KeyCode::Char('H') => app.toggle_date_headers(),
+        KeyCode::Char('T') => app.toggle_tree_view(),
+        KeyCode::Left if app.shows_tree() => app.collapse_group(),
+        KeyCode::Right if app.shows_tree() => app.expand_group(),
+        KeyCode::Char('U') => app.toggle_unpushed_only(),
+        KeyCode::Char('G') => app.show_stats = true,
+        KeyCode::Char('L') => app.toggle_preview(PreviewKind::Commits),
+        KeyCode::Char('J') if app.show_preview => app.scroll_preview(1),
+        KeyCode::Char('K') if app.show_preview => app.scroll_preview(-1),
+        KeyCode::Char('y') => app.copy_name(),
+        KeyCode::Char('Y') => app.copy_checkout_command(),
+        KeyCode::Char('i' | 'I') if alt => app.copy_tip_hash(true)?,
+        KeyCode::Char('I') => app.copy_tip_hash(false)?,
+        KeyCode::Char(' ') => app.toggle_mark(),
+        KeyCode::Char('X') => app.start_delete_marked(),
+        KeyCode::Char('M') => app.toggle_prune_merged()?,
+        KeyCode::Char('u') => app.open_upstream_menu()?,
+        KeyCode::Enter => return activate(app),
+        KeyCode::Backspace => app.remove_char(),
+        KeyCode::Char(c) if !ctrl => app.add_char(c),
+        _ => {}
+    }
+    Ok(None)
+}
+
+/// What `Enter` does: check out the selection, or delete the marked
+/// branches in cleanup mode
+fn activate(app: &mut App) -> Result<Option<Outcome>> {
+    if app.selected_group().is_some() {
+        app.expand_group();
+        return Ok(None);
+    }
+
+    // Printing changes nothing, so read-only mode allows it, and the current
+    // branch is as good a pick as any
+    if app.print {
+        let picked = app.selected_branch().map(|branch| branch.name.clone());
+        if picked.is_none() {
+            app.status_message = Some(app.no_match_hint());
+        }
+        return Ok(picked.map(Outcome::Picked));
+    }
+
+    if !app.checkouts_listed.is_empty() {
+        return app.checkout_across_repos();
+    }
+
+    // The enter command is refused too, since it could do anything
+    if app.refuses_changes() {
+        return Ok(None);
+    }
+
+    if app.cleanup {
+        // Cleanup only lists merged branches, so there's nothing to lose
+        if !app.config.confirm_merged_deletes && !app.marked.is_empty() {
+            return app.delete_marked(false);
+        }
+        app.start_delete_marked();
+        return Ok(None);
+    }
+
+    if let Some(template) = &app.config.enter_command {
+        return Ok(Some(match app.selected_branch() {
+            Some(branch) => Outcome::RunCommand(expand_template(template, &branch.name)),
+            None => Outcome::Quit,
+        }));
+    }
+
+    // A filter that matches nothing is likely the name of a branch to be
+    if app.selected_branch().is_none() && !app.filter.is_empty() {
+        if app.offers_create() {
+            app.prompt = Some(Prompt::CreateBranch);
+        } else {
+            app.status_message = Some(app.no_match_hint());
+        }
+        return Ok(None);
+    }
+
+    // Staying open says more than quitting without having done anything
+    if let Some(branch) = app.selected_branch()
+        && branch.is_current
+    {
+        app.status_message = Some(format!("Already on {}", branch.name));
+        return Ok(None);
+    }
+
+    // Git won't check a branch out in two places, so offer the other one
+    if let Some(branch) = app.selected_branch()
+        && let Some(path) = app.in_other_worktree(branch)
+    {
+        return Ok(Some(Outcome::Worktree {
+            branch: branch.name.clone(),
+            path: path.clone(),
+            created: false,
+        }));
+    }
+
+    // New commits on a detached HEAD belong to no branch, easily lost
+    if let Some(branch) = app.selected_branch()
+        && branch.ref_kind == RefKind::Tag
+    {
+        app.prompt = Some(Prompt::DetachedCheckout);
+        return Ok(None);
+    }
+    checkout_unless_dirty(app)
+}
+
+/// Checks out the selected entry, first asking when there are uncommitted
+/// changes that would come along
+fn checkout_unless_dirty(app: &mut App) -> Result<Option<Outcome>> {
+    // Looked at afresh, as the tree may have changed since the list was read
+    app.dirty = is_dirty(&open_repo()?);
+    if app.dirty && app.config.confirm_dirty_checkout {
+        app.prompt = Some(Prompt::DirtyCheckout);
+        return Ok(None);
+    }
+    checkout(app)
+}
+
+/// Stashes the uncommitted changes before checking out the selected branch,
+/// popping them onto it afterwards when `pop`
+fn stash_and_checkout(app: &mut App, pop: bool) -> Result<Option<Outcome>> {
+    let Some(branch) = app.selected_branch().filter(|branch| !branch.is_current) else {
+        return Ok(None);
+    };
+    if branch.ref_kind == RefKind::Stash {
+        app.status_message =
+            Some("Stashes are applied over the changes, not stashed for".to_string());
+        return Ok(None);
+    }
+
+    let (kind, name) = (branch.ref_kind, branch.name.clone());
+    let (checked_out, note) = stash_and_switch(&mut open_repo()?, kind, &name, pop)?;
+    app.exit_note = note;
+    Ok(Some(Outcome::CheckedOut(checked_out)))
+}
+
+/// Checks out the selected branch with the uncommitted changes merged in,
+/// leaving conflict markers where they clash with it
+fn merge_and_checkout(app: &mut App) -> Result<Option<Outcome>> {
+    let Some(branch) = app.selected_branch().filter(|branch| !branch.is_current) else {
+        return Ok(None);
+    };
+    if branch.ref_kind == RefKind::Stash {
+        app.status_message = Some("Stashes are applied over the changes, not merged".to_string());
+        return Ok(None);
+    }
+
+    let (kind, name) = (branch.ref_kind, branch.name.clone());
+    let (checked_out, note) = merge_and_switch(&mut open_repo()?, kind, &name)?;
+    app.exit_note = note;
+    Ok(Some(Outcome::CheckedOut(checked_out)))
+}
+
+/// Checks out the selected branch, offering to retry if the repository is
+/// locked
+fn checkout(app: &mut App) -> Result<Option<Outcome>> {
+    match app.checkout_selected() {
+        Ok(outcome) => Ok(Some(outcome.unwrap_or(Outcome::Quit))),
+        // Usually gone in a moment, so offer to try again rather than fail
+        Err(err) => match err.downcast::<RepoLocked>() {
+            Ok(locked) => {
+                app.lock_file = Some(locked.lock_file);
+                app.prompt = Some(Prompt::RetryLocked);
+                Ok(None)
+            }
+            Err(err) => Err(err),
+        },
+    }
+}
+
+/// Draws the picker until it's done with, putting the terminal back
+/// afterwards whatever the outcome
+fn run_picker(app: &mut App) -> Result<Result<Outcome>> {
+    // Draw on stderr so stdout stays free for output like `--print-path`
+    enable_raw_mode()?;
+    let mut stderr = io::stderr();
+    let viewport = if app.inline {
+        execute!(stderr, EnableMouseCapture)?;
+        Viewport::Inline(INLINE_HEIGHT)
+    } else {
+        execute!(stderr, EnterAlternateScreen, EnableMouseCapture)?;
+        Viewport::Fullscreen
+    };
+    if app.config.terminal_title {
+        // Terminals that keep a stack of titles save the current one, to
+        // put back on exit; others ignore this
+        write!(stderr, "\x1b[22;0t")?;
+    }
+    let backend = CrosstermBackend::new(stderr);
+    let mut terminal = Terminal::with_options(backend, TerminalOptions { viewport })?;
+
+    let res = run_app(&mut terminal, app);
+
+    disable_raw_mode()?;
+    if app.config.terminal_title {
+        write!(terminal.backend_mut(), "\x1b[23;0t")?;
+    }
+    if app.inline {
+        // Leave the last frame in the scrollback, with anything printed below it
+        let bottom = terminal.get_frame().area().bottom();
+        terminal.set_cursor_position((0, bottom.saturating_sub(1)))?;
+        execute!(terminal.backend_mut(), DisableMouseCapture)?;
+        eprintln!();
+    } else {
+        execute!(
+            terminal.backend_mut(),
+            LeaveAlternateScreen,
+            DisableMouseCapture
+        )?;
+    }
+    terminal.show_cursor()?;
+
+    if app.remembers_view()
+        && let Err(err) = app.save_view()
+    {
+        eprintln!("Failed to remember the list position: {:#}", err);
+    }
+    Ok(res)
+}
+
+fn main() -> Result<ExitCode> {
+    let args = Args::parse();
+
+    if let Some(CliCommand::Init { shell }) = args.command {
+        print!("{}", shell::init_script(shell));
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if args.read_only && (args.command.is_some() || args.checkout_last) {
+        report_error(
+            &anyhow!("--read-only only opens the picker, it can't switch branches"),
+            args.verbose,
+        );
+        return Ok(ExitCode::FAILURE);
+    }
+    if let Some(CliCommand::Switch { name }) = &args.command {
+        return Ok(run_command(args.verbose, || switch_branch(name)));
+    }
+    if args.checkout_last {
+        return Ok(run_command(args.verbose, checkout_last));
+    }
+    if args.current {
+        return Ok(run_command(args.verbose, print_current));
+    }
+    if args.jsonl {
+        return Ok(run_command(args.verbose, || print_jsonl(&args)));
+    }
+
+    // Read everything up front, so a bad config or running outside a
+    // repository fails cleanly without ever touching the terminal
+    let mut app = match Config::load().and_then(|config| App::new(config, &args)) {
+        Ok(app) => app,
+        Err(err) => {
+            report_error(&err, args.verbose);
+            return Ok(ExitCode::FAILURE);
+        }
+    };
+
+    if args.list {
+        return Ok(run_command(args.verbose, || print_list(&app, args.json)));
+    }
+
+    // A pattern naming a single branch goes to it without the picker, but
+    // across repositories it only filters, as the picker stays open there
+    let picked = match args.pattern {
+        Some(_) if args.repos.is_none() => app.pick_sole_match(),
+        _ => Ok(None),
+    };
+    let res = match picked {
+        Ok(Some(outcome)) => Ok(outcome),
+        Ok(None) => run_picker(&mut app)?,
+        Err(err) => Err(err),
+    };
+
+    match res {
+        Ok(Outcome::CheckedOut(branch)) => {
+            if let Some(note) = &app.exit_note {
+                eprintln!("{}", note);
+            }
+            if let Err(err) = remember_checkout(app.current_branch.as_deref(), &branch) {
+                eprintln!("Failed to remember the checkout: {:#}", err);
+            }
+            match args
+                .then
+                .as_ref()
+                .or(app.config.post_checkout_command.as_ref())
+            {
+                Some(command) => run_shell(command, Some(&branch)),
+                None => Ok(ExitCode::SUCCESS),
+            }
+        }
+        Ok(Outcome::RunCommand(command)) => run_shell(&command, None),
+        Ok(Outcome::StashApplied(name)) => {
+            println!("Applied {}", name);
+            Ok(ExitCode::SUCCESS)
+        }
+        Ok(Outcome::StashPopped(name)) => {
+            println!("Applied and dropped {}", name);
+            Ok(ExitCode::SUCCESS)
+        }
+        Ok(Outcome::Deleted(names)) => {
+            for name in names {
+                println!("Deleted branch {}", name);
+            }
+            Ok(ExitCode::SUCCESS)
+        }
+        Ok(Outcome::Worktree {
+            branch,
+            path,
+            created,
+        }) => {
+            if let Some(file) = &args.print_path_to {
+                std::fs::write(file, path.as_os_str().as_encoded_bytes())
+                    .map_err(|err| anyhow!("Failed to write {}: {}", file.display(), err))?;
+            }
+            if args.print_path {
+                println!("{}", path.display());
+            } else if created {
+                println!("Added a worktree for {} at {}", branch, path.display());
+            } else {
+                println!("{} is already checked out at {}", branch, path.display());
+            }
+            Ok(ExitCode::SUCCESS)
+        }
+        Ok(Outcome::Picked(name)) => {
+            println!("{}", name);
+            Ok(ExitCode::SUCCESS)
+        }
+        // With `--print`, so a shell function can tell a pick from giving up
+        Ok(Outcome::Quit) if args.print => Ok(ExitCode::FAILURE),
+        Ok(Outcome::Quit) => Ok(ExitCode::SUCCESS),
+        Ok(Outcome::Cancelled) => Ok(ExitCode::from(130)),
+        Err(err) => {
+            report_error(&err, args.verbose);
+            Ok(ExitCode::FAILURE)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use gb::RECENT_LIMIT;
+    use std::cell::RefCell;
+    use test_repo::TempRepo;
+
+    thread_local! {
+        /// What `open_repo` opens in the test on this thread, instead of the
+        /// working directory's repository
+        pub static OPEN_REPO: RefCell<Option<PathBuf>> = const { RefCell::new(None) };
+    }
+
+    fn branch(name: &str, days_ago: i64) -> GitBranch {
+        GitBranch {
+            name: name.to_string(),
+            ref_kind: RefKind::Local,
+            is_current: false,
+            tip: None,
+            last_commit_time: Utc.with_ymd_and_hms(2024, 1, 31, 12, 0, 0).unwrap()
+                - chrono::Duration::days(days_ago),
+            description: None,
+            has_stash: false,
+            is_protected: false,
+            ahead_behind: None,
+            upstream: None,
+            upstream_gone: false,
+            last_reflog_time: None,
+            tip_signed: false,
+            author: None,
+            subject: None,
+            is_merged: false,
+            is_unborn: false,
+            not_a_commit: false,
+        }
+    }
+
+    fn selected_name(app: &App) -> Option<&str> {
+        app.selected_branch().map(|branch| branch.name.as_str())
+    }
+
+    /// Runs git in `dir` with fixed dates and no user config, for test repos
+    fn git(dir: &Path, args: &[&str], date: &str) {
+        let status = Command::new("git")
+            .args(["-c", "user.name=gb", "-c", "user.email=gb@example.com"])
+            .args(args)
+            .current_dir(dir)
+            .env("GIT_CONFIG_NOSYSTEM", "1")
+            .env("GIT_CONFIG_GLOBAL", "/dev/null")
+            .env("GIT_AUTHOR_DATE", date)
+            .env("GIT_COMMITTER_DATE", date)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .unwrap();
+        assert!(status.success(), "git {args:?} failed");
+    }
+
+    fn listed_branches(dir: &Path) -> Vec<(String, bool, Option<Oid>, DateTime<Utc>)> {
+        let mut app = App::with_branches(Vec::new(), Config::default());
+        app.load_branches(&mut Repository::open(dir).unwrap())
+            .unwrap();
+        app.branches
+            .into_iter()
+            .map(|b| (b.name, b.is_current, b.tip, b.last_commit_time))
+            .collect()
+    }
+
+    #[test]
+    fn packed_refs_list_the_same_branches() {
+        let dir = TempRepo::new("packed-refs");
+
+        git(&dir, &["init", "-q", "-b", "main"], "2024-01-01T12:00:00Z");
+        git(
+            &dir,
+            &["commit", "-q", "--allow-empty", "-m", "one"],
+            "2024-01-01T12:00:00Z",
+        );
+        git(&dir, &["branch", "feature/old"], "2024-01-01T12:00:00Z");
+        git(
+            &dir,
+            &["checkout", "-q", "-b", "feature/new"],
+            "2024-01-02T12:00:00Z",
+        );
+        git(
+            &dir,
+            &["commit", "-q", "--allow-empty", "-m", "two"],
+            "2024-01-03T12:00:00Z",
+        );
+        git(&dir, &["checkout", "-q", "main"], "2024-01-04T12:00:00Z");
+
+        let loose = listed_branches(&dir);
+        assert_eq!(
+            loose
+                .iter()
+                .map(|(name, ..)| name.as_str())
+                .collect::<Vec<_>>(),
+            ["feature/new", "feature/old", "main"]
+        );
+        assert_eq!(
+            loose[0].3,
+            Utc.with_ymd_and_hms(2024, 1, 3, 12, 0, 0).unwrap()
+        );
+        assert!(loose[2].1, "main should be current");
+
+        git(&dir, &["pack-refs", "--all"], "2024-01-04T12:00:00Z");
+        let heads = std::fs::read_dir(dir.join(".git/refs/heads"))
+            .unwrap()
+            .flatten()
+            .filter(|entry| entry.file_type().is_ok_and(|kind| kind.is_file()))
+            .count();
+        assert_eq!(heads, 0, "all branches should be packed");
+
+        let packed = listed_branches(&dir);
+        assert_eq!(packed, loose);
+    }
+
+    #[test]
+    fn reversing_keeps_the_selection() {
+        let mut app = App::with_branches(
+            vec![branch("zebra", 0), branch("apple", 1), branch("mango", 2)],
+            Config::default(),
+        );
+        app.next();
+        assert_eq!(selected_name(&app), Some("apple"));
+
+        app.reverse_order();
+        let names: Vec<&str> = app.branches.iter().map(|b| b.name.as_str()).collect();
+        assert_eq!(names, ["mango", "apple", "zebra"]);
+        assert_eq!(selected_name(&app), Some("apple"));
+        assert_eq!(
+            app.status_message.as_deref(),
+            Some("Sorted by recent, oldest first")
+        );
+
+        // The direction sticks when the sort mode changes
+        app.sort_mode = SortMode::Frecency;
+        app.cycle_sort_mode();
+        let names: Vec<&str> = app.branches.iter().map(|b| b.name.as_str()).collect();
+        assert_eq!(names, ["zebra", "mango", "apple"]);
+        assert_eq!(selected_name(&app), Some("apple"));
+    }
+
+    #[test]
+    fn frecency_puts_the_branches_checked_out_most_first() {
+        let mut app = App::with_branches(
+            vec![branch("new", 0), branch("habit", 5), branch("old", 9)],
+            Config::default(),
+        );
+        let now = Utc::now();
+        let mut state = state::RepoState::default();
+        for days in [1, 2, 3] {
+            let at = now - chrono::Duration::days(days);
+            state.record_checkout(Some("new"), "habit", at.timestamp());
+        }
+        assert_eq!(state.previous_branch.as_deref(), Some("new"));
+        app.checkouts = [("habit".to_string(), vec![now; 3])].into();
+
+        app.sort_mode = SortMode::Frecency;
+        app.resort();
+        let names: Vec<&str> = app.branches.iter().map(|b| b.name.as_str()).collect();
+        assert_eq!(names, ["habit", "new", "old"]);
+
+        // Only the latest few checkouts are kept
+        for _ in 0..20 {
+            state.record_checkout(None, "habit", now.timestamp());
+        }
+        assert_eq!(
+            state.checkouts["habit"],
+            vec![now.timestamp(); state::CHECKOUTS_KEPT]
+        );
+    }
+
+    #[test]
+    fn favorites_stay_on_top_in_every_sort() {
+        let mut app = App::with_branches(
+            vec![
+                branch("zebra", 0),
+                branch("apple", 1),
+                branch("mango", 2),
+                branch("kiwi", 3),
+            ],
+            Config::default(),
+        );
+        app.favorites = ["mango".to_string(), "zebra".to_string()].into();
+        app.resort();
+        let names: Vec<&str> = app.branches.iter().map(|b| b.name.as_str()).collect();
+        assert_eq!(names, ["zebra", "mango", "apple", "kiwi"]);
+
+        app.reverse_order();
+        let names: Vec<&str> = app.branches.iter().map(|b| b.name.as_str()).collect();
+        assert_eq!(names, ["mango", "zebra", "kiwi", "apple"]);
+
+        app.reverse_order();
+        app.sort_mode = SortMode::Frecency;
+        app.cycle_sort_mode();
+        let names: Vec<&str> = app.branches.iter().map(|b| b.name.as_str()).collect();
+        assert_eq!(names, ["mango", "zebra", "apple", "kiwi"]);
+    }
+
+    #[test]
+    fn the_view_scrolls_just_far_enough_to_show_the_selection() {
+        let config = Config {
+            limit: 0,
+            scroll_margin: 1,
+            ..Config::default()
+        };
+        let mut app = App::with_branches(
+            (0..20)
+                .map(|days| branch(&format!("b{days}"), days))
+                .collect(),
+            config,
+        );
+        app.list_height = 5;
+
+        assert_eq!(app.view_offset(Some(3)), 0);
+        // With a row of margin below the selection
+        assert_eq!(app.view_offset(Some(10)), 7);
+        *app.list_state.offset_mut() = 7;
+        assert_eq!(app.view_offset(Some(9)), 7);
+        assert_eq!(app.view_offset(Some(2)), 1);
+        // No margin past the last row
+        assert_eq!(app.view_offset(Some(19)), 15);
+        assert_eq!(app.view_offset(None), 7);
+    }
+
+    #[test]
+    fn scrolling_drags_the_selection_only_when_it_leaves_the_view() {
+        let mut app = App::with_branches(
+            (0..10)
+                .map(|days| branch(&format!("b{days}"), days))
+                .collect(),
+            Config::default(),
+        );
+        app.list_height = 4;
+
+        app.next();
+        app.scroll_lines(1);
+        assert_eq!(app.list_state.offset(), 1);
+        assert_eq!(selected_name(&app), Some("b1"));
+        app.scroll_lines(1);
+        assert_eq!(app.list_state.offset(), 2);
+        assert_eq!(selected_name(&app), Some("b2"));
+
+        // Never past the last page
+        for _ in 0..10 {
+            app.scroll_lines(1);
+        }
+        assert_eq!(app.list_state.offset(), 6);
+        assert_eq!(selected_name(&app), Some("b6"));
+        app.scroll_lines(-3);
+        assert_eq!(app.list_state.offset(), 3);
+        assert_eq!(selected_name(&app), Some("b6"));
+
+        app.scroll_half_page(true);
+        assert_eq!(app.list_state.offset(), 5);
+        assert_eq!(selected_name(&app), Some("b8"));
+        app.scroll_half_page(true);
+        assert_eq!(app.list_state.offset(), 6);
+        assert_eq!(selected_name(&app), Some("b9"));
+        app.scroll_half_page(false);
+        assert_eq!(app.list_state.offset(), 4);
+        assert_eq!(selected_name(&app), Some("b7"));
+
+        // A page at a time, stopping at the ends rather than wrapping
+        app.scroll_page(false);
+        assert_eq!(app.list_state.offset(), 0);
+        assert_eq!(selected_name(&app), Some("b3"));
+        app.scroll_page(false);
+        assert_eq!(selected_name(&app), Some("b0"));
+        app.scroll_page(true);
+        assert_eq!(selected_name(&app), Some("b4"));
+        app.scroll_page(true);
+        app.scroll_page(true);
+        assert_eq!(app.list_state.offset(), 6);
+        assert_eq!(selected_name(&app), Some("b9"));
+    }
+
+    #[test]
+    fn current_first_lists_the_current_branch_on_top_but_selects_below_it() {
+        let mut current = branch("main", 5);
+        current.is_current = true;
+        let config = Config {
+            current_first: true,
+            ..Config::default()
+        };
+        let mut app = App::with_branches(
+            vec![branch("zebra", 0), current, branch("apple", 1)],
+            config,
+        );
+        let names: Vec<&str> = app.branches.iter().map(|b| b.name.as_str()).collect();
+        assert_eq!(names, ["main", "zebra", "apple"]);
+        assert_eq!(selected_name(&app), Some("zebra"));
+
+        app.sort_mode = SortMode::Frecency;
+        app.cycle_sort_mode();
+        let names: Vec<&str> = app.branches.iter().map(|b| b.name.as_str()).collect();
+        assert_eq!(names, ["main", "apple", "zebra"]);
+    }
+
+    #[test]
+    fn enter_on_the_current_branch_stays_open() {
+        let mut current = branch("main", 0);
+        current.is_current = true;
+        let mut app = App::with_branches(vec![current, branch("apple", 1)], Config::default());
+
+        assert!(matches!(activate(&mut app), Ok(None)));
+        assert_eq!(app.status_message.as_deref(), Some("Already on main"));
+    }
+
+    #[test]
+    fn deletion_summary_says_why_branches_are_kept() {
+        let mut protected = branch("release/1.0", 2);
+        protected.is_protected = true;
+        let mut app = App::with_branches(
+            vec![branch("merged-a", 0), branch("merged-b", 1), protected],
+            Config::default(),
+        );
+        app.cleanup = true;
+        app.marked = ["merged-b".to_string()].into();
+        app.cleanup_skipped = vec![
+            ("main".to_string(), "checked out"),
+            ("wip".to_string(), "not merged"),
+        ];
+
+        let (deleted, skipped) = app.deletion_summary();
+        assert_eq!(deleted, ["merged-b"]);
+        assert_eq!(
+            skipped,
+            [
+                ("merged-a", "not marked"),
+                ("release/1.0", "protected"),
+                ("main", "checked out"),
+                ("wip", "not merged"),
+            ]
+        );
+        assert_eq!(
+            format!("Delete {} {}", deleted.len(), branches(deleted.len())),
+            "Delete 1 branch"
+        );
+        assert_eq!(branches(skipped.len()), "branches");
+    }
+
+    #[test]
+    fn allow_protected_lifts_the_protection() {
+        let mut protected = branch("release/1.0", 1);
+        protected.is_protected = true;
+        let mut app = App::with_branches(vec![branch("main", 0), protected], Config::default());
+        app.select_branch("release/1.0");
+
+        app.toggle_mark();
+        assert_eq!(
+            app.status_message.take().as_deref(),
+            Some("release/1.0 is protected and can't be deleted")
+        );
+        app.start_rename();
+        assert!(app.input.is_none());
+
+        app.allow_protected = true;
+        app.toggle_mark();
+        assert_eq!(app.deletion_summary().0, ["release/1.0"]);
+        app.start_rename();
+        assert!(app.input.is_some());
+    }
+
+    #[test]
+    fn cleanup_marks_protected_branches_when_allowed() {
+        let mut protected = branch("release/1.0", 1);
+        protected.is_protected = true;
+        let mut app = App::with_branches(vec![branch("old", 0), protected], Config::default());
+
+        app.mark_for_cleanup();
+        assert_eq!(app.marked, HashSet::from(["old".to_string()]));
+        app.allow_protected = true;
+        app.mark_for_cleanup();
+        assert_eq!(app.marked.len(), 2);
+    }
+
+    #[test]
+    fn marks_local_branches_to_delete_together() {
+        let mut current = branch("main", 0);
+        current.is_current = true;
+        current.is_merged = true;
+        let mut merged = branch("merged", 1);
+        merged.is_merged = true;
+        let mut tag = branch("v1.0", 2);
+        tag.ref_kind = RefKind::Tag;
+        let mut app = App::with_branches(
+            vec![current, merged, branch("wip", 3), tag],
+            Config::default(),
+        );
+        let mark = |app: &mut App, name: &str| {
+            app.select_branch(name);
+            app.toggle_mark();
+            app.status_message.take()
+        };
+
+        assert_eq!(
+            mark(&mut app, "main").as_deref(),
+            Some("main is checked out and can't be deleted")
+        );
+        assert_eq!(
+            mark(&mut app, "v1.0").as_deref(),
+            Some("Only local branches can be marked")
+        );
+        assert_eq!(mark(&mut app, "merged"), None);
+        assert!(!app.marked_unmerged());
+        assert_eq!(mark(&mut app, "wip"), None);
+        assert!(app.marked_unmerged());
+
+        // Only what's marked is listed outside cleanup mode
+        let (deleted, skipped) = app.deletion_summary();
+        assert_eq!(deleted, ["merged", "wip"]);
+        assert!(skipped.is_empty());
+
+        mark(&mut app, "wip");
+        assert_eq!(app.marked, ["merged".to_string()].into());
+    }
+
+    #[test]
+    fn tags_warn_about_the_detached_head_first() {
+        let mut tag = branch("v1.0", 1);
+        tag.ref_kind = RefKind::Tag;
+        let mut app = App::with_branches(vec![branch("main", 0), tag], Config::default());
+        app.select_branch("v1.0");
+
+        assert!(activate(&mut app).unwrap().is_none());
+        assert_eq!(app.prompt, Some(Prompt::DetachedCheckout));
+        // Anything but `y` leaves it be
+        let key = KeyEvent::new(KeyCode::Char('n'), KeyModifiers::NONE);
+        assert!(handle_key(&mut app, key).unwrap().is_none());
+        assert_eq!(app.prompt, None);
+    }
+
+    #[test]
+    fn branch_stats_count_ages_and_merges() {
+        let now = Utc.with_ymd_and_hms(2024, 1, 31, 12, 0, 0).unwrap();
+        let mut main = branch("main", 0);
+        main.is_merged = true;
+        let mut merged = branch("merged", 3);
+        merged.is_merged = true;
+        let mut tag = branch("v1.0", 100);
+        tag.ref_kind = RefKind::Tag;
+        let mut app = App::with_branches(
+            vec![
+                main,
+                merged,
+                branch("week-old", 7),
+                branch("month-old", 29),
+                branch("ancient", 400),
+                tag,
+            ],
+            Config::default(),
+        );
+        app.base_branch = Some("main".to_string());
+
+        assert_eq!(
+            app.branch_stats(now),
+            BranchStats {
+                ages: [1, 1, 2, 1],
+                merged: 1,
+                unmerged: 3,
+            }
+        );
+    }
+
+    #[test]
+    fn equally_good_matches_are_ordered_by_recency() {
+        let mut app = App::with_branches(
+            vec![
+                branch("a/login", 5),
+                branch("b/login", 1),
+                branch("main", 0),
+            ],
+            Config::default(),
+        );
+        app.sort_mode = SortMode::Frecency;
+        app.cycle_sort_mode();
+        assert_eq!(app.sort_mode, SortMode::Name);
+
+        for c in "login".chars() {
+            app.add_char(c);
+        }
+        let names: Vec<&str> = app
+            .filtered_branches
+            .iter()
+            .map(|&i| app.branches[i].name.as_str())
+            .collect();
+        assert_eq!(names, ["b/login", "a/login"]);
+    }
+
+    #[test]
+    fn better_matches_beat_more_recent_ones() {
+        let mut app = App::with_branches(
+            vec![branch("l-o-g-i-n", 0), branch("login", 30)],
+            Config::default(),
+        );
+        for c in "login".chars() {
+            app.add_char(c);
+        }
+        let names: Vec<&str> = app
+            .filtered_branches
+            .iter()
+            .map(|&i| app.branches[i].name.as_str())
+            .collect();
+        assert_eq!(names, ["login", "l-o-g-i-n"]);
+    }
+
+    #[test]
+    fn accepts_valid_branch_names() {
+        for name in [
+            "main",
+            "feature/login",
+            "fix-123",
+            "user/jane/wip",
+            "v1.2.3",
+        ] {
+            assert!(validate_branch_name(name).is_ok(), "{name} should be valid");
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_branch_names() {
+        for name in [
+            "",
+            "-f",
+            "@",
+            "HEAD",
+            "has space",
+            "tab\there",
+            "a..b",
+            "a@{b",
+            "/leading",
+            "trailing/",
+            "double//slash",
+            "ends.",
+            ".hidden",
+            "feature/.hidden",
+            "branch.lock",
+            "feature/x.lock/y",
+            "tilde~1",
+            "caret^",
+            "colon:",
+            "question?",
+            "star*",
+            "bracket[",
+            "back\\slash",
+            "bell\u{7}",
+        ] {
+            assert!(
+                validate_branch_name(name).is_err(),
+                "{name:?} should be invalid"
+            );
+        }
+    }
+
+    #[test]
+    fn selection_survives_sort_mode_change() {
+        let mut app = App::with_branches(
+            vec![branch("zebra", 0), branch("apple", 1), branch("mango", 2)],
+            Config::default(),
+        );
+        assert_eq!(selected_name(&app), Some("zebra"));
+
+        app.next();
+        assert_eq!(selected_name(&app), Some("apple"));
+
+        // Without reflogs, activity order is the same as recency order
+        app.cycle_sort_mode();
+        assert_eq!(app.sort_mode, SortMode::Activity);
+        assert_eq!(app.list_state.selected(), Some(1));
+        assert_eq!(selected_name(&app), Some("apple"));
+
+        // And without checkouts, nearly the same as frecency order
+        app.cycle_sort_mode();
+        assert_eq!(app.sort_mode, SortMode::Frecency);
+        assert_eq!(selected_name(&app), Some("apple"));
+
+        app.cycle_sort_mode();
+        assert_eq!(app.sort_mode, SortMode::Name);
+        assert_eq!(app.list_state.selected(), Some(0));
+        assert_eq!(selected_name(&app), Some("apple"));
+
+        app.next();
+        app.cycle_sort_mode();
+        assert_eq!(app.sort_mode, SortMode::Recent);
+        assert_eq!(app.list_state.selected(), Some(2));
+        assert_eq!(selected_name(&app), Some("mango"));
+    }
+
+    #[test]
+    fn typing_selects_the_best_match() {
+        let mut app = App::with_branches(
+            vec![
+                branch("feature/login", 0),
+                branch("main", 1),
+                branch("feature/logout", 2),
+                branch("fix/thing-x", 3),
+            ],
+            Config::default(),
+        );
+        app.next();
+        app.next();
+        assert_eq!(selected_name(&app), Some("feature/logout"));
+
+        // Still a match, but no longer the best one
+        app.add_char('l');
+        assert_eq!(selected_name(&app), Some("feature/login"));
+
+        app.filter.clear();
+        app.update_filter();
+        for c in "ftx".chars() {
+            app.add_char(c);
+        }
+        assert_eq!(selected_name(&app), Some("fix/thing-x"));
+
+        // Leaving the best match by hand sticks until the next key
+        app.filter = "lo".to_string();
+        app.update_filter();
+        app.next();
+        assert_eq!(selected_name(&app), Some("feature/logout"));
+        app.add_char('g');
+        assert_eq!(selected_name(&app), Some("feature/login"));
+    }
+
+    #[test]
+    fn backspace_keeps_the_selection() {
+        let mut app = App::with_branches(
+            vec![
+                branch("main", 0),
+                branch("feature/login", 1),
+                branch("feature/logout", 2),
+            ],
+            Config::default(),
+        );
+        for c in "feature".chars() {
+            app.add_char(c);
+        }
+        app.next();
+        assert_eq!(selected_name(&app), Some("feature/logout"));
+
+        while !app.filter.is_empty() {
+            app.remove_char();
+        }
+        assert_eq!(app.list_state.selected(), Some(2));
+        assert_eq!(selected_name(&app), Some("feature/logout"));
+    }
+
+    #[test]
+    fn selection_is_cleared_when_nothing_matches() {
+        let mut app = App::with_branches(vec![branch("main", 0)], Config::default());
+        app.add_char('x');
+        assert_eq!(app.list_state.selected(), None);
+
+        app.remove_char();
+        assert_eq!(selected_name(&app), Some("main"));
+    }
+
+    #[test]
+    fn unpushed_only_lists_every_branch_ahead_of_its_upstream() {
+        let mut branches: Vec<GitBranch> = (0..12)
+            .map(|i| {
+                let mut branch = branch(&format!("feat/{i}"), i);
+                // Every other one has commits to push, the rest are behind or in sync
+                branch.ahead_behind = Some(if i % 2 == 1 { (1, 0) } else { (0, i as usize) });
+                branch
+            })
+            .collect();
+        // No upstream at all, so nothing to compare against
+        branches.push(branch("local-only", 20));
+        let config = Config {
+            limit: RECENT_LIMIT,
+            ..Config::default()
+        };
+        let mut app = App::with_branches(branches, config);
+        assert_eq!(app.listed_count(), (RECENT_LIMIT, 13));
+
+        app.toggle_unpushed_only();
+        let names: Vec<&str> = app
+            .filtered_branches
+            .iter()
+            .map(|&i| app.branches[i].name.as_str())
+            .collect();
+        // Past the cap of the regular list
+        assert_eq!(
+            names,
+            ["feat/1", "feat/3", "feat/5", "feat/7", "feat/9", "feat/11"]
+        );
+        assert_eq!(app.listed_count(), (6, 6));
+
+        app.toggle_unpushed_only();
+        assert_eq!(app.filtered_branches.len(), RECENT_LIMIT);
+    }
+
+    #[test]
+    fn switching_sources_returns_to_where_each_list_was_left() {
+        let local = || vec![branch("main", 0), branch("feat", 1), branch("fix", 2)];
+        let mut remote = vec![branch("origin/main", 0), branch("origin/feat", 1)];
+        for branch in &mut remote {
+            branch.ref_kind = RefKind::Remote;
+        }
+        let mut app = App::with_branches(local(), Config::default());
+        // What `refresh` does, without a repository to load from
+        let show = |app: &mut App, source: ListSource, branches: Vec<GitBranch>| {
+            app.save_source_view();
+            app.source = source;
+            app.branches = branches;
+            app.list_state.select(Some(0));
+            *app.list_state.offset_mut() = 0;
+            app.update_filter();
+            app.restore_source_view();
+        };
+
+        app.list_state.select(Some(2));
+        *app.list_state.offset_mut() = 1;
+        show(&mut app, ListSource::Remote, remote.clone());
+        assert_eq!(selected_name(&app), Some("origin/main"));
+        app.next();
+
+        show(&mut app, ListSource::Local, local());
+        assert_eq!(selected_name(&app), Some("fix"));
+        assert_eq!(app.list_state.offset(), 1);
+
+        show(&mut app, ListSource::Remote, remote);
+        assert_eq!(selected_name(&app), Some("origin/feat"));
+    }
+
+    #[test]
+    fn lists_a_detached_head_first_and_refuses_bare_checkouts() {
+        let dir = TempRepo::new("detached");
+        let date = "2024-01-01T12:00:00Z";
+        git(&dir, &["init", "-q", "-b", "main"], date);
+        git(&dir, &["commit", "-q", "--allow-empty", "-m", "one"], date);
+        git(&dir, &["checkout", "-q", "-b", "newer"], date);
+        git(
+            &dir,
+            &["commit", "-q", "--allow-empty", "-m", "two"],
+            "2024-01-02T12:00:00Z",
+        );
+        git(&dir, &["checkout", "-q", "--detach", "main"], date);
+        let bare = dir.join("bare.git");
+        git(
+            &dir,
+            &["clone", "-q", "--bare", ".", bare.to_str().unwrap()],
+            date,
+        );
+
+        let mut app = App::with_branches(Vec::new(), Config::default());
+        app.load_branches(&mut Repository::open(&dir).unwrap())
+            .unwrap();
+        let listed: Vec<_> = app
+            .branches
+            .iter()
+            .map(|branch| (branch.ref_kind, branch.is_current, branch.subject.clone()))
+            .collect();
+        let head = app.branches[0].clone();
+        let bare_checkout = checkout_ref(&Repository::open(&bare).unwrap(), RefKind::Local, "main");
+
+        assert_eq!(
+            listed,
+            [
+                (RefKind::Head, true, Some("one".to_string())),
+                (RefKind::Local, false, Some("two".to_string())),
+                (RefKind::Local, false, Some("one".to_string())),
+            ]
+        );
+        assert_eq!(app.current_branch, None);
+        assert_eq!(
+            checkout_args(RefKind::Head, &head.name).unwrap(),
+            ["checkout", "--detach", &head.name, "--"]
+        );
+        assert!(
+            bare_checkout
+                .unwrap_err()
+                .to_string()
+                .ends_with("is a bare repository, there's no working tree to check out into")
+        );
+    }
+
+    #[test]
+    fn checks_out_across_repos_and_refuses_the_rest() {
+        let dir = TempRepo::new("across");
+        let date = "2024-01-01T12:00:00Z";
+        for name in ["api", "web"] {
+            let repo = dir.join(name);
+            std::fs::create_dir_all(&repo).unwrap();
+            git(&repo, &["init", "-q", "-b", "main"], date);
+            git(&repo, &["commit", "-q", "--allow-empty", "-m", "one"], date);
+        }
+        git(&dir.join("web"), &["branch", "feat"], date);
+
+        let mut app = App::with_branches(Vec::new(), Config::default());
+        app.checkouts_listed = repos::find_checkouts(&[dir.to_path_buf()]).unwrap();
+        app.refresh().unwrap();
+        let names: Vec<_> = app.branches.iter().map(|b| b.name.clone()).collect();
+
+        app.select_branch("web/main");
+        assert!(activate(&mut app).unwrap().is_none());
+        let already = app.status_message.take();
+        app.select_branch("web/feat");
+        assert!(activate(&mut app).unwrap().is_none());
+        let switched = app.status_message.take();
+        let head = Repository::open(dir.join("web"))
+            .unwrap()
+            .head()
+            .unwrap()
+            .shorthand()
+            .map(str::to_string);
+        let refused = app.refuses_changes();
+
+        assert_eq!(names, ["api/main", "web/feat", "web/main"]);
+        assert_eq!(already.as_deref(), Some("web is already on main"));
+        assert_eq!(switched.as_deref(), Some("Switched web to feat"));
+        assert_eq!(head.as_deref(), Some("feat"));
+        assert_eq!(selected_name(&app), Some("web/feat"));
+        assert!(refused);
+        assert!(
+            app.status_message.as_deref().is_some_and(
+                |message| message.starts_with("Across repositories, gb only checks out")
+            )
+        );
+    }
+
+    #[test]
+    fn lists_the_stashes_with_what_each_changed() {
+        let dir = TempRepo::new("stashes");
+        let date = "2024-01-01T12:00:00Z";
+        git(&dir, &["init", "-q", "-b", "main"], date);
+        std::fs::write(dir.join("notes.txt"), "one\n").unwrap();
+        git(&dir, &["add", "notes.txt"], date);
+        git(&dir, &["commit", "-q", "-m", "one"], date);
+        git(&dir, &["checkout", "-q", "--detach"], date);
+        for (line, message) in [("two\n", "older"), ("two\nthree\n", "newer")] {
+            std::fs::write(dir.join("notes.txt"), format!("one\n{}", line)).unwrap();
+            git(&dir, &["stash", "push", "-q", "-m", message], date);
+        }
+
+        let config = Config {
+            limit: 1,
+            ..Config::default()
+        };
+        let mut app = App::with_branches(Vec::new(), config);
+        app.source = ListSource::Stashes;
+        let mut repo = Repository::open(&dir).unwrap();
+        app.load_branches(&mut repo).unwrap();
+        let listed: Vec<_> = app
+            .branches
+            .iter()
+            .map(|b| {
+                (
+                    b.name.as_str(),
+                    b.description.as_deref(),
+                    b.subject.is_some(),
+                )
+            })
+            .collect();
+        let newer = stash_diff_stat(&repo, app.branches[0].tip.unwrap()).unwrap();
+
+        // Nothing else, not even the detached HEAD, and never capped
+        assert_eq!(
+            listed,
+            [
+                ("stash@{0}", Some("On (no branch): newer"), false),
+                ("stash@{1}", Some("On (no branch): older"), false),
+            ]
+        );
+        assert_eq!(app.listed_count(), (2, 2));
+        assert_eq!((newer.insertions, newer.deletions), (2, 0));
+        assert_eq!(stash_index("stash@{1}").unwrap(), 1);
+        assert!(stash_index("main").is_err());
+
+        app.update_filter();
+        app.start_delete().unwrap();
+        assert_eq!(app.prompt, Some(Prompt::DropStash));
+        assert!(app.key_hints(200).contains("p pop · d drop · D changes"));
+
+        app.prompt = None;
+        app.cleanup = true;
+        app.source = ListSource::Local;
+        app.toggle_stashes().unwrap();
+        assert_eq!(app.source, ListSource::Local);
+        assert_eq!(
+            app.status_message.as_deref(),
+            Some("Cleanup mode only lists local branches")
+        );
+    }
+
+    #[test]
+    fn key_hints_go_with_the_selection_and_fit() {
+        let mut current = branch("main", 0);
+        current.is_current = true;
+        let mut tag = branch("v1.0", 2);
+        tag.ref_kind = RefKind::Tag;
+        let mut app = App::with_branches(vec![current, branch("feat", 1), tag], Config::default());
+        app.current_branch = Some("main".to_string());
+
+        assert_eq!(
+            app.key_hints(200),
+            "on main · r rename · u upstream · / filter · q quit"
+        );
+        app.next();
+        assert_eq!(app.key_hints(40), "on main · enter check out · d delete");
+        app.next();
+        assert_eq!(
+            app.key_hints(200),
+            "on main · enter check out detached · Alt-r/Alt-m rebase/merge · / filter · q quit"
+        );
+        app.filter_focused = true;
+        assert_eq!(
+            app.key_hints(200),
+            "on main · enter pick · esc stop filtering"
+        );
+    }
+
+    #[test]
+    fn flashed_messages_go_away_unless_replaced() {
+        let mut app = App::with_branches(vec![branch("main", 0)], Config::default());
+        app.flash("Copied main".to_string());
+        let start = app.flashed.as_ref().unwrap().1;
+        app.expire_flash(start);
+        assert_eq!(app.status_message.as_deref(), Some("Copied main"));
+        app.expire_flash(start + FLASH_DURATION);
+        assert_eq!(app.status_message, None);
+
+        // What's said after it stays
+        app.flash("Copied main".to_string());
+        app.status_message = Some("Failed to rename main".to_string());
+        app.expire_flash(start + FLASH_DURATION * 2);
+        assert_eq!(app.status_message.as_deref(), Some("Failed to rename main"));
+        assert!(app.flashed.is_none());
+    }
+
+    #[test]
+    fn rebases_and_merges_other_branches_after_asking() {
+        let mut current = branch("main", 0);
+        current.is_current = true;
+        let mut app = App::with_branches(vec![current, branch("feat", 1)], Config::default());
+        app.current_branch = Some("main".to_string());
+        let alt = |c| KeyEvent::new(KeyCode::Char(c), KeyModifiers::ALT);
+
+        handle_key(&mut app, alt('r')).unwrap();
+        assert_eq!(app.prompt, None);
+        assert_eq!(
+            app.status_message.as_deref(),
+            Some("main is the current branch, pick another to rebase")
+        );
+
+        app.next();
+        handle_key(&mut app, alt('m')).unwrap();
+        assert_eq!(app.prompt, Some(Prompt::Integrate(Integration::Merge)));
+        let yes = KeyEvent::new(KeyCode::Char('y'), KeyModifiers::NONE);
+        handle_key(&mut app, yes).unwrap();
+        assert_eq!(
+            app.pending_git,
+            Some(vec!["merge".to_string(), "feat".to_string()])
+        );
+        assert_eq!(
+            Integration::of(app.pending_git.as_ref().unwrap()),
+            Some(Integration::Merge)
+        );
+        assert_eq!(Integration::of(&["show".to_string()]), None);
+
+        app.pending_git = None;
+        app.read_only = true;
+        handle_key(&mut app, alt('r')).unwrap();
+        assert_eq!(app.prompt, None);
+        assert_eq!(app.pending_git, None);
+    }
+
+    #[test]
+    fn read_only_refuses_changes_but_not_browsing() {
+        let mut app = App::with_branches(
+            vec![branch("main", 0), branch("feat", 1)],
+            Config::default(),
+        );
+        app.read_only = true;
+
+        app.next();
+        assert_eq!(selected_name(&app), Some("feat"));
+        assert!(activate(&mut app).unwrap().is_none());
+        assert_eq!(
+            app.status_message.as_deref(),
+            Some("Read-only mode, nothing was changed")
+        );
+
+        app.status_message = None;
+        app.filter = "new-branch".to_string();
+        assert!(app.create_from_filter().unwrap().is_none());
+        assert!(app.start_worktree().unwrap().is_none());
+        assert!(app.input.is_none());
+        app.start_fetch();
+        assert!(app.fetch_task.is_none());
+        assert_eq!(
+            app.status_message.as_deref(),
+            Some("Read-only mode, nothing was changed")
+        );
+    }
+
+    #[test]
+    fn finds_the_lock_file_in_libgit2_errors() {
+        assert_eq!(
+            locked_file("failed to create locked file '/repo/.git/index.lock': File exists"),
+            Some(PathBuf::from("/repo/.git/index.lock"))
+        );
+        assert_eq!(
+            locked_file("failed to lock file '/repo/.git/refs/heads/feat.lock' for writing"),
+            Some(PathBuf::from("/repo/.git/refs/heads/feat.lock"))
+        );
+        assert_eq!(locked_file("reference 'refs/heads/nope' not found"), None);
+    }
+
+    #[test]
+    fn jsonl_records_say_what_the_list_shows() {
+        let mut feat = branch("feat", 0);
+        feat.upstream = Some("origin/feat".to_string());
+        feat.ahead_behind = Some((2, 0));
+        feat.author = Some("Alice".to_string());
+        feat.subject = Some("Add the feature".to_string());
+        let record = serde_json::to_value(BranchRecord::from(&feat)).unwrap();
+        assert_eq!(
+            record,
+            serde_json::json!({
+                "name": "feat",
+                "kind": "local",
+                "current": false,
+                "tip": null,
+                "time": "2024-01-31T12:00:00+00:00",
+                "author": "Alice",
+                "subject": "Add the feature",
+                "description": null,
+                "upstream": "origin/feat",
+                "ahead": 2,
+                "behind": 0,
+                "upstream_gone": false,
+                "merged": false,
+                "protected": false,
+                "stashed": false,
+            })
+        );
+    }
+
+    #[test]
+    fn branches_are_peeled_to_their_commit() {
+        let dir = TempRepo::new("peel");
+        git(&dir, &["init", "-q", "-b", "main"], "2024-01-01T12:00:00Z");
+        git(
+            &dir,
+            &["commit", "-q", "--allow-empty", "-m", "one"],
+            "2024-01-02T12:00:00Z",
+        );
+        git(
+            &dir,
+            &["tag", "-a", "-m", "annotated", "v1"],
+            "2024-01-03T12:00:00Z",
+        );
+
+        // git refuses to point a branch at anything but a commit, so the refs
+        // are written by hand
+        let repo = Repository::open(&dir).unwrap();
+        let tag = repo.refname_to_id("refs/tags/v1").unwrap();
+        let tree = repo.head().unwrap().peel_to_tree().unwrap().id();
+        std::fs::write(dir.join(".git/refs/heads/at-tag"), format!("{tag}\n")).unwrap();
+        std::fs::write(dir.join(".git/refs/heads/at-tree"), format!("{tree}\n")).unwrap();
+
+        let mut app = App::with_branches(Vec::new(), Config::default());
+        app.load_branches(&mut Repository::open(&dir).unwrap())
+            .unwrap();
+        let find = |name: &str| app.branches.iter().find(|b| b.name == name).unwrap();
+
+        let main = find("main");
+        let at_tag = find("at-tag");
+        assert_eq!(at_tag.tip, main.tip);
+        assert_eq!(at_tag.last_commit_time, main.last_commit_time);
+        assert!(!at_tag.not_a_commit);
+
+        let at_tree = find("at-tree");
+        assert!(at_tree.not_a_commit);
+        assert_eq!(at_tree.tip, None);
+        assert_eq!(at_tree.last_commit_time, DateTime::UNIX_EPOCH);
+        assert_eq!(
+            at_tree.age_label(Utc::now(), SortMode::Recent),
+            "(not a commit)"
+        );
+        // Sorted as the oldest, not passed off as the newest
+        assert_eq!(app.branches.last().unwrap().name, "at-tree");
+    }
+
+    #[test]
+    fn no_limit_lists_and_wraps_around_every_branch() {
+        let branches: Vec<GitBranch> = (0..50).map(|i| branch(&format!("feat/{i}"), i)).collect();
+        let config = Config {
+            limit: 0,
+            ..Config::default()
+        };
+        let mut app = App::with_branches(branches, config);
+        assert_eq!(app.filtered_branches.len(), 50);
+        assert_eq!(app.listed_count(), (50, 50));
+
+        app.previous();
+        assert_eq!(selected_name(&app), Some("feat/49"));
+        app.next();
+        assert_eq!(selected_name(&app), Some("feat/0"));
+
+        // Filtering still searches all of them
+        app.filter = "feat/49".to_string();
+        app.update_filter();
+        assert_eq!(selected_name(&app), Some("feat/49"));
+        app.next();
+        assert_eq!(selected_name(&app), Some("feat/49"));
+    }
+
+    #[test]
+    fn counts_the_remote_branches_a_fetch_pruned() {
+        let stderr = "From github.com:acme/app\n \
+             - [deleted]         (none)     -> origin/gone1\n \
+             - [deleted]         (none)     -> origin/gone2\n   \
+             3f2a1b4..9c8d7e6  main       -> origin/main\n";
+        assert_eq!(pruned_count(stderr), 2);
+        assert_eq!(pruned_count(""), 0);
+    }
+
+    #[test]
+    fn git_errors_are_joined_onto_one_line() {
+        let stderr = "error: Your local changes to the following files would be overwritten by checkout:\n\
+            \tconf.txt\n\
+            Please commit your changes or stash them before you switch branches.\n\
+            Aborting\n";
+        assert_eq!(
+            one_line(stderr),
+            "error: Your local changes to the following files would be overwritten by checkout: \
+             conf.txt Please commit your changes or stash them before you switch branches. Aborting"
+        );
+        assert_eq!(one_line(""), "");
+    }
+
+    #[test]
+    fn untracked_and_modified_files_make_the_tree_dirty() {
+        let dir = TempRepo::new("dirty");
+        git(&dir, &["init", "-q", "-b", "main"], "2024-01-01T12:00:00Z");
+        std::fs::write(dir.join(".gitignore"), "target/\n").unwrap();
+        git(&dir, &["add", ".gitignore"], "2024-01-01T12:00:00Z");
+        git(&dir, &["commit", "-q", "-m", "one"], "2024-01-01T12:00:00Z");
+        let repo = Repository::open(&dir).unwrap();
+        assert!(!is_dirty(&repo));
+
+        // Ignored files don't count
+        std::fs::create_dir_all(dir.join("target")).unwrap();
+        std::fs::write(dir.join("target/out"), "built").unwrap();
+        assert!(!is_dirty(&repo));
+
+        std::fs::write(dir.join("notes.txt"), "new").unwrap();
+        assert!(is_dirty(&repo));
+        std::fs::remove_file(dir.join("notes.txt")).unwrap();
+
+        std::fs::write(dir.join(".gitignore"), "target/\nchanged\n").unwrap();
+        let dirty = is_dirty(&repo);
+        assert!(dirty);
+    }
+
+    #[test]
+    fn highlighted_spans_split_at_every_change() {
+        let plain = Style::default();
+        let bold = Style::default().add_modifier(ratatui::style::Modifier::BOLD);
+        let spans = highlighted_spans("feature/x", |at| [0, 1, 8].contains(&at), plain, bold);
+        let parts: Vec<(&str, bool)> = spans
+            .iter()
+            .map(|span| (span.content.as_ref(), span.style == bold))
+            .collect();
+        assert_eq!(parts, [("fe", true), ("ature/", false), ("x", true)]);
+
+        let spans = highlighted_spans("main", |_| false, plain, bold);
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0].content, "main");
+    }
+
+    #[test]
+    fn finds_pull_requests_by_the_remote_branch() {
+        let mut renamed = branch("mine", 1);
+        renamed.upstream = Some("origin/theirs".to_string());
+        let mut remote = branch("origin/theirs", 1);
+        remote.ref_kind = RefKind::Remote;
+        let mut tag = branch("theirs", 1);
+        tag.ref_kind = RefKind::Tag;
+        let mut app = App::with_branches(
+            vec![renamed, remote, tag, branch("local", 2), branch("other", 3)],
+            Config::default(),
+        );
+        let pull_request = |number| PullRequest {
+            number,
+            draft: false,
+            review: forge::Review::None,
+            checks: forge::Checks::None,
+        };
+        app.pull_requests = [
+            ("theirs".to_string(), pull_request(1)),
+            ("local".to_string(), pull_request(2)),
+        ]
+        .into();
+
+        let numbers: Vec<_> = app
+            .branches
+            .iter()
+            .map(|branch| {
+                app.pull_request(branch)
+                    .map(|pull_request| pull_request.number)
+            })
+            .collect();
+        assert_eq!(numbers, [Some(1), Some(1), None, Some(2), None]);
+    }
+
+    #[test]
+    fn badges_the_branches_merged_into_the_base_branch() {
+        let mut merged = branch("merged", 1);
+        merged.is_merged = true;
+        let mut main = branch("main", 0);
+        main.is_merged = true;
+        let mut app =
+            App::with_branches(vec![main, merged, branch("unmerged", 2)], Config::default());
+        app.base_branch = Some("main".to_string());
+
+        let badged = |app: &App| {
+            app.branches
+                .iter()
+                .filter(|branch| app.shows_merged(branch))
+                .map(|branch| branch.name.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(badged(&app), ["merged"]);
+        // Cleanup mode lists nothing but merged branches
+        app.cleanup = true;
+        assert!(badged(&app).is_empty());
+    }
+
+    #[test]
+    fn renaming_keeps_the_upstream_and_head() {
+        let dir = TempRepo::new("rename");
+        let date = "2024-01-01T12:00:00Z";
+        git(&dir, &["init", "-q", "-b", "main"], date);
+        git(&dir, &["commit", "-q", "--allow-empty", "-m", "one"], date);
+        git(&dir, &["checkout", "-q", "-b", "feat"], date);
+        git(&dir, &["config", "branch.feat.remote", "origin"], date);
+        git(
+            &dir,
+            &["config", "branch.feat.merge", "refs/heads/feat"],
+            date,
+        );
+        let repo = Repository::open(&dir).unwrap();
+
+        rename_branch(&repo, "feat", "feature/login").unwrap();
+        let config = repo.config().unwrap().snapshot().unwrap();
+        let renamed = (
+            head_branch(&repo),
+            config.get_string("branch.feature/login.merge").ok(),
+            config.get_string("branch.feat.merge").is_err(),
+        );
+        let missing = rename_branch(&repo, "feat", "other").unwrap_err();
+        assert_eq!(
+            renamed,
+            (
+                Some("feature/login".to_string()),
+                Some("refs/heads/feat".to_string()),
+                true
+            )
+        );
+        assert!(missing.to_string().starts_with("Failed to rename feat"));
+    }
+
+    #[test]
+    fn sets_and_unsets_upstreams() {
+        let dir = TempRepo::new("upstream");
+        let date = "2024-01-01T12:00:00Z";
+        git(&dir, &["init", "-q", "-b", "main"], date);
+        git(&dir, &["commit", "-q", "--allow-empty", "-m", "one"], date);
+        git(&dir, &["remote", "add", "origin", "/nowhere"], date);
+        git(
+            &dir,
+            &["update-ref", "refs/remotes/origin/trunk", "HEAD"],
+            date,
+        );
+        let repo = Repository::open(&dir).unwrap();
+
+        set_upstream(&repo, "main", Some("origin/trunk")).unwrap();
+        let config = repo.config().unwrap().snapshot().unwrap();
+        let set = (
+            config.get_string("branch.main.remote").ok(),
+            config.get_string("branch.main.merge").ok(),
+        );
+        set_upstream(&repo, "main", None).unwrap();
+        let config = repo.config().unwrap().snapshot().unwrap();
+        let unset = config.get_string("branch.main.merge").is_err();
+        let missing = set_upstream(&repo, "main", Some("origin/gone")).unwrap_err();
+
+        assert_eq!(
+            set,
+            (
+                Some("origin".to_string()),
+                Some("refs/heads/trunk".to_string())
+            )
+        );
+        assert!(unset);
+        assert!(
+            missing
+                .to_string()
+                .starts_with("Failed to set the upstream of main")
+        );
+    }
+
+    #[test]
+    fn deletes_only_merged_branches() {
+        let dir = TempRepo::new("delete");
+        let date = "2024-01-01T12:00:00Z";
+        git(&dir, &["init", "-q", "-b", "main"], date);
+        git(&dir, &["commit", "-q", "--allow-empty", "-m", "one"], date);
+        git(&dir, &["branch", "merged"], date);
+        git(&dir, &["checkout", "-q", "-b", "unmerged"], date);
+        git(&dir, &["commit", "-q", "--allow-empty", "-m", "two"], date);
+        git(&dir, &["checkout", "-q", "-b", "on-unmerged"], date);
+        git(&dir, &["checkout", "-q", "main"], date);
+        let repo = Repository::open(&dir).unwrap();
+
+        let err = delete_branch(&repo, "unmerged", Some("main"), false).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "unmerged isn't merged into HEAD or main, so it wasn't deleted"
+        );
+        assert!(repo.find_branch("unmerged", BranchType::Local).is_ok());
+
+        let short = delete_branch(&repo, "merged", Some("main"), false).unwrap();
+        assert!(repo.revparse_single(&short).is_ok());
+        assert!(repo.find_branch("merged", BranchType::Local).is_err());
+
+        // Merged into HEAD counts too, like `git branch -d`
+        git(&dir, &["checkout", "-q", "on-unmerged"], date);
+        let deleted = delete_branch(&repo, "unmerged", Some("main"), false);
+        // And forcing it deletes even what isn't merged anywhere
+        git(&dir, &["checkout", "-q", "main"], date);
+        let forced = delete_branch(&repo, "on-unmerged", Some("main"), true);
+        let gone = repo.find_branch("on-unmerged", BranchType::Local).is_err();
+        deleted.unwrap();
+        forced.unwrap();
+        assert!(gone);
+    }
+
+    #[test]
+    fn finds_the_branches_checked_out_in_other_worktrees() {
+        let dir = TempRepo::new("worktrees");
+        let (main, linked) = (dir.join("main"), dir.join("linked"));
+        std::fs::create_dir_all(&main).unwrap();
+        let date = "2024-01-01T12:00:00Z";
+        git(&main, &["init", "-q", "-b", "main"], date);
+        git(&main, &["commit", "-q", "--allow-empty", "-m", "one"], date);
+        git(
+            &main,
+            &["worktree", "add", "-q", "-b", "feat", "../linked"],
+            date,
+        );
+        let linked = linked.canonicalize().unwrap();
+
+        let from_main = other_worktrees(&Repository::open(&main).unwrap());
+        let from_linked = other_worktrees(&Repository::open(&linked).unwrap());
+        assert_eq!(from_main, [("feat".to_string(), linked.clone())].into());
+        assert_eq!(
+            from_linked,
+            [("main".to_string(), main.canonicalize().unwrap())].into()
+        );
+
+        // Enter goes there rather than failing to check it out twice
+        let mut app = App::with_branches(vec![branch("feat", 0)], Config::default());
+        app.worktrees = from_main;
+        assert!(matches!(
+            activate(&mut app).unwrap(),
+            Some(Outcome::Worktree { branch, path, created: false })
+                if branch == "feat" && path == linked
+        ));
+    }
+
+    #[test]
+    fn enter_without_a_match_offers_to_create_the_branch() {
+        let mut app = App::with_branches(vec![branch("main", 0)], Config::default());
+        for c in "feature/new".chars() {
+            app.add_char(c);
+        }
+        assert!(app.filtered_branches.is_empty());
+
+        assert_eq!(
+            app.no_match_hint(),
+            "No branch matches · Enter creates feature/new"
+        );
+        assert!(activate(&mut app).unwrap().is_none());
+        assert_eq!(app.prompt, Some(Prompt::CreateBranch));
+
+        // Quick mode only offers the two branches
+        app.prompt = None;
+        app.quick = true;
+        assert!(activate(&mut app).unwrap().is_none());
+        assert_eq!(app.prompt, None);
+        assert_eq!(app.status_message.as_deref(), Some("No branch matches"));
+    }
+
+    #[test]
+    fn creates_branches_and_applies_stashes_without_git() {
+        let dir = TempRepo::new("create");
+        let date = "2024-01-01T12:00:00Z";
+        git(&dir, &["init", "-q", "-b", "main"], date);
+        std::fs::write(dir.join("a.txt"), "main\n").unwrap();
+        git(&dir, &["add", "a.txt"], date);
+        git(&dir, &["commit", "-q", "-m", "one"], date);
+        let mut repo = Repository::open(&dir).unwrap();
+
+        // Created off HEAD, taking local changes along
+        std::fs::write(dir.join("a.txt"), "edited\n").unwrap();
+        create_branch(&repo, "feature").unwrap();
+        assert_eq!(head_branch(&repo).as_deref(), Some("feature"));
+        assert!(is_dirty(&repo));
+        assert!(create_branch(&repo, "feature").is_err());
+
+        // Applied and kept, and refused over changes it would overwrite
+        git(&dir, &["stash", "-q"], date);
+        assert!(!is_dirty(&repo));
+        apply_stash(&mut repo, "stash@{0}").unwrap();
+        assert_eq!(
+            std::fs::read_to_string(dir.join("a.txt")).unwrap(),
+            "edited\n"
+        );
+        std::fs::write(dir.join("a.txt"), "again\n").unwrap();
+        let err = apply_stash(&mut repo, "stash@{0}").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Failed to apply stash: your local changes to a.txt would be overwritten, \
+             commit or stash them first"
+        );
+        assert_eq!(
+            std::fs::read_to_string(dir.join("a.txt")).unwrap(),
+            "again\n"
+        );
+        assert!(apply_stash(&mut repo, "main").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn checks_out_natively_and_runs_the_post_checkout_hook() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TempRepo::new("checkout");
+        let date = "2024-01-01T12:00:00Z";
+        git(&dir, &["init", "-q", "-b", "main"], date);
+        std::fs::write(dir.join("a.txt"), "main\n").unwrap();
+        git(&dir, &["add", "a.txt"], date);
+        git(&dir, &["commit", "-q", "-m", "one"], date);
+        git(&dir, &["checkout", "-q", "-b", "feature"], date);
+        std::fs::write(dir.join("a.txt"), "feature\n").unwrap();
+        git(&dir, &["commit", "-q", "-am", "two"], date);
+        git(&dir, &["tag", "v1"], date);
+        git(&dir, &["checkout", "-q", "main"], date);
+
+        let hook = dir.join(".git/hooks/post-checkout");
+        std::fs::write(&hook, "#!/bin/sh\necho \"$@\" > hook-args\n").unwrap();
+        std::fs::set_permissions(&hook, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let repo = Repository::open(&dir).unwrap();
+        let main = repo.head().unwrap().target().unwrap();
+
+        assert_eq!(
+            checkout_ref(&repo, RefKind::Local, "feature").unwrap(),
+            "feature"
+        );
+        let feature = repo.head().unwrap().target().unwrap();
+        assert_eq!(head_branch(&repo).as_deref(), Some("feature"));
+        assert_eq!(
+            std::fs::read_to_string(dir.join("a.txt")).unwrap(),
+            "feature\n"
+        );
+        assert!(
+            repo.statuses(None)
+                .unwrap()
+                .iter()
+                .all(|entry| { entry.path() == Some("hook-args") })
+        );
+        assert_eq!(
+            std::fs::read_to_string(dir.join("hook-args")).unwrap(),
+            format!("{} {} 1\n", main, feature)
+        );
+
+        // Local changes the checkout would overwrite stop it
+        std::fs::write(dir.join("a.txt"), "edited\n").unwrap();
+        let err = checkout_ref(&repo, RefKind::Local, "main").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Failed to checkout branch: your local changes to a.txt would be overwritten, \
+             commit or stash them first"
+        );
+        assert_eq!(head_branch(&repo).as_deref(), Some("feature"));
+        assert_eq!(
+            std::fs::read_to_string(dir.join("a.txt")).unwrap(),
+            "edited\n"
+        );
+
+        // Ones it wouldn't come along, and tags leave HEAD detached
+        git(&dir, &["checkout", "-q", "a.txt"], date);
+        std::fs::write(dir.join("b.txt"), "new\n").unwrap();
+        let tag = checkout_ref(&repo, RefKind::Tag, "v1");
+        let detached = repo.head_detached().unwrap();
+        let kept = std::fs::read_to_string(dir.join("b.txt"));
+        assert_eq!(tag.unwrap(), "v1");
+        assert!(detached);
+        assert_eq!(kept.unwrap(), "new\n");
+    }
+
+    #[test]
+    fn stashes_the_changes_around_a_checkout() {
+        let dir = TempRepo::new("stash-switch");
+        let date = "2024-01-01T12:00:00Z";
+        git(&dir, &["init", "-q", "-b", "main"], date);
+        git(&dir, &["config", "user.name", "gb"], date);
+        git(&dir, &["config", "user.email", "gb@example.com"], date);
+        std::fs::write(dir.join("a.txt"), "main\n").unwrap();
+        std::fs::write(dir.join("shared.txt"), "shared\n").unwrap();
+        git(&dir, &["add", "."], date);
+        git(&dir, &["commit", "-q", "-m", "one"], date);
+        git(&dir, &["checkout", "-q", "-b", "feature"], date);
+        std::fs::write(dir.join("a.txt"), "feature\n").unwrap();
+        git(&dir, &["commit", "-q", "-am", "two"], date);
+        git(&dir, &["checkout", "-q", "main"], date);
+        let mut repo = Repository::open(&dir).unwrap();
+        let stashes = |repo: &mut Repository| {
+            let mut count = 0;
+            repo.stash_foreach(|_, _, _| {
+                count += 1;
+                true
+            })
+            .unwrap();
+            count
+        };
+
+        // Stashed, untracked files included, and left there
+        std::fs::write(dir.join("shared.txt"), "edited\n").unwrap();
+        std::fs::write(dir.join("new.txt"), "new\n").unwrap();
+        let (branch, note) = stash_and_switch(&mut repo, RefKind::Local, "feature", false).unwrap();
+        assert_eq!(branch, "feature");
+        assert_eq!(
+            note.as_deref(),
+            Some("Your changes are stashed as stash@{0}")
+        );
+        assert_eq!(head_branch(&repo).as_deref(), Some("feature"));
+        assert!(!is_dirty(&repo));
+        assert_eq!(stashes(&mut repo), 1);
+
+        // Popped onto the other branch
+        repo.stash_pop(0, None).unwrap();
+        let (branch, note) = stash_and_switch(&mut repo, RefKind::Local, "main", true).unwrap();
+        assert_eq!((branch.as_str(), note), ("main", None));
+        assert_eq!(
+            std::fs::read_to_string(dir.join("shared.txt")).unwrap(),
+            "edited\n"
+        );
+        assert!(dir.join("new.txt").exists());
+        assert_eq!(stashes(&mut repo), 0);
+
+        // Changes that don't apply there stay stashed
+        std::fs::write(dir.join("a.txt"), "edited\n").unwrap();
+        let (_, note) = stash_and_switch(&mut repo, RefKind::Local, "feature", true).unwrap();
+        assert_eq!(
+            note.as_deref(),
+            Some(
+                "Your changes conflict with feature, fix the conflicts; \
+                 they're also still stashed as stash@{0}"
+            )
+        );
+        assert_eq!(head_branch(&repo).as_deref(), Some("feature"));
+        assert_eq!(stashes(&mut repo), 1);
+    }
+
+    #[test]
+    fn merges_the_changes_into_the_branch_checked_out() {
+        let dir = TempRepo::new("merge-switch");
+        let date = "2024-01-01T12:00:00Z";
+        git(&dir, &["init", "-q", "-b", "main"], date);
+        git(&dir, &["config", "user.name", "gb"], date);
+        git(&dir, &["config", "user.email", "gb@example.com"], date);
+        std::fs::write(dir.join("a.txt"), "main\n").unwrap();
+        std::fs::write(dir.join("b.txt"), "one\ntwo\nthree\n").unwrap();
+        git(&dir, &["add", "."], date);
+        git(&dir, &["commit", "-q", "-m", "one"], date);
+        git(&dir, &["checkout", "-q", "-b", "feature"], date);
+        std::fs::write(dir.join("a.txt"), "feature\n").unwrap();
+        std::fs::write(dir.join("b.txt"), "one\ntwo\nthree\nfour\n").unwrap();
+        git(&dir, &["commit", "-q", "-am", "two"], date);
+        git(&dir, &["checkout", "-q", "main"], date);
+        let mut repo = Repository::open(&dir).unwrap();
+
+        // Both files differ on feature, so a plain checkout refuses
+        std::fs::write(dir.join("a.txt"), "edited\n").unwrap();
+        std::fs::write(dir.join("b.txt"), "zero\none\ntwo\nthree\n").unwrap();
+        std::fs::write(dir.join("new.txt"), "new\n").unwrap();
+        let (branch, note) = merge_and_switch(&mut repo, RefKind::Local, "feature").unwrap();
+        assert_eq!(branch, "feature");
+        assert_eq!(
+            note.as_deref(),
+            Some("Your changes conflict with feature in a.txt, fix the conflict markers there")
+        );
+        assert_eq!(head_branch(&repo).as_deref(), Some("feature"));
+        let a = std::fs::read_to_string(dir.join("a.txt")).unwrap();
+        assert!(a.contains("<<<<<<<") && a.contains("edited") && a.contains("feature"));
+        assert_eq!(
+            std::fs::read_to_string(dir.join("b.txt")).unwrap(),
+            "zero\none\ntwo\nthree\nfour\n"
+        );
+        assert!(dir.join("new.txt").exists());
+        let mut stashed = 0;
+        repo.stash_foreach(|_, _, _| {
+            stashed += 1;
+            true
+        })
+        .unwrap();
+        assert_eq!(stashed, 0);
+    }
+
+    #[test]
+    fn subjects_fit_the_room_left_in_the_row() {
+        assert_eq!(subject_label("Fix login", 40).as_deref(), Some("Fix login"));
+        assert_eq!(
+            subject_label("Fix the login redirect loop", 12).as_deref(),
+            Some("Fix the log…")
+        );
+        assert_eq!(subject_label("Fix login", 7), None);
+    }
+
+    #[test]
+    fn counts_commits_ahead_of_and_behind_the_upstream() {
+        let dir = TempRepo::new("ahead");
+        let date = "2024-01-01T12:00:00Z";
+        git(&dir, &["init", "-q", "-b", "main"], date);
+        git(&dir, &["commit", "-q", "--allow-empty", "-m", "one"], date);
+        git(&dir, &["branch", "untracked"], date);
+        git(
+            &dir,
+            &["checkout", "-q", "-b", "feat", "--track", "main"],
+            date,
+        );
+        git(&dir, &["commit", "-q", "--allow-empty", "-m", "two"], date);
+        git(
+            &dir,
+            &["commit", "-q", "--allow-empty", "-m", "three"],
+            date,
+        );
+        git(&dir, &["checkout", "-q", "main"], date);
+        git(&dir, &["commit", "-q", "--allow-empty", "-m", "four"], date);
+        let repo = Repository::open(&dir).unwrap();
+
+        let counts =
+            |name| ahead_behind(&repo, &repo.find_branch(name, BranchType::Local).unwrap());
+        let (feat, untracked) = (counts("feat"), counts("untracked"));
+
+        // In the picker, they're counted on another thread and filled in
+        let mut app = App::with_branches(Vec::new(), Config::default());
+        app.background_divergence = true;
+        app.load_branches(&mut Repository::open(&dir).unwrap())
+            .unwrap();
+        let before: Vec<_> = app
+            .branches
+            .iter()
+            .map(|branch| branch.ahead_behind)
+            .collect();
+        while app.divergence_task.is_some() {
+            app.check_divergence();
+        }
+        let feat_in_app = app
+            .branches
+            .iter()
+            .find(|branch| branch.name == "feat")
+            .and_then(|branch| branch.ahead_behind);
+
+        assert_eq!(feat, Some((2, 1)));
+        assert_eq!(untracked, None);
+        assert!(before.iter().all(Option::is_none));
+        assert_eq!(feat_in_app, Some((2, 1)));
+    }
+
+    /// `main` with `a-merged` in its history and `feat`, checked out before
+    /// it, ahead of it
+    fn merged_and_unmerged(dir: &Path) {
+        git(dir, &["init", "-q", "-b", "main"], "2024-01-01T12:00:00Z");
+        git(
+            dir,
+            &["commit", "-q", "--allow-empty", "-m", "one"],
+            "2024-01-01T12:00:00Z",
+        );
+        git(dir, &["branch", "a-merged"], "2024-01-01T12:00:00Z");
+        git(
+            dir,
+            &["checkout", "-q", "-b", "feat"],
+            "2024-01-02T12:00:00Z",
+        );
+        git(
+            dir,
+            &["commit", "-q", "--allow-empty", "-m", "two"],
+            "2024-01-02T12:00:00Z",
+        );
+        git(dir, &["checkout", "-q", "main"], "2024-01-03T12:00:00Z");
+        git(
+            dir,
+            &["commit", "-q", "--allow-empty", "-m", "three"],
+            "2024-01-03T12:00:00Z",
+        );
+    }
+
+    #[test]
+    fn reads_the_branch_details_in_the_background() {
+        let dir = TempRepo::new("details");
+        merged_and_unmerged(&dir);
+
+        let mut app = App::with_branches(Vec::new(), Config::default());
+        app.background_details = true;
+        app.load_branches(&mut Repository::open(&dir).unwrap())
+            .unwrap();
+        app.update_filter();
+        let names = |app: &App| -> Vec<String> {
+            app.filtered_branches
+                .iter()
+                .map(|&i| app.branches[i].name.clone())
+                .collect()
+        };
+        // Only the previous branch is ahead before any times are in
+        let before = names(&app);
+        let pending = app.pending_details.len();
+        let unread = app.branches.iter().all(|branch| branch.subject.is_none());
+        while app.details_task.is_some() {
+            app.check_details();
+        }
+        let after = names(&app);
+
+        // Reading them again keeps what was already known up meanwhile
+        app.load_branches(&mut Repository::open(&dir).unwrap())
+            .unwrap();
+        let still_known = app.pending_details.is_empty();
+        let details = |name| {
+            let branch = app.branches.iter().find(|branch| branch.name == name);
+            branch.map(|branch| (branch.subject.as_deref(), branch.is_merged))
+        };
+
+        assert_eq!(before, ["feat", "a-merged", "main"]);
+        assert_eq!(pending, 3);
+        assert!(unread);
+        assert_eq!(after, ["feat", "main", "a-merged"]);
+        assert!(still_known);
+        assert_eq!(details("feat"), Some((Some("two"), false)));
+        assert_eq!(details("a-merged"), Some((Some("one"), true)));
+    }
+
+    #[test]
+    fn pruning_marks_the_merged_branches_before_their_details_are_in() {
+        let dir = TempRepo::new("prune");
+        merged_and_unmerged(&dir);
+        OPEN_REPO.set(Some(dir.to_path_buf()));
+
+        let mut app = App::with_branches(Vec::new(), Config::default());
+        app.background_details = true;
+        app.refresh().unwrap();
+        app.toggle_prune_merged().unwrap();
+        let marked = app.marked.clone();
+        app.toggle_prune_merged().unwrap();
+        OPEN_REPO.set(None);
+
+        assert_eq!(marked, HashSet::from(["a-merged".to_string()]));
+        assert!(app.marked.is_empty());
+        assert_eq!(app.filtered_branches.len(), 3);
+    }
+
+    #[test]
+    fn deletes_merged_branches_without_asking_when_told_to() {
+        let dir = TempRepo::new("fast-delete");
+        merged_and_unmerged(&dir);
+        OPEN_REPO.set(Some(dir.to_path_buf()));
+
+        let config = Config {
+            confirm_merged_deletes: false,
+            ..Config::default()
+        };
+        let mut app = App::with_branches(Vec::new(), config);
+        app.refresh().unwrap();
+        app.select_branch("a-merged");
+        app.start_delete().unwrap();
+        let deleted = app.prompt.is_none() && app.select_branch("a-merged");
+        // Unmerged ones are still asked about
+        app.select_branch("feat");
+        app.start_delete().unwrap();
+        OPEN_REPO.set(None);
+
+        assert!(!deleted);
+        assert!(matches!(app.prompt, Some(Prompt::DeleteUnmerged)));
+        assert_eq!(app.branches.len(), 2);
+    }
+
+    #[test]
+    fn print_mode_picks_instead_of_checking_out() {
+        let mut current = branch("main", 0);
+        current.is_current = true;
+        let mut app = App::with_branches(vec![current, branch("feat", 1)], Config::default());
+        app.print = true;
+        app.read_only = true;
+
+        assert!(matches!(
+            activate(&mut app).unwrap(),
+            Some(Outcome::Picked(name)) if name == "main"
+        ));
+
+        for c in "nope".chars() {
+            app.add_char(c);
+        }
+        assert!(activate(&mut app).unwrap().is_none());
+        assert_eq!(app.status_message.as_deref(), Some("No branch matches"));
+    }
+
+    #[test]
+    fn dash_switches_back_to_the_previous_branch() {
+        let mut current = branch("main", 0);
+        current.is_current = true;
+        let mut app = App::with_branches(
+            vec![current, branch("newer", 1), branch("feat", 2)],
+            Config::default(),
+        );
+        app.print = true;
+        app.previous_branch = Some("feat".to_string());
+        app.resort();
+        assert_eq!(app.branches[0].name, "feat");
+
+        let dash = KeyEvent::new(KeyCode::Char('-'), KeyModifiers::NONE);
+        assert!(matches!(
+            handle_key(&mut app, dash).unwrap(),
+            Some(Outcome::Picked(name)) if name == "feat"
+        ));
+
+        // Typed, it's part of a name
+        app.add_char('x');
+        assert!(handle_key(&mut app, dash).unwrap().is_none());
+        assert_eq!(app.filter, "x-");
+    }
+
+    #[test]
+    fn tree_view_collapses_and_expands_groups() {
+        let names = ["feature/login", "main", "feature/search", "fix/typo"];
+        let branches = names
+            .iter()
+            .enumerate()
+            .map(|(days, name)| branch(name, days as i64))
+            .collect();
+        let mut app = App::with_branches(branches, Config::default());
+        app.print = true;
+        app.toggle_tree_view();
+        let rows = |app: &App| -> Vec<String> {
+            (0..app.filtered_branches.len())
+                .map(|idx| match &app.tree_rows[idx].collapsed {
+                    Some(group) => format!("+{}", group.prefix),
+                    None => app.branches[app.filtered_branches[idx]].name.clone(),
+                })
+                .collect()
+        };
+        assert_eq!(rows(&app), ["+feature/", "main", "fix/typo"]);
+
+        // A collapsed group isn't a branch to pick, Enter opens it instead
+        let key = |code| KeyEvent::new(code, KeyModifiers::NONE);
+        assert!(app.selected_branch().is_none());
+        assert!(handle_key(&mut app, key(KeyCode::Enter)).unwrap().is_none());
+        assert_eq!(
+            rows(&app),
+            ["feature/login", "feature/search", "main", "fix/typo"]
+        );
+
+        // Collapsing from a branch in it selects the group
+        app.list_state.select(Some(1));
+        handle_key(&mut app, key(KeyCode::Left)).unwrap();
+        assert_eq!(app.selected_group().map(|group| group.count), Some(2));
+
+        // Selecting a branch in it opens it again
+        assert!(app.select_branch("feature/search"));
+        assert_eq!(selected_name(&app), Some("feature/search"));
+
+        // Filtering searches every group, ranked as usual
+        app.toggle_tree_view();
+        app.add_char('s');
+        app.toggle_tree_view();
+        assert!(app.tree_rows.is_empty());
+        assert_eq!(selected_name(&app), Some("feature/search"));
+    }
+
+    #[test]
+    fn clicks_select_rows_and_double_clicks_pick_them() {
+        let branches = ["newest", "newer", "older"]
+            .iter()
+            .enumerate()
+            .map(|(days, name)| branch(name, days as i64))
+            .collect();
+        let mut app = App::with_branches(branches, Config::default());
+        app.print = true;
+        app.list_area = Rect::new(0, 1, 80, 10);
+        // A header above the second branch
+        app.list_rows = vec![0, 2, 3];
+        let click = |row| MouseEvent {
+            kind: MouseEventKind::Down(MouseButton::Left),
+            column: 10,
+            row,
+            modifiers: KeyModifiers::NONE,
+        };
+
+        assert!(handle_mouse(&mut app, click(4)).unwrap().is_none());
+        assert_eq!(selected_name(&app), Some("older"));
+        // The header and the rows past the end are nothing to select
+        for row in [2, 5, 20] {
+            handle_mouse(&mut app, click(row)).unwrap();
+            assert_eq!(selected_name(&app), Some("older"));
+        }
+
+        handle_mouse(&mut app, click(3)).unwrap();
+        assert!(matches!(
+            handle_mouse(&mut app, click(3)).unwrap(),
+            Some(Outcome::Picked(name)) if name == "newer"
+        ));
+    }
+
+    #[test]
+    fn a_pattern_naming_one_branch_picks_it() {
+        let pick = |pattern: &str| {
+            let names = ["main", "maintenance", "feature/login", "fix/login"];
+            let branches = names.iter().map(|name| branch(name, 1)).collect();
+            let mut app = App::with_branches(branches, Config::default());
+            app.print = true;
+            app.filter = pattern.to_string();
+            app.update_filter();
+            match app.pick_sole_match().unwrap() {
+                Some(Outcome::Picked(name)) => Some(name),
+                _ => None,
+            }
+        };
+
+        assert_eq!(pick("feat").as_deref(), Some("feature/login"));
+        // Named exactly, among others that match too
+        assert_eq!(pick("main").as_deref(), Some("main"));
+        // Several, or none, are left for the picker
+        assert_eq!(pick("login"), None);
+        assert_eq!(pick("nope"), None);
+    }
+
+    #[test]
+    fn remote_branches_check_out_as_local_tracking_branches() {
+        let dir = TempRepo::new("track");
+        let (origin, clone) = (dir.join("origin"), dir.join("clone"));
+        std::fs::create_dir_all(&origin).unwrap();
+        let date = "2024-01-01T12:00:00Z";
+        git(&origin, &["init", "-q", "-b", "main"], date);
+        git(
+            &origin,
+            &["commit", "-q", "--allow-empty", "-m", "one"],
+            date,
+        );
+        git(&origin, &["branch", "feat"], date);
+        git(&dir, &["clone", "-q", "origin", "clone"], date);
+        let repo = Repository::open(&clone).unwrap();
+
+        let first = checkout_ref(&repo, RefKind::Remote, "origin/feat");
+        let upstream = repo
+            .find_branch("feat", BranchType::Local)
+            .and_then(|branch| branch.upstream())
+            .map(|upstream| upstream.name().unwrap().map(str::to_string));
+        // The local branch is there now, so it's the one checked out
+        git(&clone, &["checkout", "-q", "main"], date);
+        git(
+            &clone,
+            &["commit", "-q", "--allow-empty", "-m", "two"],
+            date,
+        );
+        git(&clone, &["branch", "-q", "-f", "feat", "main"], date);
+        let second = checkout_ref(&repo, RefKind::Remote, "origin/feat");
+        let main = repo.revparse_single("main").unwrap().id();
+        let head = repo.head().unwrap().target().unwrap();
+
+        assert_eq!(first.unwrap(), "feat");
+        assert_eq!(upstream.unwrap().as_deref(), Some("origin/feat"));
+        assert_eq!(second.unwrap(), "feat");
+        assert_eq!(head, main);
+    }
+
+    #[test]
+    fn diff_stat_counts_what_the_branch_changed_since_forking() {
+        let dir = TempRepo::new("diffstat");
+        let date = "2024-01-01T12:00:00Z";
+        git(&dir, &["init", "-q", "-b", "main"], date);
+        std::fs::write(dir.join("a.txt"), "one\ntwo\n").unwrap();
+        std::fs::write(
+            dir.join("old.txt"),
+            "a long enough line to be found renamed\n",
+        )
+        .unwrap();
+        git(&dir, &["add", "."], date);
+        git(&dir, &["commit", "-q", "-m", "one"], date);
+        git(&dir, &["checkout", "-q", "-b", "feature"], date);
+        std::fs::write(dir.join("a.txt"), "one\n2\nthree\n").unwrap();
+        std::fs::write(dir.join("logo.png"), [0u8, 159, 146, 150]).unwrap();
+        git(&dir, &["mv", "old.txt", "new.txt"], date);
+        git(&dir, &["add", "."], date);
+        git(&dir, &["commit", "-q", "-m", "two"], date);
+        // What main went on to do isn't the branch's doing
+        git(&dir, &["checkout", "-q", "main"], date);
+        std::fs::write(dir.join("main.txt"), "main\n").unwrap();
+        git(&dir, &["add", "."], date);
+        git(&dir, &["commit", "-q", "-m", "three"], date);
+
+        let repo = Repository::open(&dir).unwrap();
+        let head = repo.head().unwrap().target().unwrap();
+        let tip = repo.revparse_single("feature").unwrap().id();
+        let stat = diff_stat(&repo, head, tip).unwrap();
+        let nothing = diff_stat(&repo, head, head).unwrap();
+
+        assert_eq!(
+            stat.files,
+            [
+                FileStat {
+                    path: "a.txt".to_string(),
+                    lines: Some((2, 1)),
+                },
+                FileStat {
+                    path: "logo.png".to_string(),
+                    lines: None,
+                },
+                FileStat {
+                    path: "old.txt → new.txt".to_string(),
+                    lines: Some((0, 0)),
+                },
+            ]
+        );
+        assert_eq!((stat.insertions, stat.deletions), (2, 1));
+        assert!(nothing.files.is_empty());
+    }
+
+    #[test]
+    fn l_and_d_switch_the_pane_or_close_it() {
+        let mut app = App::with_branches(vec![branch("main", 0)], Config::default());
+        app.toggle_preview(PreviewKind::DiffStat);
+        assert!(app.show_preview);
+        app.toggle_preview(PreviewKind::Commits);
+        assert_eq!(
+            (app.show_preview, app.preview_kind),
+            (true, PreviewKind::Commits)
+        );
+        app.toggle_preview(PreviewKind::Commits);
+        assert!(!app.show_preview);
+    }
+
+    #[test]
+    fn preview_lists_the_latest_commits_first() {
+        let dir = TempRepo::new("preview");
+        git(&dir, &["init", "-q", "-b", "main"], "2024-01-01T12:00:00Z");
+        git(
+            &dir,
+            &["commit", "-q", "--allow-empty", "-m", "one"],
+            "2024-01-01T12:00:00Z",
+        );
+        git(
+            &dir,
+            &["commit", "-q", "--allow-empty", "-m", "two\n\nbody"],
+            "2024-01-02T12:00:00Z",
+        );
+        git(
+            &dir,
+            &["commit", "-q", "--allow-empty", "-m", "three"],
+            "2024-01-03T12:00:00Z",
+        );
+        let repo = Repository::open(&dir).unwrap();
+        let tip = repo.head().unwrap().target().unwrap();
+
+        let all = recent_commits(&repo, tip, PREVIEW_COMMITS).unwrap();
+        let latest = recent_commits(&repo, tip, 2).unwrap();
+
+        let subjects: Vec<_> = all.iter().map(|commit| commit.subject.as_str()).collect();
+        // Only the first line of a message
+        assert_eq!(subjects, ["three", "two", "one"]);
+        assert_eq!(all[0].author, "gb");
+        assert_eq!(all[0].short_id.len(), 7);
+        assert_eq!(latest.len(), 2);
+
+        // Scrolling stops at the last commit
+        let mut app = App::with_branches(Vec::new(), Config::default());
+        app.preview = Some((tip, Ok(all)));
+        app.scroll_preview(5);
+        assert_eq!(app.preview_scroll, 2);
+        app.scroll_preview(-1);
+        assert_eq!(app.preview_scroll, 1);
+    }
 }
@@ -0,0 +1,408 @@
+use anyhow::{Result, anyhow};
+use clap::ValueEnum;
+use gb::config::Theme;
+use ratatui::style::{Color, Modifier, Style};
+use std::{
+    collections::BTreeMap,
+    env,
+    io::{self, IsTerminal},
+};
+
+/// How many colors to draw with, from `--colors`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ColorMode {
+    /// Guess from `NO_COLOR`, `COLORTERM` and `TERM`, with no colors when
+    /// the picker isn't drawn on a terminal
+    #[default]
+    Auto,
+    /// The 16 named terminal colors
+    #[value(name = "16")]
+    Ansi16,
+    /// Shades of gray from the 256-color palette, for more contrast
+    #[value(name = "256")]
+    Ansi256,
+    /// No colors, only bold and reverse video
+    None,
+}
+
+impl ColorMode {
+    /// Resolves `Auto` to what the terminal looks like it supports
+    fn detect(self) -> ColorMode {
+        if self != ColorMode::Auto {
+            return self;
+        }
+        // https://no-color.org: set and not empty means no colors
+        if env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty()) {
+            return ColorMode::None;
+        }
+        // The picker draws on stderr, so that's the one that counts
+        if !io::stderr().is_terminal() {
+            return ColorMode::None;
+        }
+        let term = env::var("TERM").unwrap_or_default();
+        if term.is_empty() || term == "dumb" {
+            return ColorMode::None;
+        }
+        let colorterm = env::var("COLORTERM").unwrap_or_default();
+        if term.contains("256color") || colorterm == "truecolor" || colorterm == "24bit" {
+            ColorMode::Ansi256
+        } else {
+            ColorMode::Ansi16
+        }
+    }
+}
+
+/// Every style the UI draws with, by what it's for, so the color mode is
+/// decided once here instead of at each use
+#[derive(Clone, Copy, Debug)]
+pub struct Palette {
+    /// Secondary text: headers, ages, badges, hints
+    pub dim: Style,
+    /// The selection marker and the fetch spinner
+    pub accent: Style,
+    /// The selected branch name
+    pub selected: Style,
+    /// The other branch names
+    pub name: Style,
+    /// The checked out branch and its marker
+    pub current: Style,
+    /// The filter line
+    pub filter: Style,
+    /// The characters of a branch name the filter matched, over its style
+    pub matched: Style,
+    /// Questions and text entry in the status line
+    pub prompt: Style,
+    /// Things worth a look: divergence, stashes, favorites
+    pub notice: Style,
+    /// Things that will be lost or are gone: marked deletions, gone upstreams
+    pub danger: Style,
+    /// Branch descriptions
+    pub description: Style,
+}
+
+/// What the `styles` config can set, in the order they're listed when one
+/// isn't known
+const ROLES: [&str; 11] = [
+    "dim",
+    "accent",
+    "selected",
+    "name",
+    "current",
+    "filter",
+    "matched",
+    "prompt",
+    "notice",
+    "danger",
+    "description",
+];
+
+/// Resolves `Theme::Auto` by the background color in `COLORFGBG`, like
+/// `15;0`: white (7) and the bright colors but dark gray (9 to 15) are light
+fn detect_theme(theme: Theme) -> Theme {
+    if theme != Theme::Auto {
+        return theme;
+    }
+    let background = env::var("COLORFGBG").ok().and_then(|value| {
+        value
+            .rsplit(';')
+            .next()
+            .and_then(|color| color.parse::<u8>().ok())
+    });
+    match background {
+        Some(7 | 9..=15) => Theme::Light,
+        _ => Theme::Dark,
+    }
+}
+
+impl Palette {
+    pub fn new(mode: ColorMode, theme: Theme) -> Palette {
+        let mode = mode.detect();
+        match (detect_theme(theme), mode) {
+            (Theme::Monochrome, _) | (_, ColorMode::None) => Palette::monochrome(),
+            (Theme::Light, ColorMode::Ansi256) => Palette::light256(),
+            (Theme::Light, _) => Palette::light16(),
+            (_, ColorMode::Ansi256) => Palette::dark256(),
+            _ => Palette::ansi16(),
+        }
+    }
+
+    /// The palette with the styles the config sets in place of the theme's,
+    /// each written like `bold yellow`, `italic 244` or `black on #ffd700`.
+    /// Without colors only their bold, italics and the like are kept.
+    pub fn with_styles(
+        mut self,
+        mode: ColorMode,
+        styles: &BTreeMap<String, String>,
+    ) -> Result<Palette> {
+        let colorless = mode.detect() == ColorMode::None;
+        for (role, spec) in styles {
+            let mut style = parse_style(spec)
+                .map_err(|err| anyhow!("Invalid style '{}' for {}: {}", spec, role, err))?;
+            if colorless {
+                style.fg = None;
+                style.bg = None;
+            }
+            *self.role_mut(role).ok_or_else(|| {
+                anyhow!(
+                    "Unknown style '{}' in the styles config, expected one of {}",
+                    role,
+                    ROLES.join(", ")
+                )
+            })? = style;
+        }
+        Ok(self)
+    }
+
+    fn role_mut(&mut self, role: &str) -> Option<&mut Style> {
+        Some(match role {
+            "dim" => &mut self.dim,
+            "accent" => &mut self.accent,
+            "selected" => &mut self.selected,
+            "name" => &mut self.name,
+            "current" => &mut self.current,
+            "filter" => &mut self.filter,
+            "matched" => &mut self.matched,
+            "prompt" => &mut self.prompt,
+            "notice" => &mut self.notice,
+            "danger" => &mut self.danger,
+            "description" => &mut self.description,
+            _ => return None,
+        })
+    }
+
+    fn monochrome() -> Palette {
+        Palette {
+            dim: Style::default(),
+            accent: Style::default().add_modifier(Modifier::BOLD),
+            // Without colors, the selection only stands out reversed
+            selected: Style::default().add_modifier(Modifier::REVERSED | Modifier::BOLD),
+            name: Style::default(),
+            current: Style::default().add_modifier(Modifier::BOLD),
+            filter: Style::default().add_modifier(Modifier::BOLD),
+            matched: Style::default().add_modifier(Modifier::UNDERLINED),
+            prompt: Style::default().add_modifier(Modifier::BOLD),
+            notice: Style::default(),
+            danger: Style::default().add_modifier(Modifier::BOLD),
+            description: Style::default().add_modifier(Modifier::ITALIC),
+        }
+    }
+
+    fn dark256() -> Palette {
+        Palette {
+            dim: Style::default().fg(Color::Indexed(244)),
+            name: Style::default().fg(Color::Indexed(250)),
+            description: Style::default()
+                .fg(Color::Indexed(244))
+                .add_modifier(Modifier::ITALIC),
+            ..Palette::ansi16()
+        }
+    }
+
+    /// The palette with the accent and highlight colors the config sets in
+    /// place of its own; without colors they're left out
+    pub fn with_colors(
+        mut self,
+        mode: ColorMode,
+        accent: Option<&str>,
+        highlight: Option<&str>,
+    ) -> Result<Palette> {
+        if mode.detect() == ColorMode::None {
+            return Ok(self);
+        }
+        if let Some(accent) = accent {
+            self.accent = self.accent.fg(parse_color(accent, "accent_color")?);
+        }
+        if let Some(highlight) = highlight {
+            self.selected = self.selected.fg(parse_color(highlight, "highlight_color")?);
+        }
+        Ok(self)
+    }
+
+    fn ansi16() -> Palette {
+        Palette {
+            dim: Style::default().fg(Color::DarkGray),
+            accent: Style::default().fg(Color::Magenta),
+            selected: Style::default()
+                .fg(Color::White)
+                .add_modifier(Modifier::BOLD),
+            name: Style::default().fg(Color::Gray),
+            current: Style::default().fg(Color::Green),
+            filter: Style::default().fg(Color::Cyan),
+            matched: Style::default()
+                .fg(Color::Cyan)
+                .add_modifier(Modifier::BOLD),
+            prompt: Style::default().fg(Color::Yellow),
+            notice: Style::default().fg(Color::Yellow),
+            danger: Style::default().fg(Color::Red),
+            description: Style::default()
+                .fg(Color::DarkGray)
+                .add_modifier(Modifier::ITALIC),
+        }
+    }
+
+    /// Dark text for light backgrounds, where gray and white would barely
+    /// show and yellow not at all
+    fn light16() -> Palette {
+        Palette {
+            dim: Style::default().fg(Color::DarkGray),
+            accent: Style::default().fg(Color::Magenta),
+            selected: Style::default()
+                .fg(Color::Black)
+                .add_modifier(Modifier::BOLD),
+            name: Style::default().fg(Color::Reset),
+            current: Style::default().fg(Color::Green),
+            filter: Style::default().fg(Color::Blue),
+            matched: Style::default()
+                .fg(Color::Blue)
+                .add_modifier(Modifier::BOLD),
+            prompt: Style::default().fg(Color::Magenta),
+            notice: Style::default().fg(Color::Magenta),
+            danger: Style::default().fg(Color::Red),
+            description: Style::default()
+                .fg(Color::DarkGray)
+                .add_modifier(Modifier::ITALIC),
+        }
+    }
+
+    fn light256() -> Palette {
+        Palette {
+            dim: Style::default().fg(Color::Indexed(244)),
+            selected: Style::default()
+                .fg(Color::Indexed(16))
+                .add_modifier(Modifier::BOLD),
+            name: Style::default().fg(Color::Indexed(238)),
+            current: Style::default().fg(Color::Indexed(28)),
+            filter: Style::default().fg(Color::Indexed(25)),
+            matched: Style::default()
+                .fg(Color::Indexed(25))
+                .add_modifier(Modifier::BOLD),
+            prompt: Style::default().fg(Color::Indexed(130)),
+            notice: Style::default().fg(Color::Indexed(130)),
+            danger: Style::default().fg(Color::Indexed(160)),
+            description: Style::default()
+                .fg(Color::Indexed(244))
+                .add_modifier(Modifier::ITALIC),
+            ..Palette::light16()
+        }
+    }
+}
+
+/// A style written as words: colors by name, number or `#rrggbb`, the one
+/// after `on` for the background, and `bold`, `dim`, `italic`, `underlined`
+/// or `reversed`
+fn parse_style(spec: &str) -> Result<Style> {
+    let color = |word: &str| {
+        word.parse::<Color>()
+            .map_err(|_| anyhow!("'{}' isn't a color", word))
+    };
+    let mut style = Style::default();
+    let mut words = spec.split_whitespace();
+    while let Some(word) = words.next() {
+        style = match word {
+            "bold" => style.add_modifier(Modifier::BOLD),
+            "dim" => style.add_modifier(Modifier::DIM),
+            "italic" => style.add_modifier(Modifier::ITALIC),
+            "underlined" => style.add_modifier(Modifier::UNDERLINED),
+            "reversed" => style.add_modifier(Modifier::REVERSED),
+            "on" => style.bg(color(
+                words
+                    .next()
+                    .ok_or_else(|| anyhow!("'on' needs a color after it"))?,
+            )?),
+            _ => style.fg(color(word)?),
+        };
+    }
+    Ok(style)
+}
+
+/// A color by name, number in the 256-color palette or `#rrggbb`, for the
+/// config `key`
+fn parse_color(color: &str, key: &str) -> Result<Color> {
+    color.parse().map_err(|_| {
+        anyhow!(
+            "Invalid {} '{}' in the config, expected a color name, a number up to 255 or #rrggbb",
+            key,
+            color
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_styles_and_replaces_the_themes() {
+        assert_eq!(
+            parse_style("bold yellow on #102030").unwrap(),
+            Style::default()
+                .fg(Color::Yellow)
+                .bg(Color::Rgb(16, 32, 48))
+                .add_modifier(Modifier::BOLD)
+        );
+        assert_eq!(
+            parse_style("italic 244").unwrap(),
+            Style::default()
+                .fg(Color::Indexed(244))
+                .add_modifier(Modifier::ITALIC)
+        );
+        assert_eq!(
+            parse_style("bold purplish").unwrap_err().to_string(),
+            "'purplish' isn't a color"
+        );
+        assert!(parse_style("white on").is_err());
+
+        let styles = BTreeMap::from([("selected".to_string(), "bold blue".to_string())]);
+        let palette = Palette::new(ColorMode::Ansi16, Theme::Light)
+            .with_styles(ColorMode::Ansi16, &styles)
+            .unwrap();
+        assert_eq!(palette.selected, parse_style("bold blue").unwrap());
+        assert_eq!(palette.prompt, Palette::light16().prompt);
+
+        // Without colors only the rest of the style is left
+        let palette = Palette::new(ColorMode::None, Theme::Light)
+            .with_styles(ColorMode::None, &styles)
+            .unwrap();
+        assert_eq!(
+            palette.selected,
+            Style::default().add_modifier(Modifier::BOLD)
+        );
+
+        let unknown = BTreeMap::from([("branch".to_string(), "red".to_string())]);
+        let err = Palette::ansi16()
+            .with_styles(ColorMode::Ansi16, &unknown)
+            .unwrap_err();
+        assert!(err.to_string().starts_with("Unknown style 'branch'"));
+    }
+
+    #[test]
+    fn the_config_sets_the_accent_and_highlight_colors() {
+        let palette = Palette::new(ColorMode::Ansi16, Theme::Dark)
+            .with_colors(ColorMode::Ansi16, Some("light-blue"), Some("#ffd75f"))
+            .unwrap();
+        assert_eq!(palette.accent, Style::default().fg(Color::LightBlue));
+        assert_eq!(
+            palette.selected,
+            Style::default()
+                .fg(Color::Rgb(255, 215, 95))
+                .add_modifier(Modifier::BOLD)
+        );
+
+        // Without colors the selection stays reversed, and nothing else changes
+        let plain = Palette::new(ColorMode::None, Theme::Dark)
+            .with_colors(ColorMode::None, Some("red"), Some("red"))
+            .unwrap();
+        assert_eq!(
+            plain.selected,
+            Palette::new(ColorMode::None, Theme::Dark).selected
+        );
+
+        let err = Palette::ansi16()
+            .with_colors(ColorMode::Ansi16, Some("purplish"), None)
+            .unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("Invalid accent_color 'purplish'")
+        );
+    }
+}
@@ -0,0 +1,100 @@
+use anyhow::{Context, Result, bail};
+use git2::Repository;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// A checkout listed with `--repos`, by the name its branches are prefixed
+/// with
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Checkout {
+    pub name: String,
+    pub path: PathBuf,
+}
+
+/// The checkouts in `paths`: each one that's a checkout itself, and the
+/// ones directly inside the rest, ordered by name. Bare repositories are
+/// left out, having nothing to check out into, and of two with the same
+/// name only the first is kept.
+pub fn find_checkouts(paths: &[PathBuf]) -> Result<Vec<Checkout>> {
+    let mut checkouts: Vec<Checkout> = Vec::new();
+    for path in paths {
+        let found = if is_checkout(path) {
+            vec![path.clone()]
+        } else {
+            let entries = fs::read_dir(path).with_context(|| {
+                format!("Failed to look for repositories in {}", path.display())
+            })?;
+            let mut found: Vec<PathBuf> = entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| is_checkout(path))
+                .collect();
+            found.sort();
+            found
+        };
+        for path in found {
+            let Some(name) = path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+            else {
+                continue;
+            };
+            if !checkouts.iter().any(|checkout| checkout.name == name) {
+                checkouts.push(Checkout { name, path });
+            }
+        }
+    }
+
+    if checkouts.is_empty() {
+        let paths: Vec<_> = paths
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect();
+        bail!("No git checkouts in {}", paths.join(", "));
+    }
+    Ok(checkouts)
+}
+
+fn is_checkout(path: &Path) -> bool {
+    path.join(".git").exists() && Repository::open(path).is_ok_and(|repo| !repo.is_bare())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_repo::TempRepo;
+    use std::process::{Command, Stdio};
+
+    #[test]
+    fn finds_the_checkouts_in_a_directory() {
+        let dir = TempRepo::new("repos");
+        let git = |dir: &Path, args: &[&str]| {
+            let status = Command::new("git")
+                .args(args)
+                .current_dir(dir)
+                .stdout(Stdio::null())
+                .status()
+                .unwrap();
+            assert!(status.success(), "git {args:?} failed");
+        };
+        fs::create_dir_all(dir.join("notes")).unwrap();
+        fs::create_dir_all(dir.join("docs")).unwrap();
+        git(&dir, &["init", "-q", "web"]);
+        git(&dir, &["init", "-q", "api"]);
+        git(&dir.join("notes"), &["init", "-q", "--bare"]);
+
+        let found = find_checkouts(&[dir.to_path_buf()]).unwrap();
+        let names: Vec<_> = found
+            .iter()
+            .map(|checkout| checkout.name.as_str())
+            .collect();
+        let single = find_checkouts(&[dir.join("api"), dir.to_path_buf()]).unwrap();
+        let empty = find_checkouts(&[dir.join("notes")]).unwrap_err();
+
+        assert_eq!(names, ["api", "web"]);
+        assert_eq!(single.len(), 2);
+        assert_eq!(single[0].path, dir.join("api"));
+        assert!(empty.to_string().starts_with("No git checkouts in"));
+    }
+}
@@ -0,0 +1,242 @@
+use crate::cli::{Args, Shell};
+use clap::{Arg, CommandFactory};
+
+/// Ref names offered wherever a branch or filter goes
+const BRANCHES: &str = "git for-each-ref --format='%(refname:short)' refs/heads 2>/dev/null";
+
+/// What `gb init` prints for `eval`ing (or `source`ing, in fish): a `gb`
+/// function that runs gb and changes into the worktree it picks or adds,
+/// which gb writes to a temp file so its output still reaches the terminal,
+/// and completions for its options, subcommands and branches. Anything that
+/// doesn't open the picker goes straight through.
+pub fn init_script(shell: Shell) -> String {
+    let command = Args::command();
+    let options: Vec<&Arg> = command
+        .get_arguments()
+        .filter(|arg| arg.get_long().is_some())
+        .collect();
+    let subcommands: Vec<&str> = command
+        .get_subcommands()
+        .map(|sub| sub.get_name())
+        .collect();
+    match shell {
+        Shell::Bash => format!(
+            "{}\n{}",
+            POSIX_FUNCTION,
+            bash_completion(&options, &subcommands)
+        ),
+        Shell::Zsh => format!(
+            "{}\n{}",
+            POSIX_FUNCTION,
+            zsh_completion(&options, &subcommands)
+        ),
+        Shell::Fish => format!("{}\n{}", FISH_FUNCTION, fish_completion(&command, &options)),
+    }
+}
+
+const POSIX_FUNCTION: &str = r#"gb() {
+    case "$1" in
+        init|switch|help)
+            command gb "$@"
+            return
+            ;;
+    esac
+    local arg tmp dir code
+    for arg in "$@"; do
+        case "$arg" in
+            -h|--help|-V|--version|--list|--jsonl|--current|--print|--print-path|--print-path-to)
+                command gb "$@"
+                return
+                ;;
+        esac
+    done
+    tmp="$(mktemp)" || return
+    command gb --print-path-to "$tmp" "$@"
+    code=$?
+    dir="$(cat -- "$tmp")"
+    rm -f -- "$tmp"
+    if [ "$code" -eq 0 ] && [ -n "$dir" ] && [ -d "$dir" ]; then
+        cd -- "$dir"
+    fi
+    return "$code"
+}
+"#;
+
+const FISH_FUNCTION: &str = r#"function gb --description 'Pick a git branch, changing into the worktree gb picks'
+    if contains -- "$argv[1]" init switch help
+        command gb $argv
+        return
+    end
+    for arg in $argv
+        if contains -- $arg -h --help -V --version --list --jsonl --current --print --print-path --print-path-to
+            command gb $argv
+            return
+        end
+    end
+    set -l tmp (mktemp); or return
+    command gb --print-path-to $tmp $argv
+    set -l code $status
+    set -l dir (cat $tmp)
+    rm -f $tmp
+    if test $code -eq 0 -a -n "$dir"; and test -d "$dir"
+        cd $dir
+    end
+    return $code
+end
+"#;
+
+/// The values an option takes, when it's one of a few
+fn choices(arg: &Arg) -> Vec<String> {
+    arg.get_possible_values()
+        .iter()
+        .filter(|value| !value.is_hide_set())
+        .map(|value| value.get_name().to_string())
+        .collect()
+}
+
+fn takes_value(arg: &Arg) -> bool {
+    arg.get_action().takes_values()
+}
+
+fn long(arg: &Arg) -> String {
+    format!("--{}", arg.get_long().unwrap_or_default())
+}
+
+/// `case` arms completing the values of the options that take one: their
+/// choices, or nothing for free text like a glob
+fn value_arms(options: &[&Arg], complete: impl Fn(&str) -> String) -> String {
+    let mut arms = String::new();
+    for arg in options.iter().filter(|arg| takes_value(arg)) {
+        let choices = choices(arg);
+        arms.push_str(&format!(
+            "        {}) {}; return ;;\n",
+            long(arg),
+            complete(&choices.join(" "))
+        ));
+    }
+    arms
+}
+
+fn bash_completion(options: &[&Arg], subcommands: &[&str]) -> String {
+    let flags: Vec<String> = options.iter().map(|arg| long(arg)).collect();
+    format!(
+        r#"_gb() {{
+    local cur="${{COMP_WORDS[COMP_CWORD]}}" prev="${{COMP_WORDS[COMP_CWORD-1]}}"
+    case "$prev" in
+{arms}        init) COMPREPLY=($(compgen -W "bash zsh fish" -- "$cur")); return ;;
+    esac
+    if [[ "$cur" == -* ]]; then
+        COMPREPLY=($(compgen -W "{flags} --help --version" -- "$cur"))
+    else
+        local words="$({branches})"
+        [ "$COMP_CWORD" -eq 1 ] && words="{subcommands} $words"
+        COMPREPLY=($(compgen -W "$words" -- "$cur"))
+    fi
+}}
+complete -F _gb gb
+"#,
+        arms = value_arms(options, |choices| format!(
+            "COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))",
+            choices
+        )),
+        flags = flags.join(" "),
+        branches = BRANCHES,
+        subcommands = subcommands.join(" "),
+    )
+}
+
+fn zsh_completion(options: &[&Arg], subcommands: &[&str]) -> String {
+    let flags: Vec<String> = options.iter().map(|arg| long(arg)).collect();
+    format!(
+        r#"_gb() {{
+    case "${{words[CURRENT-1]}}" in
+{arms}        init) compadd -- bash zsh fish; return ;;
+    esac
+    if [[ "$PREFIX" == -* ]]; then
+        compadd -- {flags} --help --version
+    else
+        (( CURRENT == 2 )) && compadd -- {subcommands}
+        compadd -- ${{(f)"$({branches})"}}
+    fi
+}}
+(( $+functions[compdef] )) && compdef _gb gb
+"#,
+        arms = value_arms(options, |choices| format!("compadd -- {}", choices)),
+        flags = flags.join(" "),
+        branches = BRANCHES,
+        subcommands = subcommands.join(" "),
+    )
+}
+
+fn fish_completion(command: &clap::Command, options: &[&Arg]) -> String {
+    let mut lines = vec![
+        "complete -c gb -f".to_string(),
+        format!("complete -c gb -a \"({})\"", BRANCHES),
+        "complete -c gb -n '__fish_seen_subcommand_from init' -a 'bash zsh fish'".to_string(),
+    ];
+    for sub in command.get_subcommands() {
+        lines.push(format!(
+            "complete -c gb -n __fish_use_subcommand -a {} -d {}",
+            sub.get_name(),
+            fish_quote(&summary(sub.get_about()))
+        ));
+    }
+    for arg in options {
+        let mut line = format!(
+            "complete -c gb -l {} -d {}",
+            arg.get_long().unwrap_or_default(),
+            fish_quote(&summary(arg.get_help()))
+        );
+        if takes_value(arg) {
+            line.push_str(" -x");
+            let choices = choices(arg);
+            if !choices.is_empty() {
+                line.push_str(&format!(" -a {}", fish_quote(&choices.join(" "))));
+            }
+        }
+        lines.push(line);
+    }
+    lines.join("\n") + "\n"
+}
+
+/// The help text up to its first `;`, which is enough to tell options apart
+fn summary(help: Option<&clap::builder::StyledStr>) -> String {
+    let help = help.map(|help| help.to_string()).unwrap_or_default();
+    match help.split_once(';') {
+        Some((first, _)) => first.trim().to_string(),
+        None => help,
+    }
+}
+
+/// Single-quoted for fish, where only `\` and `'` need escaping
+fn fish_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    #[test]
+    fn completes_options_and_their_values() {
+        let bash = init_script(Shell::Bash);
+        assert!(bash.contains("--read-only"));
+        assert!(bash.contains("--sort) COMPREPLY=($(compgen -W \"recent"));
+        assert!(bash.contains("--only) COMPREPLY=($(compgen -W \"\""));
+        assert!(bash.contains("words=\"switch init $words\""));
+        // The path comes back through a file, leaving stdout on the terminal
+        assert!(bash.contains("command gb --print-path-to \"$tmp\" \"$@\"\n"));
+        assert!(!bash.contains("$(command gb"));
+
+        let fish = init_script(Shell::Fish);
+        assert!(
+            fish.contains("complete -c gb -l colors -d 'How many colors to draw with' -x -a '")
+        );
+        assert_eq!(fish_quote("it's `a\\b`"), r"'it\'s `a\\b`'");
+
+        // Only checked where bash is installed
+        let status = Command::new("bash").args(["-n", "-c", &bash]).status();
+        assert!(status.is_err() || status.unwrap().success());
+    }
+}
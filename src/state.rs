@@ -0,0 +1,137 @@
+use crate::forge::PullRequest;
+use anyhow::{Context, Result};
+use git2::Repository;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    env, fs, io,
+    path::PathBuf,
+};
+
+/// What gb remembers between runs, stored in `$XDG_DATA_HOME/gb/state.json`
+/// (or `~/.local/share/gb/state.json`) and keyed by repository path.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct State {
+    #[serde(default)]
+    repos: BTreeMap<String, RepoState>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RepoState {
+    /// The branch that was checked out before gb's last checkout
+    pub previous_branch: Option<String>,
+    /// Branches pinned to the top of the list with `*`
+    pub favorites: BTreeSet<String>,
+    /// Where the list was left, for `start_selection = "last"`
+    pub last_view: Option<ListView>,
+    /// When gb checked out each branch, as Unix timestamps, the latest
+    /// [`CHECKOUTS_KEPT`] of them, for sorting by frecency
+    pub checkouts: BTreeMap<String, Vec<i64>>,
+    /// The open pull requests by the branch they're from, as the forge last
+    /// listed them, so the list doesn't wait on the network for them
+    pub pull_requests: BTreeMap<String, PullRequest>,
+}
+
+/// How many checkouts of each branch are remembered
+pub const CHECKOUTS_KEPT: usize = 10;
+
+impl RepoState {
+    /// Remembers that gb just checked out `branch`, coming from `previous`
+    pub fn record_checkout(&mut self, previous: Option<&str>, branch: &str, now: i64) {
+        if let Some(previous) = previous.filter(|&previous| previous != branch) {
+            self.previous_branch = Some(previous.to_string());
+        }
+        let times = self.checkouts.entry(branch.to_string()).or_default();
+        times.push(now);
+        let excess = times.len().saturating_sub(CHECKOUTS_KEPT);
+        times.drain(..excess);
+    }
+
+    /// Carries what's remembered about `old` over to its new name
+    pub fn rename_branch(&mut self, old: &str, new: &str) {
+        if self.previous_branch.as_deref() == Some(old) {
+            self.previous_branch = Some(new.to_string());
+        }
+        if self.favorites.remove(old) {
+            self.favorites.insert(new.to_string());
+        }
+        if let Some(view) = self.last_view.as_mut().filter(|view| view.selected == old) {
+            view.selected = new.to_string();
+        }
+        if let Some(times) = self.checkouts.remove(old) {
+            self.checkouts.insert(new.to_string(), times);
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ListView {
+    /// The selected branch
+    pub selected: String,
+    /// How far the list was scrolled, in rows
+    pub offset: usize,
+}
+
+impl State {
+    pub fn load() -> Result<State> {
+        let Some(path) = state_path() else {
+            return Ok(State::default());
+        };
+
+        match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .with_context(|| format!("Invalid state file {}", path.display())),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(State::default()),
+            Err(err) => {
+                Err(err).with_context(|| format!("Failed to read state file {}", path.display()))
+            }
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let Some(path) = state_path() else {
+            return Ok(());
+        };
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+
+        // Write to a temporary file first so a crash can't leave a half-written state
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write state file {}", tmp_path.display()))?;
+        fs::rename(&tmp_path, &path)
+            .with_context(|| format!("Failed to write state file {}", path.display()))?;
+        Ok(())
+    }
+
+    pub fn repo(&self, repo: &Repository) -> RepoState {
+        self.repos.get(&repo_key(repo)).cloned().unwrap_or_default()
+    }
+
+    pub fn repo_mut(&mut self, repo: &Repository) -> &mut RepoState {
+        self.repos.entry(repo_key(repo)).or_default()
+    }
+}
+
+fn repo_key(repo: &Repository) -> String {
+    let path = repo.workdir().unwrap_or_else(|| repo.path());
+    fs::canonicalize(path)
+        .unwrap_or_else(|_| path.to_path_buf())
+        .display()
+        .to_string()
+}
+
+fn state_path() -> Option<PathBuf> {
+    let data_dir = env::var_os("XDG_DATA_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("share"))
+        })?;
+
+    Some(data_dir.join("gb").join("state.json"))
+}
@@ -0,0 +1,47 @@
+//! Scratch directories for the tests that need a real git checkout on disk.
+//! Both the library and the binary build this module under `cfg(test)`.
+
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// An empty directory under the system temp dir, removed again when dropped,
+/// so a failing assert doesn't leave it behind for the next run
+pub struct TempRepo {
+    path: PathBuf,
+}
+
+impl TempRepo {
+    /// Creates the directory; `name` says which test it belongs to, and the
+    /// process id and a counter keep it apart from every other one
+    pub fn new(name: &str) -> TempRepo {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let count = COUNT.fetch_add(1, Ordering::Relaxed);
+        let path =
+            std::env::temp_dir().join(format!("gb-{}-{}-{}", name, std::process::id(), count));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TempRepo { path }
+    }
+}
+
+impl Deref for TempRepo {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl AsRef<Path> for TempRepo {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempRepo {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
@@ -0,0 +1,225 @@
+use std::collections::BTreeSet;
+
+/// Branches sharing a slash-delimited prefix, like `feature/`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Group {
+    /// The whole prefix, trailing slash included, like `user/ann/`
+    pub prefix: String,
+    /// How many groups it's in
+    pub depth: usize,
+    /// How many branches are in it, counting its subgroups'
+    pub count: usize,
+}
+
+impl Group {
+    /// The last part of the prefix, like `ann/`, as it's shown under the
+    /// group it's in
+    pub fn label(&self) -> &str {
+        let inner = &self.prefix[..self.prefix.len() - 1];
+        match inner.rfind('/') {
+            Some(slash) => &self.prefix[slash + 1..],
+            None => &self.prefix,
+        }
+    }
+}
+
+/// A row of the tree view
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TreeRow {
+    /// Which of the names it shows, or for a collapsed group, the first one
+    /// in it
+    pub index: usize,
+    /// How many groups it's in
+    pub depth: usize,
+    /// The groups that start at this row, outermost first, which get a
+    /// header above it
+    pub headers: Vec<Group>,
+    /// Where the name starts once the prefix of the group it's in is left
+    /// off, in bytes
+    pub label_start: usize,
+    /// The group this row stands for, when it's collapsed into one row
+    pub collapsed: Option<Group>,
+}
+
+enum Entry {
+    Name(usize),
+    Group {
+        prefix: String,
+        entries: Vec<Entry>,
+        count: usize,
+    },
+}
+
+/// Lays `names` out as a tree of their slash-delimited prefixes. Groups go
+/// where their first name was, and names keep their order within them, so
+/// the most recent group comes first when the names are sorted by recency.
+/// Only the groups in `expanded` are open; the rest take one row each. A
+/// prefix only one name has makes no group, that name stays in the group
+/// above.
+pub fn tree_rows(names: &[&str], expanded: &BTreeSet<String>) -> Vec<TreeRow> {
+    let mut entries = Vec::new();
+    for (index, name) in names.iter().enumerate() {
+        insert(&mut entries, name, index, 0);
+    }
+
+    let mut rows = Vec::new();
+    flatten(&entries, 0, 0, expanded, &mut Vec::new(), &mut rows);
+    rows
+}
+
+/// The prefixes of `name` that could be groups, outermost first: `a/` and
+/// `a/b/` for `a/b/c`
+pub fn prefixes(name: &str) -> impl Iterator<Item = &str> {
+    name.match_indices('/').map(|(slash, _)| &name[..=slash])
+}
+
+fn insert(entries: &mut Vec<Entry>, name: &str, index: usize, start: usize) {
+    let Some(slash) = name[start..].find('/') else {
+        entries.push(Entry::Name(index));
+        return;
+    };
+    let prefix = &name[..start + slash + 1];
+    let position = entries
+        .iter()
+        .position(|entry| matches!(entry, Entry::Group { prefix: other, .. } if other == prefix));
+    let position = position.unwrap_or_else(|| {
+        entries.push(Entry::Group {
+            prefix: prefix.to_string(),
+            entries: Vec::new(),
+            count: 0,
+        });
+        entries.len() - 1
+    });
+    if let Entry::Group { entries, count, .. } = &mut entries[position] {
+        *count += 1;
+        insert(entries, name, index, prefix.len());
+    }
+}
+
+fn flatten(
+    entries: &[Entry],
+    depth: usize,
+    label_start: usize,
+    expanded: &BTreeSet<String>,
+    headers: &mut Vec<Group>,
+    rows: &mut Vec<TreeRow>,
+) {
+    for entry in entries {
+        match entry {
+            &Entry::Name(index) => rows.push(TreeRow {
+                index,
+                depth,
+                headers: std::mem::take(headers),
+                label_start,
+                collapsed: None,
+            }),
+            Entry::Group { entries, count, .. } if *count == 1 => {
+                flatten(entries, depth, label_start, expanded, headers, rows)
+            }
+            Entry::Group {
+                prefix,
+                entries,
+                count,
+            } => {
+                let group = Group {
+                    prefix: prefix.clone(),
+                    depth,
+                    count: *count,
+                };
+                if expanded.contains(prefix) {
+                    headers.push(group);
+                    flatten(entries, depth + 1, prefix.len(), expanded, headers, rows);
+                } else {
+                    rows.push(TreeRow {
+                        index: first_name(entries),
+                        depth,
+                        headers: std::mem::take(headers),
+                        label_start,
+                        collapsed: Some(group),
+                    });
+                }
+            }
+        }
+    }
+}
+
+fn first_name(entries: &[Entry]) -> usize {
+    match &entries[0] {
+        &Entry::Name(index) => index,
+        Entry::Group { entries, .. } => first_name(entries),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Each row as its indented label, `+` marking collapsed groups and `-`
+    /// the headers of open ones
+    fn outline(names: &[&str], expanded: &[&str]) -> Vec<String> {
+        let expanded = expanded.iter().map(|prefix| prefix.to_string()).collect();
+        let mut lines = Vec::new();
+        for row in tree_rows(names, &expanded) {
+            for group in &row.headers {
+                lines.push(format!(
+                    "{}- {} {}",
+                    "  ".repeat(group.depth),
+                    group.label(),
+                    group.count
+                ));
+            }
+            let indent = "  ".repeat(row.depth);
+            lines.push(match &row.collapsed {
+                Some(group) => format!("{}+ {} {}", indent, group.label(), group.count),
+                None => format!("{}{}", indent, &names[row.index][row.label_start..]),
+            });
+        }
+        lines
+    }
+
+    #[test]
+    fn groups_collapse_by_prefix_where_their_first_branch_was() {
+        let names = [
+            "feature/login",
+            "main",
+            "user/ann/wip",
+            "feature/search",
+            "fix/typo",
+            "user/ann/spike",
+            "user/bob/try",
+        ];
+        assert_eq!(
+            outline(&names, &[]),
+            ["+ feature/ 2", "main", "+ user/ 3", "fix/typo"]
+        );
+        assert_eq!(
+            outline(&names, &["feature/", "user/", "user/ann/"]),
+            [
+                "- feature/ 2",
+                "  login",
+                "  search",
+                "main",
+                "- user/ 3",
+                "  - ann/ 2",
+                "    wip",
+                "    spike",
+                "  bob/try",
+                "fix/typo",
+            ]
+        );
+    }
+
+    #[test]
+    fn collapsed_groups_stand_for_their_first_branch() {
+        let names = ["a/x", "b", "a/y/1", "a/y/2"];
+        let rows = tree_rows(&names, &["a/".to_string()].into());
+        let collapsed: Vec<_> = rows
+            .iter()
+            .map(|row| (row.index, row.collapsed.as_ref().map(|group| group.label())))
+            .collect();
+        assert_eq!(collapsed, [(0, None), (2, Some("y/")), (1, None)]);
+
+        assert_eq!(prefixes("a/y/1").collect::<Vec<_>>(), ["a/", "a/y/"]);
+        assert_eq!(prefixes("main").count(), 0);
+    }
+}